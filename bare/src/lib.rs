@@ -0,0 +1,510 @@
+//! The termios core of `raw_tty`, on nothing but `core` and `libc`, without allocating.
+//!
+//! Some code can't rely on std: a panic handler with a broken allocator, a signal handler,
+//! tools linked into an initramfs. The functions here wrap the syscalls directly, report
+//! failures as a plain [`Errno`] and keep the saved mode on the stack. `raw_tty` builds
+//! its std API on top of them and re-exports this crate as `raw_tty::bare`.
+//!
+//! The crate is `no_std`. The `std` feature only adds finding `errno` through std on
+//! platforms whose libc has no known location for it; without it those platforms don't
+//! build.
+//!
+//! ```no_run
+//! use raw_tty_bare::Guard;
+//!
+//! fn main() -> Result<(), raw_tty_bare::Errno> {
+//!     let mut guard = Guard::new(0)?;
+//!     guard.set_raw()?;
+//!     // Restored when dropped.
+//!     Ok(())
+//! }
+//! ```
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+// Nothing from std beyond what core offers.
+#![deny(clippy::std_instead_of_core, clippy::std_instead_of_alloc)]
+
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use libc::c_int;
+
+/// The mode of a terminal, as `tcgetattr` returns it.
+///
+/// This wraps the `termios` of the platform, whose fields are available through `Deref`.
+/// Convert with [`Termios::from_libc`] and [`Termios::into_libc`] where a `libc::termios`
+/// is needed.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct Termios(libc::termios);
+
+impl Termios {
+    /// Wraps a `libc::termios`.
+    pub fn from_libc(termios: libc::termios) -> Termios {
+        Termios(termios)
+    }
+
+    /// The `libc::termios`, e.g. for passing it to libc directly.
+    pub fn into_libc(self) -> libc::termios {
+        self.0
+    }
+
+    /// The `libc::termios`, borrowed.
+    pub fn as_libc(&self) -> &libc::termios {
+        &self.0
+    }
+
+    /// The `libc::termios`, borrowed mutably.
+    pub fn as_libc_mut(&mut self) -> &mut libc::termios {
+        &mut self.0
+    }
+}
+
+/// Every flag cleared and every control character 0.
+impl Default for Termios {
+    fn default() -> Termios {
+        // All fields are integers, for which zero is valid.
+        Termios(unsafe { core::mem::zeroed() })
+    }
+}
+
+impl Deref for Termios {
+    type Target = libc::termios;
+
+    fn deref(&self) -> &libc::termios {
+        &self.0
+    }
+}
+
+impl DerefMut for Termios {
+    fn deref_mut(&mut self) -> &mut libc::termios {
+        &mut self.0
+    }
+}
+
+impl fmt::Debug for Termios {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Termios")
+            .field("c_iflag", &self.c_iflag)
+            .field("c_oflag", &self.c_oflag)
+            .field("c_cflag", &self.c_cflag)
+            .field("c_lflag", &self.c_lflag)
+            .field("c_cc", &self.c_cc)
+            .finish()
+    }
+}
+
+/// The size of a terminal window, in characters and pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WindowSize {
+    pub rows: u16,
+    pub cols: u16,
+    pub x_pixels: u16,
+    pub y_pixels: u16,
+}
+
+/// An `errno` value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Errno(pub c_int);
+
+impl Errno {
+    /// The `errno` of the last failed call on this thread.
+    pub fn last() -> Errno {
+        Errno(errno())
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "errno {}", self.0)
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "emscripten",
+    target_os = "fuchsia",
+    target_os = "hurd",
+    target_os = "l4re",
+    target_os = "redox"
+))]
+fn errno() -> c_int {
+    unsafe { *libc::__errno_location() }
+}
+
+#[cfg(any(target_vendor = "apple", target_os = "freebsd"))]
+fn errno() -> c_int {
+    unsafe { *libc::__error() }
+}
+
+#[cfg(any(
+    target_os = "android",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "cygwin"
+))]
+fn errno() -> c_int {
+    unsafe { *libc::__errno() }
+}
+
+#[cfg(any(target_os = "solaris", target_os = "illumos"))]
+fn errno() -> c_int {
+    unsafe { *libc::___errno() }
+}
+
+#[cfg(target_os = "haiku")]
+fn errno() -> c_int {
+    unsafe { *libc::_errnop() }
+}
+
+#[cfg(target_os = "aix")]
+fn errno() -> c_int {
+    unsafe { *libc::_Errno() }
+}
+
+#[cfg(all(
+    feature = "std",
+    not(any(
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "emscripten",
+        target_os = "fuchsia",
+        target_os = "hurd",
+        target_os = "l4re",
+        target_os = "redox",
+        target_vendor = "apple",
+        target_os = "freebsd",
+        target_os = "android",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "cygwin",
+        target_os = "solaris",
+        target_os = "illumos",
+        target_os = "haiku",
+        target_os = "aix"
+    ))
+))]
+#[allow(clippy::std_instead_of_core)]
+fn errno() -> c_int {
+    std::io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
+
+fn check(result: c_int) -> Result<c_int, Errno> {
+    match result {
+        -1 => Err(Errno::last()),
+        result => Ok(result),
+    }
+}
+
+/// `tcgetattr(fd)`, with the rates of a custom speed read through `TCGETS2`, see
+/// [`termios2`].
+pub fn get_attr(fd: c_int) -> Result<Termios, Errno> {
+    extern "C" {
+        pub fn tcgetattr(fd: c_int, termptr: *mut Termios) -> c_int;
+    }
+    let mut termios = MaybeUninit::uninit();
+    check(unsafe { tcgetattr(fd, termios.as_mut_ptr()) })?;
+    let mut termios = unsafe { termios.assume_init() };
+    if termios2::custom_speeds(&termios).is_some() {
+        termios2::get_speeds(fd, &mut termios)?;
+    }
+    Ok(termios)
+}
+
+/// `tcsetattr(fd, TCSANOW, termios)`, or `TCSETS2` for a custom speed, see [`termios2`].
+pub fn set_attr(fd: c_int, termios: &Termios) -> Result<(), Errno> {
+    extern "C" {
+        pub fn tcsetattr(fd: c_int, opt: c_int, termptr: *const Termios) -> c_int;
+    }
+    if termios2::custom_speeds(termios).is_some() {
+        return termios2::set_attr(fd, termios);
+    }
+    check(unsafe { tcsetattr(fd, 0, termios) }).map(|_| ())
+}
+
+/// Speeds without a `B*` constant, on Linux.
+///
+/// The `termios` of libc only has room for the constants, so Linux marks any other rate
+/// with `BOTHER` in `c_cflag` and keeps the rate in `c_ispeed` and `c_ospeed`. These are
+/// only read and written by the `TCGETS2` and `TCSETS2` ioctls, which [`get_attr`] and
+/// [`set_attr`] switch to whenever the mode has `BOTHER`. Every other mode keeps going
+/// through `tcgetattr` and `tcsetattr`.
+#[cfg(all(
+    target_os = "linux",
+    any(target_env = "gnu", target_env = "musl"),
+    not(any(
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "sparc",
+        target_arch = "sparc64",
+        target_arch = "mips",
+        target_arch = "mips32r6",
+        target_arch = "mips64",
+        target_arch = "mips64r6"
+    ))
+))]
+pub mod termios2 {
+    use super::{check, Errno, Termios};
+
+    use core::mem::MaybeUninit;
+    use libc::{c_int, speed_t, tcflag_t};
+
+    /// Where the output speed is in `c_cflag`, and the input speed after `IBSHIFT`.
+    const BAUD: tcflag_t = libc::CBAUD | (libc::CBAUD << libc::IBSHIFT);
+
+    #[cfg(target_env = "gnu")]
+    fn speeds(termios: &mut Termios) -> (&mut speed_t, &mut speed_t) {
+        let termios = termios.as_libc_mut();
+        (&mut termios.c_ispeed, &mut termios.c_ospeed)
+    }
+
+    #[cfg(target_env = "musl")]
+    fn speeds(termios: &mut Termios) -> (&mut speed_t, &mut speed_t) {
+        let termios = termios.as_libc_mut();
+        (&mut termios.__c_ispeed, &mut termios.__c_ospeed)
+    }
+
+    /// The input and output rates, if either is custom.
+    pub fn custom_speeds(termios: &Termios) -> Option<(u32, u32)> {
+        let output = termios.c_cflag & libc::CBAUD;
+        let input = (termios.c_cflag >> libc::IBSHIFT) & libc::CBAUD;
+        if output != libc::BOTHER && input != libc::BOTHER {
+            return None;
+        }
+        let mut termios = *termios;
+        let (input, output) = speeds(&mut termios);
+        Some((*input, *output))
+    }
+
+    /// Clears the input speed bits `cfsetispeed` doesn't know about, so that the input
+    /// speed follows the output speed again.
+    pub fn clear_input_speed(termios: &mut Termios) {
+        termios.c_cflag &= !(libc::CBAUD << libc::IBSHIFT);
+    }
+
+    /// Sets both rates to `baud`, which doesn't need to have a constant.
+    pub fn set_custom_speed(termios: &mut Termios, baud: u32) -> Result<(), Errno> {
+        termios.c_cflag &= !BAUD;
+        termios.c_cflag |= libc::BOTHER | (libc::BOTHER << libc::IBSHIFT);
+        let (input, output) = speeds(termios);
+        *input = baud;
+        *output = baud;
+        Ok(())
+    }
+
+    fn get(fd: c_int) -> Result<libc::termios2, Errno> {
+        let mut termios = MaybeUninit::uninit();
+        check(unsafe { libc::ioctl(fd, libc::TCGETS2, termios.as_mut_ptr()) })?;
+        Ok(unsafe { termios.assume_init() })
+    }
+
+    /// Fills in the rates of `termios`, read with `TCGETS2`.
+    pub fn get_speeds(fd: c_int, termios: &mut Termios) -> Result<(), Errno> {
+        let termios2 = get(fd)?;
+        let (input, output) = speeds(termios);
+        *input = termios2.c_ispeed;
+        *output = termios2.c_ospeed;
+        Ok(())
+    }
+
+    /// Applies `termios` with `TCSETS2`, rates included.
+    pub fn set_attr(fd: c_int, termios: &Termios) -> Result<(), Errno> {
+        let mut termios2 = get(fd)?;
+        termios2.c_iflag = termios.c_iflag;
+        termios2.c_oflag = termios.c_oflag;
+        termios2.c_cflag = termios.c_cflag;
+        termios2.c_lflag = termios.c_lflag;
+        termios2.c_line = termios.c_line;
+        let len = termios2.c_cc.len().min(termios.c_cc.len());
+        termios2.c_cc[..len].copy_from_slice(&termios.c_cc[..len]);
+        let mut termios = *termios;
+        let (input, output) = speeds(&mut termios);
+        termios2.c_ispeed = *input;
+        termios2.c_ospeed = *output;
+        check(unsafe { libc::ioctl(fd, libc::TCSETS2, &termios2) }).map(|_| ())
+    }
+}
+
+/// Without `termios2` every speed has a constant.
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_env = "gnu", target_env = "musl"),
+    not(any(
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "sparc",
+        target_arch = "sparc64",
+        target_arch = "mips",
+        target_arch = "mips32r6",
+        target_arch = "mips64",
+        target_arch = "mips64r6"
+    ))
+)))]
+pub mod termios2 {
+    use super::{Errno, Termios};
+
+    use libc::c_int;
+
+    pub fn custom_speeds(_termios: &Termios) -> Option<(u32, u32)> {
+        None
+    }
+
+    pub fn clear_input_speed(_termios: &mut Termios) {}
+
+    /// Fails with `EINVAL`.
+    pub fn set_custom_speed(_termios: &mut Termios, _baud: u32) -> Result<(), Errno> {
+        Err(Errno(libc::EINVAL))
+    }
+
+    pub fn get_speeds(_fd: c_int, _termios: &mut Termios) -> Result<(), Errno> {
+        Ok(())
+    }
+
+    pub fn set_attr(_fd: c_int, _termios: &Termios) -> Result<(), Errno> {
+        Err(Errno(libc::EINVAL))
+    }
+}
+
+pub fn get_window_size(fd: c_int) -> Result<WindowSize, Errno> {
+    let mut size: libc::winsize = unsafe { core::mem::zeroed() };
+    check(unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) })?;
+    Ok(WindowSize {
+        rows: size.ws_row,
+        cols: size.ws_col,
+        x_pixels: size.ws_xpixel,
+        y_pixels: size.ws_ypixel,
+    })
+}
+
+pub fn set_window_size(fd: c_int, size: &WindowSize) -> Result<(), Errno> {
+    let size = libc::winsize {
+        ws_row: size.rows,
+        ws_col: size.cols,
+        ws_xpixel: size.x_pixels,
+        ws_ypixel: size.y_pixels,
+    };
+    check(unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) }).map(|_| ())
+}
+
+/// Turns `termios` into raw mode, like `cfmakeraw`.
+pub fn make_raw(termios: &mut Termios) {
+    extern "C" {
+        pub fn cfmakeraw(termptr: *mut Termios);
+    }
+    unsafe { cfmakeraw(termios) }
+}
+
+/// Turns `termios` into raw mode by clearing only what line editing, echo, signals, flow
+/// control and the newline translations need, leaving e.g. `IXANY`, parity and the speed
+/// as they are.
+pub fn make_raw_merged(termios: &mut Termios) {
+    termios.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INLCR | libc::IGNCR | libc::IXON);
+    termios.c_oflag &= !libc::OPOST;
+    termios.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ECHONL | libc::ISIG | libc::IEXTEN);
+    termios.c_cc[libc::VMIN] = 1;
+    termios.c_cc[libc::VTIME] = 0;
+}
+
+/// Turns `termios` into raw mode without any input or character size processing left,
+/// so that every byte arrives unchanged.
+pub fn make_8bit_clean(termios: &mut Termios) {
+    make_raw(termios);
+    termios.c_iflag &= !(libc::IGNBRK
+        | libc::BRKINT
+        | libc::IGNPAR
+        | libc::PARMRK
+        | libc::INPCK
+        | libc::ISTRIP
+        | libc::INLCR
+        | libc::IGNCR
+        | libc::ICRNL
+        | libc::IXON
+        | libc::IXOFF
+        | libc::IXANY);
+    termios.c_cflag &= !(libc::CSIZE | libc::PARENB);
+    termios.c_cflag |= libc::CS8;
+}
+
+/// Saves the mode of a tty and restores it when dropped, failures are ignored then.
+///
+/// Unlike the `TtyModeGuard` of `raw_tty` this has no hooks, statistics or registry, and
+/// the fd isn't owned.
+#[derive(Debug)]
+pub struct Guard {
+    fd: c_int,
+    saved: Termios,
+}
+
+impl Guard {
+    pub fn new(fd: c_int) -> Result<Guard, Errno> {
+        Ok(Guard {
+            fd,
+            saved: get_attr(fd)?,
+        })
+    }
+
+    /// The mode which is restored.
+    pub fn saved(&self) -> &Termios {
+        &self.saved
+    }
+
+    /// Switches to raw mode.
+    pub fn set_raw(&mut self) -> Result<(), Errno> {
+        let mut termios = self.saved;
+        make_raw(&mut termios);
+        set_attr(self.fd, &termios)
+    }
+
+    /// Applies the saved mode right away.
+    pub fn restore(&self) -> Result<(), Errno> {
+        set_attr(self.fd, &self.saved)
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let _ = self.restore();
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::std_instead_of_core, clippy::std_instead_of_alloc)]
+mod test {
+    use super::*;
+    use std::fs::File;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::ptr;
+
+    fn openpty() -> std::io::Result<(File, File)> {
+        let (mut master, mut slave) = (-1, -1);
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                ptr::null_mut(),
+                ptr::null(),
+                ptr::null(),
+            )
+        };
+        if result == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        unsafe { Ok((File::from_raw_fd(master), File::from_raw_fd(slave))) }
+    }
+
+    #[test]
+    fn test_guard() -> std::io::Result<()> {
+        let (_master, slave) = openpty()?;
+        let fd = slave.as_raw_fd();
+        let mut guard = Guard::new(fd).unwrap();
+        guard.set_raw().unwrap();
+        assert_eq!(get_attr(fd).unwrap().c_lflag & libc::ICANON, 0);
+        drop(guard);
+        assert_ne!(get_attr(fd).unwrap().c_lflag & libc::ICANON, 0);
+
+        let null = std::fs::File::open("/dev/null")?;
+        assert_eq!(get_attr(null.as_raw_fd()).unwrap_err(), Errno(libc::ENOTTY));
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+//! The per-frame cost of polling for input in game mode.
+//!
+//! Run with `cargo bench --bench poll_key`. Prints the average time of a `poll_key` call
+//! which finds nothing, the common case in a frame loop, and of one which decodes a key.
+
+use raw_tty::prompt::KeyPoller;
+use raw_tty::{pty, TtyWithGuard};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 100_000;
+
+fn report(name: &str, elapsed: Duration, iterations: u32) {
+    println!(
+        "{:<24} {:>8.0} ns/poll",
+        name,
+        elapsed.as_nanos() as f64 / f64::from(iterations)
+    );
+}
+
+fn main() -> io::Result<()> {
+    let (mut master, slave) = pty::open()?;
+    let mut tty = TtyWithGuard::builder(slave).game_mode().build()?;
+    let mut keys = KeyPoller::new();
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        assert!(keys.poll_key(&mut *tty)?.is_none());
+    }
+    report("no input", start.elapsed(), ITERATIONS);
+
+    // Small enough batches to stay within the pty buffer.
+    let batch = 1000;
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..ITERATIONS / batch {
+        master.write_all(&[b'x'; 1000])?;
+        master.flush()?;
+        std::thread::sleep(Duration::from_millis(1));
+        let start = Instant::now();
+        for _ in 0..batch {
+            assert!(keys.poll_key(&mut *tty)?.is_some());
+        }
+        elapsed += start.elapsed();
+    }
+    report("one key pending", elapsed, ITERATIONS);
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        assert!(keys.poll_key(&mut *tty)?.is_none());
+    }
+    report("no input, after keys", start.elapsed(), ITERATIONS);
+    Ok(())
+}
@@ -0,0 +1,42 @@
+//! The cost of many small writes to a tty, with and without a `RawWriter`.
+//!
+//! Run with `cargo bench --bench raw_writer --features pty`. Prints the average time per
+//! 64 byte write, like a cursor move followed by a short string.
+
+use raw_tty::{pty, RawWriter};
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::Instant;
+
+const WRITES: u32 = 100_000;
+const PIECE: &[u8] = b"\x1b[12;40Hsome text of a frame, followed by more text to make 64 bytes";
+
+fn measure(name: &str, out: &mut impl Write) -> io::Result<()> {
+    let start = Instant::now();
+    for _ in 0..WRITES {
+        out.write_all(&PIECE[..64])?;
+    }
+    out.flush()?;
+    println!(
+        "{:<12} {:>8.0} ns/write",
+        name,
+        start.elapsed().as_nanos() as f64 / f64::from(WRITES)
+    );
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let (mut master, mut slave) = pty::open()?;
+    // Keep the pty buffer from filling up.
+    thread::spawn(move || {
+        let mut buf = [0; 64 * 1024];
+        while let Ok(n) = master.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
+    });
+    measure("unbuffered", &mut slave)?;
+    measure("RawWriter", &mut RawWriter::new(&mut slave))?;
+    Ok(())
+}
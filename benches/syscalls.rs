@@ -0,0 +1,81 @@
+//! The costs this crate's caching, batching and buffering try to cut: creating guards,
+//! switching modes, reading from a tty and decoding keys.
+//!
+//! Run with `cargo bench --bench syscalls --features decoder,pty`, and compare against a
+//! baseline with criterion's `--save-baseline` and `--baseline` before and after a change.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use raw_tty::input::Decoder;
+use raw_tty::{pty, IntoRawMode, TtyModeGuard};
+use std::hint::black_box;
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::thread;
+
+fn guards(c: &mut Criterion) {
+    let (_master, slave) = pty::open().unwrap();
+    let fd = slave.as_raw_fd();
+    c.bench_function("guard create and drop", |b| {
+        b.iter(|| drop(black_box(TtyModeGuard::new(fd).unwrap())))
+    });
+
+    let mut guard = TtyModeGuard::new(fd).unwrap();
+    c.bench_function("set_raw_mode and restore", |b| {
+        b.iter(|| {
+            guard.set_raw_mode().unwrap();
+            guard.restore().unwrap();
+        })
+    });
+}
+
+fn reader(c: &mut Criterion) {
+    const CHUNK: usize = 16 * 1024;
+    let (mut master, slave) = pty::open().unwrap();
+    let mut tty = slave.into_raw_mode().unwrap();
+    // Keep the master writing, so that reads never wait for long.
+    thread::spawn(move || {
+        let data = [b'x'; CHUNK];
+        while master.write_all(&data).is_ok() {}
+    });
+
+    let mut group = c.benchmark_group("reader");
+    group.throughput(Throughput::Bytes(CHUNK as u64));
+    let mut buf = vec![0; CHUNK];
+    group.bench_function("read 16K from a pty", |b| {
+        b.iter(|| {
+            let mut read = 0;
+            while read < CHUNK {
+                read += tty.read(&mut buf[read..]).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+fn decoder(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decoder");
+    for (name, input) in [
+        ("plain key", &b"x"[..]),
+        ("arrow key", b"\x1b[A"),
+        ("kitty key", b"\x1b[97;5u"),
+        ("paste of 1K", &[b'x'; 1024][..]),
+    ] {
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter_batched_ref(
+                Decoder::new,
+                |decoder| {
+                    decoder.feed(black_box(input));
+                    while let Some(event) = decoder.next_event() {
+                        black_box(event);
+                    }
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, guards, reader, decoder);
+criterion_main!(benches);
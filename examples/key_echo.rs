@@ -0,0 +1,34 @@
+//! Prints each key pressed, until `q`.
+//!
+//! Run with `cargo run --example key_echo --features decoder` from a terminal. Arrow keys,
+//! function keys and modifiers show up decoded, e.g. `Up` or `Char('c') CTRL`, which makes
+//! this a quick way to see what a terminal sends.
+
+use raw_tty::input::{Key, Modifiers};
+use raw_tty::prompt::read_key_blocking;
+use raw_tty::IntoRawMode;
+use std::fs::OpenOptions;
+use std::io::{self, stdout, Write};
+
+fn main() -> io::Result<()> {
+    // Not stdin: `Stdin` buffers, which would hide the rest of an escape sequence from the
+    // Escape key timeout.
+    let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    let mut tty = tty.into_raw_mode()?;
+    let mut out = stdout();
+    // Raw mode doesn't turn `\n` into `\r\n`.
+    write!(out, "Press keys, q quits.\r\n")?;
+    out.flush()?;
+    loop {
+        let key = read_key_blocking(&mut tty)?;
+        if key.modifiers == Modifiers::NONE {
+            write!(out, "{:?}\r\n", key.key)?;
+        } else {
+            write!(out, "{:?} {:?}\r\n", key.key, key.modifiers)?;
+        }
+        out.flush()?;
+        if key.key == Key::Char('q') && key.modifiers == Modifiers::NONE {
+            return Ok(());
+        }
+    }
+}
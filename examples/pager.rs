@@ -0,0 +1,56 @@
+//! A minimal `more`, showing a file a screen at a time.
+//!
+//! Run with `cargo run --example pager --features decoder -- FILE` from a terminal. Space
+//! or Page Down shows the next screen, Enter or Down the next line and `q` quits. The
+//! screen is as high as the window at the time it is shown.
+
+use raw_tty::input::Key;
+use raw_tty::prompt::read_key_blocking;
+use raw_tty::{IntoRawMode, RawReader, RawWriter};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, stdout, Write};
+
+const STATUS: &str = "--More--";
+
+fn main() -> io::Result<()> {
+    let path = env::args_os()
+        .nth(1)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "usage: pager FILE"))?;
+    let text = fs::read_to_string(path)?;
+    let mut lines = text.lines().peekable();
+
+    // Unbuffered unlike `Stdin`, for the Escape key timeout.
+    let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    let mut tty = tty.into_raw_mode()?;
+    let mut out = RawWriter::new(stdout());
+    let mut wanted = screen_rows(&tty);
+    loop {
+        for line in lines.by_ref().take(wanted) {
+            write!(out, "{}\r\n", line)?;
+        }
+        if lines.peek().is_none() {
+            return out.flush();
+        }
+        write!(out, "{}", STATUS)?;
+        out.flush()?;
+        let key = read_key_blocking(&mut tty)?;
+        // Erases the status line again.
+        write!(out, "\r\x1b[K")?;
+        wanted = match key.key {
+            Key::Char(' ') | Key::PageDown => screen_rows(&tty),
+            Key::Enter | Key::Down => 1,
+            Key::Char('q') => return out.flush(),
+            _ => 0,
+        };
+    }
+}
+
+/// The lines of text which fit above the status line.
+fn screen_rows(tty: &RawReader<File>) -> usize {
+    match tty.window_size() {
+        Ok(size) if size.rows > 1 => usize::from(size.rows) - 1,
+        // A pty nobody sized yet.
+        _ => 23,
+    }
+}
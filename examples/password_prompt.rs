@@ -0,0 +1,35 @@
+//! Asks for a new password twice, without echoing it.
+//!
+//! Run with `cargo run --example password_prompt` from a terminal. The tty stays in
+//! cooked mode, so the line can be edited as usual; only `ECHO` is switched off while a
+//! password is read, and back on afterwards even if reading fails.
+
+use raw_tty::GuardMode;
+use std::io::{self, stdin, stdout, BufRead, Write};
+use std::process;
+
+fn main() -> io::Result<()> {
+    let mut tty = stdin().guard_mode()?;
+    let first = read_password(&mut tty, "Password: ")?;
+    let second = read_password(&mut tty, "Retype password: ")?;
+    drop(tty);
+    if first != second {
+        println!("Passwords don't match.");
+        process::exit(1);
+    }
+    println!("Passwords match, {} characters.", first.chars().count());
+    Ok(())
+}
+
+fn read_password(tty: &mut raw_tty::TtyWithGuard<io::Stdin>, prompt: &str) -> io::Result<String> {
+    let mut password = String::new();
+    // Prompting only once echo is off, or what is typed ahead would show.
+    tty.with_echo(false, |stdin| {
+        print!("{}", prompt);
+        stdout().flush()?;
+        stdin.lock().read_line(&mut password)
+    })?;
+    // The newline typed wasn't echoed either.
+    println!();
+    Ok(password.trim_end_matches('\n').to_string())
+}
@@ -0,0 +1,36 @@
+//! Runs a program on a pty between the terminal and itself, like `script` without the
+//! file.
+//!
+//! Run with `cargo run --example proxy --features pty [-- COMMAND ARGS...]` from a
+//! terminal. Without a command it runs `$SHELL`. When the program exits, it prints how many
+//! bytes went each way.
+
+use raw_tty::events::Events;
+use raw_tty::proxy::Proxy;
+use raw_tty::{guard_first_tty, pty};
+use std::env;
+use std::io;
+use std::process::Command;
+
+fn main() -> io::Result<()> {
+    let mut args = env::args_os().skip(1);
+    let program = args
+        .next()
+        .or_else(|| env::var_os("SHELL"))
+        .unwrap_or_else(|| "sh".into());
+    let (mut master, mut child) = pty::spawn(Command::new(program).args(args))?;
+
+    let mut tty = guard_first_tty()?;
+    tty.set_raw_mode()?;
+    let mut events = Events::new(&*tty)?;
+    let (mut input, mut output) = (0, 0);
+    let reason = Proxy::new()
+        .on_input(|bytes| input += bytes.len())
+        .on_output(|bytes| output += bytes.len())
+        .child(&mut child)
+        .run(&mut master, &mut tty, &mut events)?;
+    drop(events);
+    drop(tty);
+    println!("{:?}, {} bytes in, {} bytes out", reason, input, output);
+    Ok(())
+}
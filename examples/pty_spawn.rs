@@ -0,0 +1,30 @@
+//! Drives an interactive program through a pty, like a test of that program would.
+//!
+//! Run with `cargo run --example pty_spawn --features pty`. Starts `cat` on a pty, types a
+//! line, waits for `cat` to print it back and ends it with Ctrl-D.
+
+use raw_tty::pty;
+use std::io;
+use std::process::Command;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn main() -> io::Result<()> {
+    let (mut master, mut child) = pty::spawn(&mut Command::new("cat"))?;
+    master.send("hello\n")?;
+    // Once as the echo of the pty, once from `cat`. Both with `\n` turned into `\r\n`.
+    master.expect("hello\r\n", TIMEOUT)?;
+    master.expect("hello\r\n", TIMEOUT)?;
+    println!("cat replied");
+
+    master.send("\x04")?;
+    match child.wait_timeout(TIMEOUT)? {
+        Some(status) => println!("cat exited: {}", status),
+        None => {
+            child.kill()?;
+            println!("cat didn't exit, killed it");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,159 @@
+//! Diagnostics to attach when filing bugs against a terminal or OS.
+//!
+//! Run with `cargo run --example raw_tty_doctor --features doctor` from an interactive
+//! terminal. It switches to raw mode and back, checks that the mode and the signal
+//! handlers are restored and prints what it found.
+
+use raw_tty::events::{Event, Events};
+use raw_tty::{GuardMode, Termios};
+use std::fs;
+use std::io::{self, Write};
+use std::{env, mem, ptr};
+
+const LFLAGS: &[(libc::tcflag_t, &str)] = &[
+    (libc::ECHO, "ECHO"),
+    (libc::ECHOE, "ECHOE"),
+    (libc::ECHOK, "ECHOK"),
+    (libc::ECHONL, "ECHONL"),
+    (libc::ICANON, "ICANON"),
+    (libc::IEXTEN, "IEXTEN"),
+    (libc::ISIG, "ISIG"),
+    (libc::NOFLSH, "NOFLSH"),
+    (libc::TOSTOP, "TOSTOP"),
+];
+
+const IFLAGS: &[(libc::tcflag_t, &str)] = &[
+    (libc::BRKINT, "BRKINT"),
+    (libc::ICRNL, "ICRNL"),
+    (libc::IGNBRK, "IGNBRK"),
+    (libc::IGNCR, "IGNCR"),
+    (libc::IGNPAR, "IGNPAR"),
+    (libc::INLCR, "INLCR"),
+    (libc::INPCK, "INPCK"),
+    (libc::ISTRIP, "ISTRIP"),
+    (libc::IXANY, "IXANY"),
+    (libc::IXOFF, "IXOFF"),
+    (libc::IXON, "IXON"),
+    (libc::PARMRK, "PARMRK"),
+];
+
+const OFLAGS: &[(libc::tcflag_t, &str)] = &[(libc::OPOST, "OPOST"), (libc::ONLCR, "ONLCR")];
+
+const CFLAGS: &[(libc::tcflag_t, &str)] = &[
+    (libc::CREAD, "CREAD"),
+    (libc::CLOCAL, "CLOCAL"),
+    (libc::PARENB, "PARENB"),
+    (libc::HUPCL, "HUPCL"),
+];
+
+fn flags(ios: &Termios) -> Vec<String> {
+    let mut set = Vec::new();
+    for &(value, table) in &[
+        (ios.c_iflag, IFLAGS),
+        (ios.c_oflag, OFLAGS),
+        (ios.c_cflag, CFLAGS),
+        (ios.c_lflag, LFLAGS),
+    ] {
+        for &(flag, name) in table {
+            if value & flag != 0 {
+                set.push(name.to_string());
+            }
+        }
+    }
+    set.push(format!("VMIN={}", ios.c_cc[libc::VMIN]));
+    set.push(format!("VTIME={}", ios.c_cc[libc::VTIME]));
+    set
+}
+
+fn same_mode(a: &Termios, b: &Termios) -> bool {
+    (a.c_iflag, a.c_oflag, a.c_cflag, a.c_lflag, a.c_cc)
+        == (b.c_iflag, b.c_oflag, b.c_cflag, b.c_lflag, b.c_cc)
+}
+
+fn check(out: &mut impl Write, name: &str, ok: bool) -> io::Result<bool> {
+    writeln!(out, "[{}] {}", if ok { " ok " } else { "FAIL" }, name)?;
+    Ok(ok)
+}
+
+fn handler(signal: libc::c_int) -> libc::sighandler_t {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        libc::sigaction(signal, ptr::null(), &mut action);
+        action.sa_sigaction
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut out = io::stdout();
+    writeln!(
+        out,
+        "raw_tty {} on {} {}, TERM={}",
+        env!("CARGO_PKG_VERSION"),
+        env::consts::OS,
+        env::consts::ARCH,
+        env::var("TERM").unwrap_or_else(|_| "<unset>".into())
+    )?;
+
+    let tty = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")?;
+    let mut tty = tty.guard_mode()?;
+    let saved = tty.saved_termios();
+    let mut ok = true;
+
+    match tty.window_size() {
+        Ok(size) => writeln!(
+            out,
+            "window size: {}x{} ({}x{} pixels)",
+            size.cols, size.rows, size.x_pixels, size.y_pixels
+        )?,
+        Err(e) => ok &= check(&mut out, &format!("window size: {}", e), false)?,
+    }
+
+    tty.set_raw_mode()?;
+    let raw = tty.current_termios()?;
+    let (before, after) = (flags(&saved), flags(&raw));
+    let removed: Vec<_> = before.iter().filter(|f| !after.contains(f)).collect();
+    let added: Vec<_> = after.iter().filter(|f| !before.contains(f)).collect();
+    tty.restore()?;
+    writeln!(out, "cooked: {}", before.join(" "))?;
+    writeln!(out, "raw mode removes: {:?}", removed)?;
+    writeln!(out, "raw mode adds: {:?}", added)?;
+    ok &= check(&mut out, "raw mode disables ICANON and ECHO", {
+        raw.c_lflag & (libc::ICANON | libc::ECHO) == 0
+    })?;
+    ok &= check(
+        &mut out,
+        "restore brings back the saved mode",
+        same_mode(&tty.current_termios()?, &saved),
+    )?;
+
+    let winch = handler(libc::SIGWINCH);
+    {
+        let mut events = Events::new(&*tty)?;
+        unsafe { libc::raise(libc::SIGWINCH) };
+        ok &= check(
+            &mut out,
+            "SIGWINCH arrives as a resize event",
+            events.next_event()? == Event::Resize,
+        )?;
+    }
+    ok &= check(
+        &mut out,
+        "signal handlers are restored",
+        handler(libc::SIGWINCH) == winch,
+    )?;
+
+    let stats = tty.stats();
+    writeln!(out, "{:?}", stats)?;
+    drop(tty);
+    if !ok {
+        writeln!(
+            out,
+            "some checks failed, please include this output in the report"
+        )?;
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,25 @@
+/* The C ABI of raw_tty, exported by programs built with its `capi` feature. */
+#ifndef RAW_TTY_H
+#define RAW_TTY_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Errors are returned as negated errno values. */
+
+uint32_t raw_tty_capi_version(void);
+/* The number of guards entered through this interface for fd. */
+int raw_tty_enter_raw(int fd);
+/* Restores the mode saved by the newest raw_tty_enter_raw for fd. */
+int raw_tty_restore(int fd);
+/* The number of live guards for fd, from the host or plugins. */
+int raw_tty_query(int fd);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
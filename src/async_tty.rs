@@ -0,0 +1,372 @@
+//! A guarded tty for async code, independent of the runtime.
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::util::poll;
+use crate::TtyWithGuard;
+
+use std::future::{self, Future};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+
+/// A [`TtyWithGuard`] read and written from async code.
+///
+/// The fd is switched to `O_NONBLOCK`, and a thread of its own wakes the tasks waiting for
+/// it, so this works under any runtime. Adapting it to a runtime's traits, e.g. tokio's
+/// `AsyncRead`, is a matter of calling [`poll_read`](AsyncTty::poll_read) and
+/// [`poll_write`](AsyncTty::poll_write).
+///
+/// Restoring stays synchronous: dropping an `AsyncTty` stops its thread and restores the
+/// mode before the drop returns, on the thread that drops it. To make sure that is the
+/// main thread rather than a runtime worker which may never run again after the runtime
+/// shut down, take the tty out with [`into_blocking`](AsyncTty::into_blocking) at the end
+/// of the async part and drop it after the runtime is gone. With a minimal executor
+/// standing in for the runtime:
+///
+/// ```no_run
+/// use raw_tty::{AsyncTty, GuardMode};
+/// use std::future::Future;
+/// use std::io::{self, stdin};
+/// use std::pin::pin;
+/// use std::sync::Arc;
+/// use std::task::{Context, Poll, Wake};
+/// use std::thread::{self, Thread};
+///
+/// struct Unpark(Thread);
+///
+/// impl Wake for Unpark {
+///     fn wake(self: Arc<Self>) {
+///         self.0.unpark();
+///     }
+/// }
+///
+/// fn block_on<F: Future>(future: F) -> F::Output {
+///     let waker = Arc::new(Unpark(thread::current())).into();
+///     let mut cx = Context::from_waker(&waker);
+///     let mut future = pin!(future);
+///     loop {
+///         match future.as_mut().poll(&mut cx) {
+///             Poll::Ready(output) => return output,
+///             Poll::Pending => thread::park(),
+///         }
+///     }
+/// }
+///
+/// fn main() -> io::Result<()> {
+///     let mut tty = stdin().guard_mode()?;
+///     tty.set_raw_mode()?;
+///     let tty = block_on(async move {
+///         let mut tty = AsyncTty::new(tty)?;
+///         let mut key = [0];
+///         tty.read(&mut key).await?;
+///         tty.into_blocking()
+///     })?;
+///     // The mode is restored here, after the async part is over.
+///     drop(tty);
+///     Ok(())
+/// }
+/// ```
+pub struct AsyncTty<T: AsRawFd> {
+    /// Only `None` once taken by `into_blocking`.
+    tty: Option<TtyWithGuard<T>>,
+    shared: Arc<Shared>,
+    /// Written to when a task starts waiting, closed to stop the thread.
+    wake: Option<UnixStream>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// The tasks waiting for the tty.
+#[derive(Debug, Default)]
+struct Shared {
+    wakers: Mutex<Wakers>,
+}
+
+#[derive(Debug, Default)]
+struct Wakers {
+    read: Option<Waker>,
+    write: Option<Waker>,
+}
+
+impl Shared {
+    fn wakers(&self) -> MutexGuard<'_, Wakers> {
+        self.wakers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T: AsRawFd> AsyncTty<T> {
+    /// Takes over `tty`, in whatever mode it is in. `O_NONBLOCK` is set until
+    /// [`into_blocking`](AsyncTty::into_blocking), or until the guard restores the previous
+    /// flags when dropped.
+    pub fn new(mut tty: TtyWithGuard<T>) -> io::Result<AsyncTty<T>> {
+        tty.set_nonblocking(true)?;
+        let fd = tty.as_raw_fd();
+        let shared = Arc::new(Shared::default());
+        let (wake, woken) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("raw_tty waker".into())
+                .spawn(move || watch(fd, &woken, &shared))?
+        };
+        Ok(AsyncTty {
+            tty: Some(tty),
+            shared,
+            wake: Some(wake),
+            thread: Some(thread),
+        })
+    }
+
+    /// Stops the thread, clears `O_NONBLOCK` again and returns the guarded tty, to be
+    /// dropped or used further outside of async code.
+    pub fn into_blocking(mut self) -> io::Result<TtyWithGuard<T>> {
+        self.stop();
+        let mut tty = self.tty.take().expect("taken only here");
+        tty.set_nonblocking(false)?;
+        Ok(tty)
+    }
+
+    pub fn get_ref(&self) -> &TtyWithGuard<T> {
+        self.tty.as_ref().expect("taken only by into_blocking")
+    }
+
+    pub fn get_mut(&mut self) -> &mut TtyWithGuard<T> {
+        self.tty.as_mut().expect("taken only by into_blocking")
+    }
+
+    /// Registers `waker` to be woken once the tty is readable or writable.
+    fn wait(&self, waker: &Waker, write: bool) -> io::Result<()> {
+        let mut wakers = self.shared.wakers();
+        let slot = if write {
+            &mut wakers.write
+        } else {
+            &mut wakers.read
+        };
+        *slot = Some(waker.clone());
+        drop(wakers);
+        match self.wake.as_ref().map(|mut wake| wake.write(&[0])) {
+            // Full means a wakeup is pending already.
+            Some(Err(e)) if e.kind() != io::ErrorKind::WouldBlock => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn stop(&mut self) {
+        drop(self.wake.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<T: Read + AsRawFd> AsyncTty<T> {
+    /// Reads what is available, or arranges for the task to be woken once there is input.
+    pub fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        loop {
+            match (**self.get_mut()).read(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return match self.wait(cx.waker(), false) {
+                        Ok(()) => Poll::Pending,
+                        Err(e) => Poll::Ready(Err(e)),
+                    };
+                }
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    /// Reads into `buf` once there is input, like `Read::read`.
+    pub fn read<'a>(
+        &'a mut self,
+        buf: &'a mut [u8],
+    ) -> impl Future<Output = io::Result<usize>> + 'a {
+        future::poll_fn(move |cx| self.poll_read(cx, buf))
+    }
+}
+
+impl<T: Write + AsRawFd> AsyncTty<T> {
+    /// Writes what the tty takes, or arranges for the task to be woken once it takes more.
+    pub fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        loop {
+            match (**self.get_mut()).write(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return match self.wait(cx.waker(), true) {
+                        Ok(()) => Poll::Pending,
+                        Err(e) => Poll::Ready(Err(e)),
+                    };
+                }
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    /// Flushes the user space buffer of the tty, if it has one.
+    pub fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            match (**self.get_mut()).flush() {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return match self.wait(cx.waker(), true) {
+                        Ok(()) => Poll::Pending,
+                        Err(e) => Poll::Ready(Err(e)),
+                    };
+                }
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    /// Writes all of `buf` and flushes, like `Write::write_all`.
+    pub async fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let n = future::poll_fn(|cx| self.poll_write(cx, buf)).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            buf = &buf[n..];
+        }
+        future::poll_fn(|cx| self.poll_flush(cx)).await
+    }
+}
+
+impl<T: AsRawFd> Drop for AsyncTty<T> {
+    fn drop(&mut self) {
+        // The guard restores after the thread stopped, right here.
+        self.stop();
+    }
+}
+
+/// Waits for the tty on behalf of the registered tasks, until `woken` is closed.
+fn watch(fd: RawFd, woken: &UnixStream, shared: &Shared) {
+    loop {
+        let interest = {
+            let wakers = shared.wakers();
+            let mut events = 0;
+            if wakers.read.is_some() {
+                events |= libc::POLLIN;
+            }
+            if wakers.write.is_some() {
+                events |= libc::POLLOUT;
+            }
+            events
+        };
+        let mut fds = [
+            libc::pollfd {
+                fd: woken.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: if interest == 0 { -1 } else { fd },
+                events: interest,
+                revents: 0,
+            },
+        ];
+        if poll(&mut fds, None).is_err() {
+            return;
+        }
+        if fds[0].revents != 0 {
+            let mut buf = [0; 64];
+            match (&*woken).read(&mut buf) {
+                // The `AsyncTty` is gone.
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+        }
+        let ready = fds[1].revents;
+        if ready != 0 {
+            let failed = ready & (libc::POLLHUP | libc::POLLERR | libc::POLLNVAL) != 0;
+            let mut wakers = shared.wakers();
+            let mut woke = Vec::with_capacity(2);
+            if failed || ready & libc::POLLIN != 0 {
+                woke.extend(wakers.read.take());
+            }
+            if failed || ready & libc::POLLOUT != 0 {
+                woke.extend(wakers.write.take());
+            }
+            drop(wakers);
+            woke.into_iter().for_each(Waker::wake);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::attr::get_terminal_attr;
+    use crate::util::openpty;
+    use crate::GuardMode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::Wake;
+    use std::time::{Duration, Instant};
+
+    /// Counts its wakeups.
+    struct Counter(AtomicUsize);
+
+    impl Wake for Counter {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_async_tty() -> io::Result<()> {
+        let (mut master, slave) = openpty()?;
+        let dup = slave.try_clone()?;
+        let saved = get_terminal_attr(dup.as_raw_fd())?;
+        let mut tty = slave.guard_mode()?;
+        tty.set_raw_mode()?;
+        let mut tty = AsyncTty::new(tty)?;
+
+        let counter = Arc::new(Counter(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut buf = [0; 4];
+        assert!(tty.poll_read(&mut cx, &mut buf).is_pending());
+        master.write_all(b"k")?;
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while counter.0.load(Ordering::SeqCst) == 0 {
+            assert!(Instant::now() < deadline, "not woken");
+            thread::sleep(Duration::from_millis(1));
+        }
+        match tty.poll_read(&mut cx, &mut buf) {
+            Poll::Ready(n) => assert_eq!(&buf[..n?], b"k"),
+            Poll::Pending => panic!("pending after the wakeup"),
+        }
+        assert!(tty.poll_write(&mut cx, b"out").is_ready());
+
+        // Still raw, until the blocking guard is dropped.
+        let tty = tty.into_blocking()?;
+        assert_eq!(
+            get_terminal_attr(dup.as_raw_fd())?.c_lflag & libc::ICANON,
+            0
+        );
+        drop(tty);
+        assert_eq!(get_terminal_attr(dup.as_raw_fd())?.c_lflag, saved.c_lflag);
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_restores() -> io::Result<()> {
+        let (_master, slave) = openpty()?;
+        let dup = slave.try_clone()?;
+        let saved = get_terminal_attr(dup.as_raw_fd())?;
+        let mut tty = slave.guard_mode()?;
+        tty.set_raw_mode()?;
+        let mut tty = AsyncTty::new(tty)?;
+        let waker = Waker::from(Arc::new(Counter(AtomicUsize::new(0))));
+        assert!(tty
+            .poll_read(&mut Context::from_waker(&waker), &mut [0])
+            .is_pending());
+        // While the thread waits for input.
+        drop(tty);
+        assert_eq!(get_terminal_attr(dup.as_raw_fd())?.c_lflag, saved.c_lflag);
+        Ok(())
+    }
+}
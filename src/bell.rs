@@ -0,0 +1,151 @@
+//! Ringing the terminal's bell, audibly or by flashing the screen.
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::TtyWithGuard;
+
+use std::env;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::Duration;
+
+/// How [`TtyWithGuard::bell`] gets the user's attention.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BellKind {
+    /// `BEL`, which the terminal may turn into a sound, a flash or a notification as the
+    /// user configured it.
+    Audible,
+    /// Flashing the screen, falling back to `BEL` where that isn't possible.
+    Visual,
+}
+
+const BEL: &[u8] = b"\x07";
+/// The flash of xterm: reverse video for 100 ms.
+const XTERM_FLASH: &[u8] = b"\x1b[?5h$<100/>\x1b[?5l";
+
+impl<T: Write + AsRawFd> TtyWithGuard<T> {
+    /// Rings the bell.
+    ///
+    /// With the `terminfo` feature the `bel` and `flash` capabilities of `$TERM` are used
+    /// where it has them, including their delays. Otherwise a visual bell flashes like
+    /// xterm does, which most terminals understand, except where `TERM` is `dumb` or unset
+    /// and it rings `BEL` instead. A visual bell blocks for the duration of the flash.
+    ///
+    /// ```no_run
+    /// use raw_tty::{BellKind, IntoRawMode};
+    /// use std::fs;
+    /// use std::io;
+    ///
+    /// fn main() -> io::Result<()> {
+    ///     let tty = fs::OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    ///     let mut tty = tty.into_raw_mode()?;
+    ///     // E.g. when the cursor hit the end of the line.
+    ///     tty.bell(BellKind::Visual)
+    /// }
+    /// ```
+    pub fn bell(&mut self, kind: BellKind) -> io::Result<()> {
+        let term = env::var("TERM").ok();
+        #[cfg(feature = "terminfo")]
+        let sequence = match terminfo() {
+            Some(terminfo) => bell_sequence(kind, term.as_deref(), terminfo.bell(kind)),
+            None => bell_sequence(kind, term.as_deref(), None),
+        };
+        #[cfg(not(feature = "terminfo"))]
+        let sequence = bell_sequence(kind, term.as_deref(), None);
+        write_padded(&mut **self, sequence)
+    }
+}
+
+/// The entry of `$TERM`, loaded once.
+#[cfg(feature = "terminfo")]
+fn terminfo() -> Option<&'static crate::terminfo::Terminfo> {
+    static TERMINFO: std::sync::OnceLock<Option<crate::terminfo::Terminfo>> =
+        std::sync::OnceLock::new();
+    TERMINFO
+        .get_or_init(|| crate::terminfo::Terminfo::from_env().ok())
+        .as_ref()
+}
+
+/// The sequence for `kind` on the terminal named `term`, with `capability` from its
+/// terminfo entry if there is one.
+fn bell_sequence<'a>(kind: BellKind, term: Option<&str>, capability: Option<&'a [u8]>) -> &'a [u8] {
+    if let Some(sequence) = capability {
+        return sequence;
+    }
+    // Like `TerminalClass::Dumb` of sessions.
+    let dumb = matches!(term, None | Some("") | Some("dumb"));
+    match kind {
+        BellKind::Visual if !dumb => XTERM_FLASH,
+        _ => BEL,
+    }
+}
+
+/// Writes `sequence`, waiting out its `$<..>` padding specifications instead of sending
+/// them. Flushes before each wait, so that the first half of a flash is visible.
+fn write_padded<W: Write>(out: &mut W, mut sequence: &[u8]) -> io::Result<()> {
+    while let Some(start) = sequence.windows(2).position(|w| w == b"$<") {
+        let end = match sequence[start..].iter().position(|&b| b == b'>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        out.write_all(&sequence[..start])?;
+        out.flush()?;
+        thread::sleep(padding(&sequence[start + 2..end]));
+        sequence = &sequence[end + 1..];
+    }
+    out.write_all(sequence)?;
+    out.flush()
+}
+
+/// The delay of a padding specification like `100/` or `5.5*`, in milliseconds. The
+/// per-line `*` makes no difference for a bell.
+fn padding(spec: &[u8]) -> Duration {
+    let number: String = spec
+        .iter()
+        .take_while(|b| b.is_ascii_digit() || **b == b'.')
+        .map(|&b| b as char)
+        .collect();
+    Duration::from_secs_f64(number.parse::<f64>().unwrap_or(0.0) / 1000.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::openpty;
+    use crate::GuardMode;
+    use std::io::Read;
+    use std::time::Instant;
+
+    #[test]
+    fn test_bell_sequence() {
+        let xterm = Some("xterm-256color");
+        assert_eq!(bell_sequence(BellKind::Audible, xterm, None), BEL);
+        assert_eq!(bell_sequence(BellKind::Visual, xterm, None), XTERM_FLASH);
+        assert_eq!(bell_sequence(BellKind::Visual, Some("dumb"), None), BEL);
+        assert_eq!(bell_sequence(BellKind::Visual, None, None), BEL);
+        let flash = &b"\x1bg"[..];
+        assert_eq!(bell_sequence(BellKind::Visual, xterm, Some(flash)), flash);
+    }
+
+    #[test]
+    fn test_write_padded() -> io::Result<()> {
+        assert_eq!(padding(b"100/"), Duration::from_millis(100));
+        assert_eq!(padding(b"2.5*"), Duration::from_micros(2500));
+
+        let (mut master, slave) = openpty()?;
+        let mut tty = slave.guard_mode()?;
+        let start = Instant::now();
+        write_padded(&mut *tty, b"\x1b[?5h$<20/>\x1b[?5l")?;
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let mut buf = [0; 10];
+        master.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"\x1b[?5h\x1b[?5l");
+
+        tty.bell(BellKind::Audible)?;
+        let mut buf = [0];
+        master.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"\x07");
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+//! Interrupting a read blocked on a tty from another thread.
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::util::poll;
+use crate::RawReader;
+
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Cancels the reads of a [`RawReader`], see [`RawReader::canceller`].
+///
+/// Cloning gives another handle for the same reader. Cancelling is permanent: the read
+/// blocked at the time and every later one fail with a [`ReadCancelled`] error, so that
+/// loops like `read_exact` end too. Dropping the reader restores the mode as usual.
+///
+/// ```no_run
+/// use raw_tty::{IntoRawMode, ReadCancelled};
+/// use std::io::{self, stdin, Read};
+/// use std::thread;
+///
+/// fn main() -> io::Result<()> {
+///     let mut tty = stdin().into_raw_mode()?;
+///     let canceller = tty.canceller()?;
+///     // E.g. a quit command arriving over the network.
+///     thread::spawn(move || canceller.cancel());
+///     let mut key = [0];
+///     match tty.read(&mut key) {
+///         Err(ref e) if ReadCancelled::find(e).is_some() => Ok(()),
+///         result => result.map(drop),
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReadCanceller {
+    state: Arc<Cancel>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Cancel {
+    cancelled: AtomicBool,
+    /// Written to on cancel.
+    wake: UnixStream,
+    /// Polled along with the tty, it stays readable once cancelled.
+    waiter: UnixStream,
+}
+
+impl ReadCanceller {
+    /// Makes the blocked read, if any, and all later reads fail. Never blocks itself.
+    pub fn cancel(&self) -> io::Result<()> {
+        if self.state.cancelled.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        match (&self.state.wake).write(&[0]) {
+            // Full means a wake-up is pending already.
+            Err(e) if e.kind() != io::ErrorKind::WouldBlock => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl Cancel {
+    fn new() -> io::Result<Cancel> {
+        let (wake, waiter) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+        Ok(Cancel {
+            cancelled: AtomicBool::new(false),
+            wake,
+            waiter,
+        })
+    }
+
+    /// Waits until `fd` is readable, failing if cancelled first. The waiter never gets
+    /// read, so a cancelled wait returns at once.
+    pub(crate) fn wait_readable(&self, fd: RawFd) -> io::Result<()> {
+        let mut fds = [fd, self.waiter.as_raw_fd()].map(|fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        });
+        loop {
+            poll(&mut fds, None)?;
+            // Checked after the wait, as both may have become readable.
+            if self.cancelled.load(Ordering::SeqCst) {
+                return Err(ReadCancelled::error());
+            }
+            if fds[0].revents != 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<R: AsRawFd> RawReader<R> {
+    /// A handle for cancelling the reads of this reader from another thread, e.g. to exit
+    /// promptly on a quit command while the main thread is blocked reading keys.
+    ///
+    /// From the first call on, every read polls the tty along with a socket the canceller
+    /// writes to, instead of blocking in `read` directly. All calls return handles for the
+    /// same cancellation.
+    pub fn canceller(&mut self) -> io::Result<ReadCanceller> {
+        let state = match self.cancel {
+            Some(ref state) => state.clone(),
+            None => self.cancel.insert(Arc::new(Cancel::new()?)).clone(),
+        };
+        Ok(ReadCanceller { state })
+    }
+}
+
+/// The error of a read on a [`RawReader`] after its [`ReadCanceller`] was used. Its kind is
+/// `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadCancelled;
+
+impl ReadCancelled {
+    fn error() -> io::Error {
+        io::Error::other(ReadCancelled)
+    }
+
+    /// The `ReadCancelled` inside `error`, if that is what it is.
+    pub fn find(error: &io::Error) -> Option<&ReadCancelled> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl std::fmt::Display for ReadCancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "the read was cancelled")
+    }
+}
+
+impl std::error::Error for ReadCancelled {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::{self, openpty};
+    use crate::IntoRawMode;
+    use std::io::Read;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_cancel_blocked_read() -> io::Result<()> {
+        let (mut master, slave) = openpty()?;
+        let mut tty = slave.into_raw_mode()?;
+        let canceller = tty.canceller()?;
+
+        master.write_all(b"a")?;
+        let mut key = [0];
+        tty.read_exact(&mut key)?;
+        assert_eq!(&key, b"a");
+
+        let reader = thread::spawn(move || {
+            let mut key = [0];
+            let error = tty.read_exact(&mut key).unwrap_err();
+            assert!(ReadCancelled::find(&error).is_some());
+            // Stays cancelled, even with input pending.
+            assert!(tty.read(&mut key).is_err());
+            let fd = tty.as_raw_fd();
+            assert!(util::wait_readable(fd, Some(Duration::from_secs(5))).unwrap());
+        });
+        thread::sleep(Duration::from_millis(20));
+        assert!(!canceller.is_cancelled());
+        canceller.clone().cancel()?;
+        assert!(canceller.is_cancelled());
+        master.write_all(b"b")?;
+        reader.join().unwrap();
+        Ok(())
+    }
+}
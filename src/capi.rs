@@ -0,0 +1,135 @@
+//! A C ABI for native plugins sharing the terminal with a Rust host, with the `capi`
+//! feature.
+//!
+//! A plugin which switches the terminal to raw mode with its own `tcsetattr` calls
+//! doesn't know that the host did the same, and one of them ends up restoring a mode the
+//! other still relies on. With these functions both sides go through the same guards:
+//! every [`raw_tty_enter_raw`] pushes a [`TtyModeGuard`](crate::TtyModeGuard) for the fd,
+//! which saves the mode as it is at that point, and [`raw_tty_restore`] pops the newest
+//! one again. Nesting works across the boundary, and [`exit`](crate::exit) restores the
+//! guards of plugins as well.
+//!
+//! The functions are exported unmangled from the binary which links this crate; a plugin
+//! looks them up with `dlsym`, or links against them. `include/raw_tty.h` declares them.
+//! An executable only puts them into its dynamic symbol table when linked with
+//! `-C link-arg=-rdynamic` (`-Wl,--export-dynamic`), e.g. through `RUSTFLAGS` or a
+//! `cargo:rustc-link-arg-bins=-rdynamic` line in the build script of the host. Without
+//! it neither `dlsym` nor the dynamic linker finds them.
+//! Errors are returned as negated `errno` values. The surface only grows in a compatible
+//! way, [`raw_tty_capi_version`] tells which functions are available.
+
+use crate::{ErrorPolicy, NotATty, TtyModeGuard};
+
+use libc::c_int;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::{mpsc, Mutex};
+
+/// The version of this interface, bumped when functions are added.
+pub const CAPI_VERSION: u32 = 1;
+
+/// The guards entered through this interface, newest last.
+static GUARDS: Mutex<Vec<TtyModeGuard>> = Mutex::new(Vec::new());
+
+fn errno(e: &io::Error) -> c_int {
+    match e.raw_os_error() {
+        Some(errno) => -errno,
+        None if e.get_ref().is_some_and(|e| e.is::<NotATty>()) => -libc::ENOTTY,
+        None => -libc::EIO,
+    }
+}
+
+/// The [`CAPI_VERSION`] of the linked crate.
+#[no_mangle]
+pub extern "C" fn raw_tty_capi_version() -> u32 {
+    CAPI_VERSION
+}
+
+/// Saves the mode of `fd` and switches it to raw mode. Returns the number of guards now
+/// entered through this interface for `fd`, or a negated `errno`.
+#[no_mangle]
+pub extern "C" fn raw_tty_enter_raw(fd: c_int) -> c_int {
+    let mut guard = match TtyModeGuard::new(fd as RawFd) {
+        Ok(guard) => guard,
+        Err(e) => return errno(&e),
+    };
+    // Never panic across the boundary, errors are returned by raw_tty_restore instead.
+    guard.set_error_policy(ErrorPolicy::Ignore);
+    if let Err(e) = guard.set_raw_mode() {
+        return errno(&e);
+    }
+    let mut guards = GUARDS.lock().unwrap_or_else(|e| e.into_inner());
+    guards.push(guard);
+    guards.iter().filter(|guard| guard.fd == fd).count() as c_int
+}
+
+/// Restores the mode saved by the newest [`raw_tty_enter_raw`] for `fd`. Returns 0, a
+/// negated `errno`, or `-EINVAL` if nothing was entered for `fd`.
+#[no_mangle]
+pub extern "C" fn raw_tty_restore(fd: c_int) -> c_int {
+    let mut guards = GUARDS.lock().unwrap_or_else(|e| e.into_inner());
+    let index = match guards.iter().rposition(|guard| guard.fd == fd) {
+        Some(index) => index,
+        None => return -libc::EINVAL,
+    };
+    let mut guard = guards.remove(index);
+    drop(guards);
+    // Restored once, by dropping, with the error reported here instead of ignored.
+    let (sender, receiver) = mpsc::channel();
+    guard.set_error_policy(ErrorPolicy::Report(Box::new(move |e| {
+        let _ = sender.send(errno(e));
+    })));
+    drop(guard);
+    receiver.try_recv().unwrap_or(0)
+}
+
+/// The number of live guards for `fd`, from either side of the boundary, so 0 if nobody
+/// changed its mode through this crate.
+#[no_mangle]
+pub extern "C" fn raw_tty_query(fd: c_int) -> c_int {
+    crate::live_guards_on(fd as RawFd) as c_int
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::attr::get_terminal_attr;
+    use crate::util;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn test_nested_across_boundary() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let fd = slave.as_raw_fd();
+        let canonical = |fd| get_terminal_attr(fd).map(|ios| ios.c_lflag & libc::ICANON != 0);
+        assert_eq!(raw_tty_capi_version(), CAPI_VERSION);
+        assert_eq!(raw_tty_query(fd), 0);
+
+        // The host is in raw mode when the plugin enters it too.
+        let mut host = TtyModeGuard::new(fd)?;
+        host.set_raw_mode()?;
+        assert_eq!(raw_tty_enter_raw(fd), 1);
+        assert_eq!(raw_tty_enter_raw(fd), 2);
+        assert_eq!(raw_tty_query(fd), 3);
+        assert_eq!(raw_tty_restore(fd), 0);
+        assert_eq!(raw_tty_restore(fd), 0);
+        assert!(!canonical(fd)?);
+        drop(host);
+        assert!(canonical(fd)?);
+
+        assert_eq!(raw_tty_restore(fd), -libc::EINVAL);
+        let null = std::fs::File::open("/dev/null")?;
+        assert_eq!(raw_tty_enter_raw(null.as_raw_fd()), -libc::ENOTTY);
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_once() -> io::Result<()> {
+        let tty = crate::testing::FakeTty::new();
+        assert_eq!(raw_tty_enter_raw(tty.fd()), 1);
+        let calls = tty.set_attr_calls();
+        assert_eq!(raw_tty_restore(tty.fd()), 0);
+        assert_eq!(tty.set_attr_calls(), calls + 1);
+        Ok(())
+    }
+}
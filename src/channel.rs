@@ -0,0 +1,215 @@
+//! Reading a tty on a thread of its own, for programs whose main thread can't block.
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::{RawReader, ReadSet};
+
+use std::io::{self, Read};
+use std::ops;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+/// The receiving end of a reader thread, see [`RawReader::into_channel`].
+///
+/// Derefs to the `Receiver`. The thread sends what it read until the tty reaches end of
+/// file, which disconnects the channel, or a read fails, which it sends as the last item.
+/// Dropping this stops the thread, which restores the mode of the tty, and waits for it:
+/// once the drop returns, the terminal is back in its previous mode.
+#[derive(Debug)]
+pub struct ChannelReader<T> {
+    receiver: Receiver<io::Result<T>>,
+    /// Closing it wakes up the thread.
+    stop: Option<UnixStream>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<T> ops::Deref for ChannelReader<T> {
+    type Target = Receiver<io::Result<T>>;
+
+    #[inline]
+    fn deref(&self) -> &Receiver<io::Result<T>> {
+        &self.receiver
+    }
+}
+
+impl<T> Drop for ChannelReader<T> {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<R: Read + AsRawFd + Send + 'static> RawReader<R> {
+    /// Moves the reader to a thread of its own, which sends the bytes as they arrive.
+    ///
+    /// ```no_run
+    /// use raw_tty::IntoRawMode;
+    /// use std::io::{self, stdin};
+    /// use std::time::Duration;
+    ///
+    /// fn main() -> io::Result<()> {
+    ///     let keys = stdin().into_raw_mode()?.into_channel()?;
+    ///     loop {
+    ///         // E.g. the event loop of a GUI toolkit.
+    ///         if let Ok(bytes) = keys.recv_timeout(Duration::from_millis(16)) {
+    ///             if bytes?.contains(&b'q') {
+    ///                 break;
+    ///             }
+    ///         }
+    ///     }
+    ///     // Dropping `keys` restores the terminal.
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn into_channel(self) -> io::Result<ChannelReader<Vec<u8>>> {
+        spawn(self, |reader, stop, sender| loop {
+            let readable = ReadSet::new().add(&**reader).add(stop).wait(None)?;
+            if readable.contains(stop) {
+                return Ok(());
+            }
+            let mut buf = Vec::new();
+            match reader.read_available(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {
+                    if sender.send(Ok(buf)).is_err() {
+                        return Ok(());
+                    }
+                }
+                Err(ref e) if is_spurious(e) => {}
+                Err(e) => return Err(e),
+            }
+        })
+    }
+
+    /// Like [`into_channel`](RawReader::into_channel), with the bytes decoded into
+    /// events on the thread, which also takes care of the Escape key timeout.
+    #[cfg(feature = "decoder")]
+    pub fn into_event_channel(self) -> io::Result<ChannelReader<crate::input::InputEvent>> {
+        let mut decoder = crate::input::Decoder::new();
+        spawn(self, move |reader, stop, sender| loop {
+            let pending = decoder.has_pending();
+            let timeout = Some(crate::prompt::ESCAPE_TIMEOUT).filter(|_| pending);
+            let readable = ReadSet::new().add(&**reader).add(stop).wait(timeout)?;
+            if readable.contains(stop) {
+                return Ok(());
+            }
+            let mut eof = false;
+            if readable.is_empty() {
+                if let Some(event) = decoder.flush_pending() {
+                    if sender.send(Ok(event)).is_err() {
+                        return Ok(());
+                    }
+                }
+            } else {
+                let mut buf = Vec::new();
+                match reader.read_available(&mut buf) {
+                    Ok(0) => eof = true,
+                    Ok(_) => decoder.feed(&buf),
+                    Err(ref e) if is_spurious(e) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            while let Some(event) = decoder.next_event() {
+                if sender.send(Ok(event)).is_err() {
+                    return Ok(());
+                }
+            }
+            if eof {
+                while let Some(event) = decoder.flush_pending() {
+                    let _ = sender.send(Ok(event));
+                }
+                return Ok(());
+            }
+        })
+    }
+}
+
+fn is_spurious(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    )
+}
+
+/// Runs `run` on a new thread until it returns, sending its error as the last item.
+fn spawn<R, T, F>(mut reader: RawReader<R>, mut run: F) -> io::Result<ChannelReader<T>>
+where
+    R: Read + AsRawFd + Send + 'static,
+    T: Send + 'static,
+    F: FnMut(&mut RawReader<R>, &UnixStream, &Sender<io::Result<T>>) -> io::Result<()>
+        + Send
+        + 'static,
+{
+    let (stop, stopped) = UnixStream::pair()?;
+    let (sender, receiver) = mpsc::channel();
+    let thread = thread::Builder::new()
+        .name("raw_tty reader".into())
+        .spawn(move || {
+            if let Err(e) = run(&mut reader, &stopped, &sender) {
+                let _ = sender.send(Err(e));
+            }
+            // Restores the mode, before the channel disconnects.
+            drop(reader);
+        })?;
+    Ok(ChannelReader {
+        receiver,
+        stop: Some(stop),
+        thread: Some(thread),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::attr::get_terminal_attr;
+    use crate::util::openpty;
+    use crate::IntoRawMode;
+    use std::io::Write;
+    use std::time::Duration;
+
+    #[test]
+    fn test_into_channel() -> io::Result<()> {
+        let (mut master, slave) = openpty()?;
+        let fd = slave.as_raw_fd();
+        let dup = slave.try_clone()?;
+        let saved = get_terminal_attr(fd)?;
+        let bytes = slave.into_raw_mode()?.into_channel()?;
+        assert_eq!(get_terminal_attr(fd)?.c_lflag & libc::ICANON, 0);
+
+        master.write_all(b"abc")?;
+        let mut received = Vec::new();
+        while received.len() < 3 {
+            received.extend(bytes.recv_timeout(Duration::from_secs(5)).unwrap()?);
+        }
+        assert_eq!(received, b"abc");
+
+        // Stops the thread while it waits, and restores.
+        drop(bytes);
+        assert_eq!(get_terminal_attr(dup.as_raw_fd())?.c_lflag, saved.c_lflag);
+        Ok(())
+    }
+
+    #[cfg(feature = "decoder")]
+    #[test]
+    fn test_into_event_channel() -> io::Result<()> {
+        use crate::input::{InputEvent, Key, KeyEvent};
+
+        let (mut master, slave) = openpty()?;
+        let events = slave.into_raw_mode()?.into_event_channel()?;
+        master.write_all(b"\x1b[Ax\x1b")?;
+        let timeout = Duration::from_secs(5);
+        for key in [Key::Up, Key::Char('x'), Key::Escape] {
+            let event = events.recv_timeout(timeout).unwrap()?;
+            assert_eq!(event, InputEvent::Key(KeyEvent::from(key)));
+        }
+
+        // The channel disconnects once the terminal hangs up.
+        drop(master);
+        assert!(events.recv_timeout(timeout).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+//! Tearing down independent guards in the reverse order of their creation.
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use std::ops;
+use std::sync::{Arc, Mutex};
+
+enum Slot<'a> {
+    /// Still held by its [`Registered`].
+    Live,
+    /// Dropped by its owner, waiting for the newer guards.
+    Released(Box<dyn Send + 'a>),
+}
+
+/// Makes sure that guards are torn down newest first, whatever order their owners drop
+/// them in.
+///
+/// Each guard saves the mode it found, so guards which didn't come from the same place,
+/// e.g. one for raw mode in the input handling and one for the alternate screen in the
+/// renderer, have to be dropped in the reverse order of their creation: otherwise an older
+/// guard restores its mode first and a newer one then brings back the mode the older one
+/// replaced. A guard passed to [`register`](CleanupOrchestrator::register) comes back as a
+/// [`Registered`] handle. When a handle is dropped before the handles registered after it,
+/// the guard is only dropped once all of those are gone.
+///
+/// ```no_run
+/// use raw_tty::{CleanupOrchestrator, GuardMode};
+/// use std::io::{self, stdin};
+///
+/// fn main() -> io::Result<()> {
+///     let cleanup = CleanupOrchestrator::new();
+///     let mut raw = cleanup.register(stdin().guard_mode()?);
+///     raw.set_raw_mode()?;
+///     let mut slow_reads = cleanup.register(stdin().guard_mode()?);
+///     slow_reads.modify_mode(|mut ios| {
+///         ios.c_cc[libc::VTIME] = 10;
+///         ios
+///     })?;
+///     // Deferred until slow_reads is dropped, so the original mode is restored last.
+///     drop(raw);
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct CleanupOrchestrator<'a> {
+    slots: Arc<Mutex<Vec<Slot<'a>>>>,
+}
+
+impl<'a> CleanupOrchestrator<'a> {
+    pub fn new() -> CleanupOrchestrator<'a> {
+        CleanupOrchestrator::default()
+    }
+
+    /// Takes over tearing down `guard`, which can still be used through the handle.
+    pub fn register<G: Send + 'a>(&self, guard: G) -> Registered<'a, G> {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        slots.push(Slot::Live);
+        Registered {
+            guard: Some(guard),
+            index: slots.len() - 1,
+            slots: self.slots.clone(),
+        }
+    }
+
+    /// The number of registered guards which weren't torn down yet, including the
+    /// deferred ones.
+    pub fn pending(&self) -> usize {
+        self.slots.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+/// A guard registered with a [`CleanupOrchestrator`], derefs to the guard.
+pub struct Registered<'a, G: Send + 'a> {
+    /// Only `None` while being dropped.
+    guard: Option<G>,
+    index: usize,
+    slots: Arc<Mutex<Vec<Slot<'a>>>>,
+}
+
+impl<G: Send> ops::Deref for Registered<'_, G> {
+    type Target = G;
+
+    #[inline]
+    fn deref(&self) -> &G {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<G: Send> ops::DerefMut for Registered<'_, G> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut G {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl<'a, G: Send + 'a> Drop for Registered<'a, G> {
+    fn drop(&mut self) {
+        let guard = match self.guard.take() {
+            Some(guard) => guard,
+            None => return,
+        };
+        let mut due = Vec::new();
+        {
+            let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+            slots[self.index] = Slot::Released(Box::new(guard));
+            while let Some(Slot::Released(_)) = slots.last() {
+                if let Some(Slot::Released(guard)) = slots.pop() {
+                    due.push(guard);
+                }
+            }
+        }
+        // Dropped outside the lock, newest first, in case a guard registers or releases
+        // another one.
+        for guard in due {
+            drop(guard);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::attr::get_terminal_attr;
+    use crate::{util, TtyModeGuard};
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    struct Logged(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+    impl Drop for Logged {
+        fn drop(&mut self) {
+            self.1.lock().unwrap().push(self.0);
+        }
+    }
+
+    #[test]
+    fn test_reverse_order() -> io::Result<()> {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let cleanup = CleanupOrchestrator::new();
+        let a = cleanup.register(Logged("a", log.clone()));
+        let b = cleanup.register(Logged("b", log.clone()));
+        let c = cleanup.register(Logged("c", log.clone()));
+        drop(a);
+        drop(b);
+        assert!(log.lock().unwrap().is_empty());
+        assert_eq!(cleanup.pending(), 3);
+        drop(c);
+        assert_eq!(*log.lock().unwrap(), ["c", "b", "a"]);
+        assert_eq!(cleanup.pending(), 0);
+
+        let (_master, slave) = util::openpty()?;
+        let fd = slave.as_raw_fd();
+        let mut raw = cleanup.register(TtyModeGuard::new(fd)?);
+        raw.set_raw_mode()?;
+        let mut slow = cleanup.register(TtyModeGuard::new(fd)?);
+        slow.modify_mode(|mut ios| {
+            ios.c_cc[libc::VTIME] = 10;
+            ios
+        })?;
+        drop(raw);
+        assert_eq!(get_terminal_attr(fd)?.c_lflag & libc::ICANON, 0);
+        drop(slow);
+        assert_ne!(get_terminal_attr(fd)?.c_lflag & libc::ICANON, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,6 @@
+//! Drop-in replacements for the APIs of other terminal crates, to switch to this crate
+//! first and move to the guards later.
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+pub mod termion;
@@ -0,0 +1,146 @@
+//! The `termion::raw` API, with the `termion-compat` feature.
+//!
+//! [`IntoRawMode`] and [`RawTerminal`] have the signatures of termion's, so code written
+//! against termion keeps compiling after changing its imports:
+//!
+//! ```no_run
+//! // Was `use termion::raw::IntoRawMode;`.
+//! use raw_tty::compat::termion::IntoRawMode;
+//! use std::io::{self, stdout, Write};
+//!
+//! fn main() -> io::Result<()> {
+//!     let mut stdout = stdout().into_raw_mode()?;
+//!     write!(stdout, "raw\r\n")?;
+//!     stdout.suspend_raw_mode()?;
+//!     writeln!(stdout, "cooked")?;
+//!     stdout.activate_raw_mode()?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Underneath is a [`TtyModeGuard`], which [`RawTerminal::guard`] hands out for the parts
+//! of a program that have moved on: the mode is restored by the guard's drop, without
+//! termion's panic when restoring fails, and the guard's hooks and error policy apply.
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::{Termios, TtyModeGuard};
+
+use std::io::{self, Write};
+use std::ops;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+
+/// A terminal in raw mode, which is restored when dropped, like `termion::raw::RawTerminal`.
+pub struct RawTerminal<W: Write + AsFd> {
+    /// Dropped first, while `output` still holds the fd open.
+    guard: TtyModeGuard,
+    output: W,
+    /// The mode `activate_raw_mode` goes back to.
+    raw: Termios,
+}
+
+/// Types which can be switched to raw mode, like `termion::raw::IntoRawMode`.
+pub trait IntoRawMode: Write + AsFd + Sized {
+    /// Switches to raw mode, until the returned terminal is dropped.
+    fn into_raw_mode(self) -> io::Result<RawTerminal<Self>>;
+}
+
+impl<W: Write + AsFd> IntoRawMode for W {
+    fn into_raw_mode(self) -> io::Result<RawTerminal<W>> {
+        let mut guard = TtyModeGuard::new(self.as_fd().as_raw_fd())?;
+        guard.set_raw_mode()?;
+        let raw = guard.get_attr()?;
+        Ok(RawTerminal {
+            guard,
+            output: self,
+            raw,
+        })
+    }
+}
+
+impl<W: Write + AsFd> RawTerminal<W> {
+    /// Temporarily switches back to the mode from before raw mode.
+    pub fn suspend_raw_mode(&self) -> io::Result<()> {
+        self.guard
+            .set_attr("suspend_raw_mode", &self.guard.saved()?)
+    }
+
+    /// Switches to raw mode again after [`suspend_raw_mode`](RawTerminal::suspend_raw_mode).
+    pub fn activate_raw_mode(&self) -> io::Result<()> {
+        self.guard.set_attr("activate_raw_mode", &self.raw)
+    }
+
+    /// The guard behind this terminal, which termion doesn't have.
+    pub fn guard(&mut self) -> &mut TtyModeGuard {
+        &mut self.guard
+    }
+}
+
+impl<W: Write + AsFd> ops::Deref for RawTerminal<W> {
+    type Target = W;
+
+    fn deref(&self) -> &W {
+        &self.output
+    }
+}
+
+impl<W: Write + AsFd> ops::DerefMut for RawTerminal<W> {
+    fn deref_mut(&mut self) -> &mut W {
+        &mut self.output
+    }
+}
+
+impl<W: Write + AsFd> Write for RawTerminal<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl<W: Write + AsFd> AsFd for RawTerminal<W> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.output.as_fd()
+    }
+}
+
+impl<W: Write + AsFd> AsRawFd for RawTerminal<W> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.output.as_fd().as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::attr::get_terminal_attr;
+    use crate::util::openpty;
+    use std::io::Read;
+
+    #[test]
+    fn test_raw_terminal() -> io::Result<()> {
+        let (mut master, slave) = openpty()?;
+        let fd = slave.as_raw_fd();
+        let dup = slave.try_clone()?;
+        let cooked = get_terminal_attr(fd)?;
+
+        let mut tty = slave.into_raw_mode()?;
+        assert_eq!(get_terminal_attr(fd)?.c_lflag & libc::ICANON, 0);
+        writeln!(tty, "a")?;
+        tty.suspend_raw_mode()?;
+        assert_eq!(get_terminal_attr(fd)?.c_lflag, cooked.c_lflag);
+        // Cooked again, the newline is translated.
+        writeln!(tty, "b")?;
+        tty.activate_raw_mode()?;
+        assert_eq!(get_terminal_attr(fd)?.c_lflag & libc::ICANON, 0);
+        let mut buf = [0; 5];
+        master.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"a\nb\r\n");
+
+        drop(tty);
+        assert_eq!(get_terminal_attr(dup.as_raw_fd())?.c_lflag, cooked.c_lflag);
+        Ok(())
+    }
+}
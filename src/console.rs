@@ -0,0 +1,169 @@
+//! Coping with `/dev/console` and the consoles of containers.
+//!
+//! The system console and the tty a container runtime sets up for `tty: true` are terminals
+//! as far as termios goes, so raw mode works on them. Other ioctls are less reliable: the
+//! window size is often reported as 0x0 or fails outright, and the process group calls
+//! fail when the console isn't the controlling terminal of anything. [`probe`] checks all
+//! of them up front and reports what failed instead of returning the first error, and
+//! [`window_size`] falls back to something usable.
+//!
+//! ```no_run
+//! use raw_tty::{console, IntoRawMode};
+//! use std::io::{self, stdin};
+//!
+//! fn main() -> io::Result<()> {
+//!     let support = console::probe(&stdin());
+//!     for (operation, error) in &support.failed {
+//!         eprintln!("{:?} failed: {}", operation, error);
+//!     }
+//!     let size = console::window_size(&stdin());
+//!     let _stdin = stdin().into_raw_mode()?;
+//!     print!("{}x{}\r\n", size.cols, size.rows);
+//!     Ok(())
+//! }
+//! ```
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::attr::{get_terminal_attr, get_window_size};
+use crate::util::fstat;
+use crate::{syscall, JobControl, WindowSize};
+
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+
+/// What [`window_size`] falls back to without the ioctl and environment variables.
+pub const DEFAULT_SIZE: WindowSize = WindowSize {
+    rows: 24,
+    cols: 80,
+    x_pixels: 0,
+    y_pixels: 0,
+};
+
+/// A call on the tty which [`probe`] tries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// `tcgetattr`, which raw mode needs.
+    GetAttr,
+    /// `TIOCGWINSZ`, failing or reporting 0x0.
+    GetWindowSize,
+    /// `tcgetpgrp`.
+    ForegroundProcessGroup,
+}
+
+/// What works on a tty, see [`probe`].
+#[derive(Debug)]
+pub struct ConsoleSupport {
+    /// Whether the tty is `/dev/console`, or what a container mounted there.
+    pub is_console: bool,
+    /// Whether the mode can be read, and so raw mode can be used.
+    pub termios: bool,
+    /// The window size, if the tty reports a usable one.
+    pub window_size: Option<WindowSize>,
+    /// The job control, if it could be determined.
+    pub job_control: Option<JobControl>,
+    /// The operations which failed, with their errors.
+    pub failed: Vec<(Operation, io::Error)>,
+}
+
+/// Tries every [`Operation`] on `tty`, collecting the failures.
+pub fn probe<T: AsRawFd>(tty: &T) -> ConsoleSupport {
+    let fd = tty.as_raw_fd();
+    let mut failed = Vec::new();
+    let mut record = |operation, error| failed.push((operation, error));
+
+    let termios = match get_terminal_attr(fd) {
+        Ok(_) => true,
+        Err(e) => {
+            record(Operation::GetAttr, e);
+            false
+        }
+    };
+    let window_size = match get_window_size(fd) {
+        Ok(size) if size.rows > 0 && size.cols > 0 => Some(size),
+        Ok(_) => {
+            let e = io::Error::new(io::ErrorKind::InvalidData, "the window size is 0x0");
+            record(Operation::GetWindowSize, e);
+            None
+        }
+        Err(e) => {
+            record(Operation::GetWindowSize, e);
+            None
+        }
+    };
+    let job_control = match syscall::with(fd, |sys| sys.job_control(fd)) {
+        Ok(job_control) => Some(job_control),
+        Err(e) => {
+            record(Operation::ForegroundProcessGroup, e);
+            None
+        }
+    };
+    ConsoleSupport {
+        is_console: is_console(tty),
+        termios,
+        window_size,
+        job_control,
+        failed,
+    }
+}
+
+/// Whether `tty` is the same device as `/dev/console`.
+pub fn is_console<T: AsRawFd>(tty: &T) -> bool {
+    match (fstat(tty.as_raw_fd()), fs::metadata("/dev/console")) {
+        // dev_t isn't u64 everywhere.
+        #[allow(clippy::unnecessary_cast)]
+        (Ok(tty), Ok(console)) => {
+            tty.st_mode & libc::S_IFMT == libc::S_IFCHR && tty.st_rdev as u64 == console.rdev()
+        }
+        _ => false,
+    }
+}
+
+/// The window size of `tty`, never failing and never 0x0.
+///
+/// Falls back to `LINES` and `COLUMNS`, which container runtimes often pass on from the
+/// attaching terminal, and then to [`DEFAULT_SIZE`].
+pub fn window_size<T: AsRawFd>(tty: &T) -> WindowSize {
+    match get_window_size(tty.as_raw_fd()) {
+        Ok(size) if size.rows > 0 && size.cols > 0 => size,
+        _ => {
+            let var = |name| env::var(name).ok()?.parse().ok().filter(|&n| n > 0);
+            WindowSize {
+                rows: var("LINES").unwrap_or(DEFAULT_SIZE.rows),
+                cols: var("COLUMNS").unwrap_or(DEFAULT_SIZE.cols),
+                ..WindowSize::default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util;
+
+    #[test]
+    fn test_probe() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let support = probe(&slave);
+        assert!(!support.is_console);
+        assert!(support.termios);
+        assert_eq!(support.job_control, Some(JobControl::Unavailable));
+        // A fresh pty is 0x0, like many container consoles.
+        assert_eq!(support.window_size, None);
+        assert_eq!(support.failed.len(), 1);
+        assert_eq!(support.failed[0].0, Operation::GetWindowSize);
+        let size = window_size(&slave);
+        assert!(size.rows > 0 && size.cols > 0);
+
+        let null = fs::File::open("/dev/null")?;
+        let support = probe(&null);
+        assert!(!support.termios);
+        let failed: Vec<_> = support.failed.iter().map(|(op, _)| *op).collect();
+        assert_eq!(failed, [Operation::GetAttr, Operation::GetWindowSize]);
+        Ok(())
+    }
+}
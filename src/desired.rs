@@ -0,0 +1,156 @@
+//! Describing the mode a program needs, instead of switching to it step by step.
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::{ControlFlags, Flags, InputFlags, LocalFlags, OutputFlags, Termios};
+
+use libc::cc_t;
+
+/// The flags and control characters a program needs, to be applied with
+/// [`TtyModeGuard::reconcile`](crate::TtyModeGuard::reconcile).
+///
+/// Everything not mentioned is left as it is on the tty. Reconciling is idempotent, so the
+/// program can keep one `DesiredMode` and reconcile whenever the mode may have been
+/// changed behind its back, e.g. after running a subprocess or after
+/// [`reattach`](crate::TtyModeGuard::reattach), instead of tracking which toggles to redo.
+///
+/// ```no_run
+/// use raw_tty::{DesiredMode, GuardMode, LocalFlags};
+/// use std::io::{self, stdin};
+/// use std::process::Command;
+///
+/// fn main() -> io::Result<()> {
+///     let mut tty = stdin().guard_mode()?;
+///     let desired = DesiredMode::raw().set(LocalFlags::ISIG);
+///     tty.reconcile(&desired)?;
+///     Command::new("vi").status()?;
+///     // Only touches the terminal if vi left it in another mode.
+///     tty.reconcile(&desired)?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DesiredMode {
+    /// The flags to set, as a termios of masks.
+    set: Termios,
+    /// The flags to clear.
+    clear: Termios,
+    /// The control characters to change.
+    chars: [Option<cc_t>; libc::NCCS],
+}
+
+impl DesiredMode {
+    /// Nothing desired, reconciling doesn't change anything.
+    pub fn new() -> DesiredMode {
+        DesiredMode::default()
+    }
+
+    /// Raw mode as `cfmakeraw` sets it: no line editing, echo, signals, flow control or
+    /// translations, 8 bit characters, and reads returning as soon as a byte arrived.
+    pub fn raw() -> DesiredMode {
+        DesiredMode::new()
+            .clear(
+                InputFlags::IGNBRK
+                    | InputFlags::BRKINT
+                    | InputFlags::PARMRK
+                    | InputFlags::ISTRIP
+                    | InputFlags::INLCR
+                    | InputFlags::IGNCR
+                    | InputFlags::ICRNL
+                    | InputFlags::IXON,
+            )
+            .clear(OutputFlags::OPOST)
+            .clear(ControlFlags::PARENB | ControlFlags(libc::CSIZE))
+            .set(ControlFlags(libc::CS8))
+            .clear(
+                LocalFlags::ECHO
+                    | LocalFlags::ECHONL
+                    | LocalFlags::ICANON
+                    | LocalFlags::ISIG
+                    | LocalFlags::IEXTEN,
+            )
+            .control_char(libc::VMIN, 1)
+            .control_char(libc::VTIME, 0)
+    }
+
+    /// Wants `flags` set. The last call about a flag wins.
+    pub fn set<F: Flags>(mut self, flags: F) -> DesiredMode {
+        *F::field(self.set.as_libc_mut()) |= flags.bits();
+        *F::field(self.clear.as_libc_mut()) &= !flags.bits();
+        self
+    }
+
+    /// Wants `flags` cleared. The last call about a flag wins.
+    pub fn clear<F: Flags>(mut self, flags: F) -> DesiredMode {
+        *F::field(self.clear.as_libc_mut()) |= flags.bits();
+        *F::field(self.set.as_libc_mut()) &= !flags.bits();
+        self
+    }
+
+    /// Wants the control character at `index`, e.g. `libc::VMIN`, to be `value`.
+    pub fn control_char(mut self, index: usize, value: cc_t) -> DesiredMode {
+        self.chars[index] = Some(value);
+        self
+    }
+
+    /// `live` with the desired changes applied.
+    pub fn apply(&self, live: &Termios) -> Termios {
+        let mut ios = *live;
+        let raw = ios.as_libc_mut();
+        let fields = [
+            (&mut raw.c_iflag, self.set.c_iflag, self.clear.c_iflag),
+            (&mut raw.c_oflag, self.set.c_oflag, self.clear.c_oflag),
+            (&mut raw.c_cflag, self.set.c_cflag, self.clear.c_cflag),
+            (&mut raw.c_lflag, self.set.c_lflag, self.clear.c_lflag),
+        ];
+        for (field, set, clear) in fields {
+            *field = (*field & !clear) | set;
+        }
+        for (c, desired) in raw.c_cc.iter_mut().zip(&self.chars) {
+            if let Some(desired) = *desired {
+                *c = desired;
+            }
+        }
+        ios
+    }
+
+    /// Whether `live` already is as desired.
+    pub fn is_satisfied_by(&self, live: &Termios) -> bool {
+        let ios = self.apply(live);
+        let flags = |ios: &Termios| [ios.c_iflag, ios.c_oflag, ios.c_cflag, ios.c_lflag];
+        flags(&ios) == flags(live) && ios.c_cc == live.c_cc
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::attr::{get_terminal_attr, set_terminal_attr};
+    use crate::{util, GuardMode};
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn test_reconcile() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let fd = slave.as_raw_fd();
+        let mut tty = slave.guard_mode()?;
+        let desired = DesiredMode::raw().set(LocalFlags::ISIG);
+
+        assert!(tty.reconcile(&desired)?);
+        let ios = get_terminal_attr(fd)?;
+        assert_eq!(ios.c_lflag & (libc::ICANON | libc::ECHO), 0);
+        assert_ne!(ios.c_lflag & libc::ISIG, 0);
+        assert!(desired.is_satisfied_by(&ios));
+
+        let calls = tty.stats().set_attr_calls;
+        assert!(!tty.reconcile(&desired)?);
+        assert_eq!(tty.stats().set_attr_calls, calls);
+
+        // What a subprocess leaves behind.
+        set_terminal_attr(fd, &tty.saved_termios())?;
+        assert!(tty.reconcile(&desired)?);
+        assert_eq!(get_terminal_attr(fd)?.c_lflag & libc::ICANON, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+//! A snapshot of the terminal environment, for attaching to crash and bug reports.
+//!
+//! ```no_run
+//! use std::panic;
+//!
+//! fn main() {
+//!     let previous = panic::take_hook();
+//!     panic::set_hook(Box::new(move |info| {
+//!         eprintln!("{}", raw_tty::diagnostics::report());
+//!         previous(info);
+//!     }));
+//! }
+//! ```
+//!
+//! The [`Display`](fmt::Display) output has one `key: value` per line, with the fds
+//! indented below their `fd N:` line, so that it can be parsed again by simple tools.
+
+use crate::attr::{get_terminal_attr, get_window_size};
+use crate::{Termios, WindowSize};
+
+use std::env;
+use std::ffi::CStr;
+use std::fmt;
+use std::os::unix::io::RawFd;
+
+const IFLAGS: &[(&str, libc::tcflag_t)] = &[
+    ("BRKINT", libc::BRKINT),
+    ("ICRNL", libc::ICRNL),
+    ("IGNBRK", libc::IGNBRK),
+    ("IGNCR", libc::IGNCR),
+    ("INLCR", libc::INLCR),
+    ("ISTRIP", libc::ISTRIP),
+    ("IXON", libc::IXON),
+    ("IXOFF", libc::IXOFF),
+];
+const OFLAGS: &[(&str, libc::tcflag_t)] = &[("OPOST", libc::OPOST), ("ONLCR", libc::ONLCR)];
+const CFLAGS: &[(&str, libc::tcflag_t)] = &[
+    ("CREAD", libc::CREAD),
+    ("CLOCAL", libc::CLOCAL),
+    ("PARENB", libc::PARENB),
+    ("HUPCL", libc::HUPCL),
+];
+const LFLAGS: &[(&str, libc::tcflag_t)] = &[
+    ("ECHO", libc::ECHO),
+    ("ECHOE", libc::ECHOE),
+    ("ECHONL", libc::ECHONL),
+    ("ICANON", libc::ICANON),
+    ("IEXTEN", libc::IEXTEN),
+    ("ISIG", libc::ISIG),
+    ("TOSTOP", libc::TOSTOP),
+];
+
+/// A termios, decoded into the flags that matter for raw mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mode {
+    /// The names of the set flags, input flags first, then output, control and local.
+    pub flags: Vec<&'static str>,
+    pub vmin: libc::cc_t,
+    pub vtime: libc::cc_t,
+}
+
+impl Mode {
+    pub fn decode(ios: &Termios) -> Mode {
+        let groups = [
+            (ios.c_iflag, IFLAGS),
+            (ios.c_oflag, OFLAGS),
+            (ios.c_cflag, CFLAGS),
+            (ios.c_lflag, LFLAGS),
+        ];
+        let flags = groups
+            .iter()
+            .flat_map(|&(value, table)| table.iter().filter(move |&&(_, f)| value & f != 0))
+            .map(|&(name, _)| name)
+            .collect();
+        Mode {
+            flags,
+            vmin: ios.c_cc[libc::VMIN],
+            vtime: ios.c_cc[libc::VTIME],
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for flag in &self.flags {
+            write!(f, "{} ", flag)?;
+        }
+        write!(f, "VMIN={} VTIME={}", self.vmin, self.vtime)
+    }
+}
+
+/// What [`report`] found out about one fd.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdReport {
+    pub fd: RawFd,
+    pub is_tty: bool,
+    /// The path of the terminal device, from `ttyname`.
+    pub tty_name: Option<String>,
+    pub mode: Option<Mode>,
+    pub window_size: Option<WindowSize>,
+    /// The foreground process group, if the tty is the controlling terminal.
+    pub foreground_pgrp: Option<libc::pid_t>,
+}
+
+impl FdReport {
+    pub fn new(fd: RawFd) -> FdReport {
+        let is_tty = unsafe { libc::isatty(fd) == 1 };
+        let mut name = [0 as libc::c_char; 256];
+        let tty_name = match unsafe { libc::ttyname_r(fd, name.as_mut_ptr(), name.len()) } {
+            0 => Some(
+                unsafe { CStr::from_ptr(name.as_ptr()) }
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            _ => None,
+        };
+        let foreground_pgrp = match unsafe { libc::tcgetpgrp(fd) } {
+            -1 => None,
+            pgrp => Some(pgrp),
+        };
+        FdReport {
+            fd,
+            is_tty,
+            tty_name,
+            mode: get_terminal_attr(fd).ok().map(|ios| Mode::decode(&ios)),
+            window_size: get_window_size(fd).ok(),
+            foreground_pgrp,
+        }
+    }
+}
+
+/// The environment a terminal program runs in, see [`report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// The version of this crate.
+    pub version: &'static str,
+    pub os: &'static str,
+    pub arch: &'static str,
+    /// `TERM`, if it is set.
+    pub term: Option<String>,
+    /// The process group of this process, to compare with the foreground process groups.
+    pub pgrp: libc::pid_t,
+    pub fds: Vec<FdReport>,
+}
+
+impl Report {
+    /// A report on the given fds instead of the standard ones.
+    pub fn for_fds(fds: &[RawFd]) -> Report {
+        Report {
+            version: env!("CARGO_PKG_VERSION"),
+            os: env::consts::OS,
+            arch: env::consts::ARCH,
+            term: env::var("TERM").ok(),
+            pgrp: unsafe { libc::getpgrp() },
+            fds: fds.iter().map(|&fd| FdReport::new(fd)).collect(),
+        }
+    }
+}
+
+/// Reports on the environment and on stdin, stdout and stderr. Nothing is changed, and
+/// nothing that fails is an error: it is left out of the report instead.
+pub fn report() -> Report {
+    Report::for_fds(&[libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO])
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "raw_tty: {}", self.version)?;
+        writeln!(f, "platform: {} {}", self.os, self.arch)?;
+        writeln!(f, "TERM: {}", self.term.as_deref().unwrap_or("<unset>"))?;
+        writeln!(f, "pgrp: {}", self.pgrp)?;
+        for fd in &self.fds {
+            writeln!(f, "fd {}:", fd.fd)?;
+            writeln!(f, "  tty: {}", fd.is_tty)?;
+            if let Some(ref name) = fd.tty_name {
+                writeln!(f, "  name: {}", name)?;
+            }
+            if let Some(ref mode) = fd.mode {
+                writeln!(f, "  mode: {}", mode)?;
+            }
+            if let Some(size) = fd.window_size {
+                writeln!(f, "  window size: {}x{}", size.cols, size.rows)?;
+            }
+            if let Some(pgrp) = fd.foreground_pgrp {
+                writeln!(f, "  foreground pgrp: {}", pgrp)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util;
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn test_report() -> std::io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let null = File::open("/dev/null")?;
+        let report = Report::for_fds(&[slave.as_raw_fd(), null.as_raw_fd()]);
+        let (tty, not_a_tty) = (&report.fds[0], &report.fds[1]);
+        assert!(tty.is_tty);
+        assert!(tty.tty_name.as_deref().unwrap().starts_with("/dev/"));
+        let mode = tty.mode.as_ref().unwrap();
+        assert!(mode.flags.contains(&"ICANON"));
+        // Not the controlling terminal of anything.
+        assert_eq!(tty.foreground_pgrp, None);
+        assert!(!not_a_tty.is_tty);
+        assert_eq!(
+            (not_a_tty.mode.as_ref(), not_a_tty.window_size),
+            (None, None)
+        );
+
+        let text = report.to_string();
+        assert!(text.starts_with(&format!("raw_tty: {}\n", env!("CARGO_PKG_VERSION"))));
+        assert!(text.contains(&format!("fd {}:\n  tty: false\n", null.as_raw_fd())));
+        assert!(text.contains(&format!("  mode: {}\n", mode)));
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! The frame clock of programs which render continuously.
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::util::poll;
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+/// What [`EventLoop::tick`] woke up for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tick {
+    /// The tty has input to read, or hung up, which the read then reports.
+    Input,
+    /// The next frame is due.
+    Frame,
+}
+
+/// Waits for input or for the next frame, whichever comes first, for games and TUIs
+/// which redraw at a fixed rate.
+///
+/// ```no_run
+/// use raw_tty::{EventLoop, IntoRawMode, Tick};
+/// use std::io::{self, stdin, Read};
+///
+/// fn main() -> io::Result<()> {
+///     let mut stdin = stdin().into_raw_mode()?;
+///     let mut events = EventLoop::new(&stdin);
+///     let mut buf = [0; 64];
+///     loop {
+///         match events.tick(60)? {
+///             Tick::Input if stdin.read(&mut buf)? == 0 => return Ok(()),
+///             Tick::Input => { /* update the state */ }
+///             Tick::Frame => { /* draw */ }
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct EventLoop {
+    fd: RawFd,
+    next_frame: Option<Instant>,
+    skipped: u64,
+}
+
+impl EventLoop {
+    pub fn new<T: AsRawFd>(tty: &T) -> EventLoop {
+        EventLoop {
+            fd: tty.as_raw_fd(),
+            next_frame: None,
+            skipped: 0,
+        }
+    }
+
+    /// Waits until the tty is readable or the next of `fps` frames per second is due.
+    ///
+    /// The first frame is due right away. A due frame is returned before input, so that
+    /// a flood of input can't stop the drawing. Frames which were missed, because drawing
+    /// or handling input took too long, are skipped instead of returned in a burst, see
+    /// [`skipped_frames`](EventLoop::skipped_frames). With an `fps` of 0 it only waits for
+    /// input.
+    pub fn tick(&mut self, fps: u32) -> io::Result<Tick> {
+        let now = Instant::now();
+        if fps == 0 {
+            self.next_frame = None;
+        } else if self.next_frame.is_none() {
+            self.next_frame = Some(now);
+        }
+        if let Some(due) = self.next_frame {
+            if now >= due {
+                let interval = Duration::from_secs(1) / fps;
+                let mut next = due + interval;
+                if next <= now {
+                    let missed = (now - due).as_nanos() / interval.as_nanos();
+                    self.skipped += missed as u64;
+                    next = now + interval;
+                }
+                self.next_frame = Some(next);
+                return Ok(Tick::Frame);
+            }
+        }
+
+        let mut pollfd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if poll(std::slice::from_mut(&mut pollfd), self.next_frame)? > 0 {
+            return Ok(Tick::Input);
+        }
+        self.tick(fps)
+    }
+
+    /// When the next frame is due, if `tick` has been called with a frame rate.
+    pub fn frame_deadline(&self) -> Option<Instant> {
+        self.next_frame
+    }
+
+    /// How many frames were skipped because they were due before `tick` was called.
+    pub fn skipped_frames(&self) -> u64 {
+        self.skipped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util;
+    use std::io::Write;
+
+    #[test]
+    fn test_tick() -> io::Result<()> {
+        let (mut master, slave) = util::openpty()?;
+        let mut events = EventLoop::new(&slave);
+        let start = Instant::now();
+        assert_eq!(events.tick(50)?, Tick::Frame);
+        assert_eq!(events.tick(50)?, Tick::Frame);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        master.write_all(b"x\n")?;
+        assert_eq!(events.tick(50)?, Tick::Input);
+        assert_eq!(events.tick(0)?, Tick::Input);
+        assert_eq!(events.frame_deadline(), None);
+
+        events.tick(50)?;
+        std::thread::sleep(Duration::from_millis(70));
+        assert_eq!(events.tick(50)?, Tick::Frame);
+        assert!(events.skipped_frames() >= 2);
+        Ok(())
+    }
+}
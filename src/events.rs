@@ -0,0 +1,454 @@
+//! A single blocking event source for synchronous terminal programs.
+//!
+//! Almost every non-async TUI ends up writing the same loop: wait until the tty has input,
+//! but also wake up when the window is resized or when the process is stopped and continued
+//! by job control. [`Events`] merges all of these into one [`Event`] stream, using the
+//! self-pipe trick under the hood so that the signal handlers themselves do nothing but
+//! write a byte.
+//!
+//! ```no_run
+//! use raw_tty::events::{Event, Events};
+//! use std::io::{self, stdin};
+//!
+//! fn main() -> io::Result<()> {
+//!     let mut events = Events::new(&stdin())?;
+//!     loop {
+//!         match events.next_event()? {
+//!             Event::Readable => { /* read from stdin */ }
+//!             Event::Resize => { /* query the new window size and redraw */ }
+//!             Event::Suspend => { /* restore the terminal and stop the process */ }
+//!             Event::Continue => { /* re-apply raw mode and redraw */ }
+//!             Event::Hangup => return Ok(()),
+//!             Event::Idle => { /* only with set_idle_timeout */ }
+//!             Event::Reattached => { /* redraw everything */ }
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::util::*;
+
+use libc::c_int;
+use std::collections::VecDeque;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+use std::{io, mem, ptr};
+
+/// Something which happened to the terminal or the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The tty has input available to read.
+    Readable,
+    /// The window size changed (`SIGWINCH`).
+    Resize,
+    /// The process was asked to stop (`SIGTSTP`).
+    ///
+    /// Since the signal is caught, the process is *not* stopped automatically. The caller
+    /// is expected to restore the terminal and then stop itself, e.g. with `SIGSTOP`.
+    Suspend,
+    /// The process was continued after having been stopped (`SIGCONT`).
+    Continue,
+    /// The terminal hung up, e.g. the ssh connection dropped or the pty master was closed.
+    ///
+    /// Only returned once the input which arrived before the hangup was read, until then
+    /// it is `Readable`. Like `Readable` this is level triggered, so it keeps being returned.
+    Hangup,
+    /// No input arrived for the interval set with [`Events::set_idle_timeout`].
+    Idle,
+    /// A guard was [reattached](crate::TtyModeGuard::reattach) to a new terminal, which
+    /// has to be redrawn.
+    Reattached,
+}
+
+const SIGNALS: [c_int; 3] = [libc::SIGWINCH, libc::SIGTSTP, libc::SIGCONT];
+
+/// Written to the self-pipe for [`Event::Reattached`], 0 is no signal.
+const REATTACHED: u8 = 0;
+
+/// The write end of the self-pipe of the currently active `Events`, or -1.
+static PIPE_WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_signal(signal: c_int) {
+    let fd = PIPE_WRITE_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = signal as u8;
+        // Nothing useful can be done on failure: a full pipe already has a wakeup pending.
+        unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+    }
+}
+
+/// Makes the active `Events`, if any, return [`Event::Reattached`].
+pub(crate) fn notify_reattached() {
+    let fd = PIPE_WRITE_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        unsafe { libc::write(fd, &REATTACHED as *const u8 as *const libc::c_void, 1) };
+    }
+}
+
+/// Merges tty readability and job-control/resize signals into one blocking stream of
+/// [`Event`]s.
+///
+/// Only one `Events` may be active in a process at a time, since signal dispositions are
+/// process-wide. The previous signal handlers are restored when it is dropped.
+pub struct Events {
+    tty: RawFd,
+    pipe: [RawFd; 2],
+    previous: Vec<(c_int, libc::sigaction)>,
+    pending: VecDeque<Event>,
+    resize_debounce: Option<Duration>,
+    /// When the debounced `Event::Resize` is to be delivered.
+    resize_due: Option<Instant>,
+    idle_timeout: Option<Duration>,
+    /// When `Event::Readable` was last returned, or the idle timeout set.
+    last_input: Instant,
+    /// Whether `Event::Idle` was returned since `last_input`.
+    idle_sent: bool,
+}
+
+impl Events {
+    /// Starts listening for events on `tty` and installs the signal handlers.
+    pub fn new<T: AsRawFd>(tty: &T) -> io::Result<Events> {
+        let pipe = self_pipe()?;
+        if PIPE_WRITE_FD
+            .compare_exchange(-1, pipe[1], Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            unsafe {
+                libc::close(pipe[0]);
+                libc::close(pipe[1]);
+            }
+            return Err(io::Error::other(
+                "another raw_tty::events::Events is already active",
+            ));
+        }
+
+        let mut events = Events {
+            tty: tty.as_raw_fd(),
+            pipe,
+            previous: Vec::with_capacity(SIGNALS.len()),
+            pending: VecDeque::new(),
+            resize_debounce: None,
+            resize_due: None,
+            idle_timeout: None,
+            last_input: Instant::now(),
+            idle_sent: false,
+        };
+
+        for &signal in SIGNALS.iter() {
+            unsafe {
+                let mut action: libc::sigaction = mem::zeroed();
+                action.sa_sigaction = on_signal as extern "C" fn(c_int) as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                let mut previous = mem::zeroed();
+                convert_to_result(libc::sigaction(signal, &action, &mut previous))?;
+                events.previous.push((signal, previous));
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// The fd of the tty this listens on.
+    #[cfg(feature = "pty")]
+    pub(crate) fn tty(&self) -> RawFd {
+        self.tty
+    }
+
+    /// Delays `Event::Resize` until no further resize happened for `interval`, or delivers it
+    /// right away with `None`, the default.
+    ///
+    /// Dragging a window edge sends a burst of `SIGWINCH`; debouncing lets a renderer redraw
+    /// once for the final size. Resizes which pile up before being taken are always merged
+    /// into a single event.
+    pub fn set_resize_debounce(&mut self, interval: Option<Duration>) {
+        self.resize_debounce = interval;
+    }
+
+    /// Returns `Event::Idle` once no input arrived for `timeout`, or never with `None`, the
+    /// default.
+    ///
+    /// `Event::Idle` is returned once per idle stretch: the next one only comes `timeout`
+    /// after the next `Event::Readable`. Signals don't count as input. The interval starts
+    /// over when this is called.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+        self.last_input = Instant::now();
+        self.idle_sent = false;
+    }
+
+    /// Blocks until the next event happens.
+    ///
+    /// Signals take priority over input. `Event::Readable` is level triggered: it will be
+    /// returned again by the next call if the input wasn't consumed.
+    pub fn next_event(&mut self) -> io::Result<Event> {
+        loop {
+            if let Some(event) = self.poll(&mut [], None)? {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Waits for an event, or for one of `extra` to become ready, or for `deadline`.
+    ///
+    /// Returns `None` if nothing happened to the tty or the signals. The `revents` of
+    /// `extra` are always updated.
+    pub(crate) fn poll(
+        &mut self,
+        extra: &mut [libc::pollfd],
+        deadline: Option<Instant>,
+    ) -> io::Result<Option<Event>> {
+        for fd in extra.iter_mut() {
+            fd.revents = 0;
+        }
+        if let Some(event) = self.pending.pop_front() {
+            return Ok(Some(event));
+        }
+        if let Some(event) = self.due_resize().or_else(|| self.due_idle()) {
+            return Ok(Some(event));
+        }
+
+        let mut fds = Vec::with_capacity(2 + extra.len());
+        fds.push(libc::pollfd {
+            fd: self.pipe[0],
+            events: libc::POLLIN,
+            revents: 0,
+        });
+        fds.push(libc::pollfd {
+            fd: self.tty,
+            events: libc::POLLIN,
+            revents: 0,
+        });
+        fds.extend_from_slice(extra);
+        let idle_due = self.idle_due();
+        let wake = [deadline, self.resize_due, idle_due]
+            .iter()
+            .flatten()
+            .min()
+            .copied();
+        poll(&mut fds, wake)?;
+        for (fd, polled) in extra.iter_mut().zip(&fds[2..]) {
+            fd.revents = polled.revents;
+        }
+
+        if fds[0].revents != 0 {
+            self.drain_pipe()?;
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(Some(event));
+            }
+        }
+        if let Some(event) = self.due_resize() {
+            return Ok(Some(event));
+        }
+        let revents = fds[1].revents;
+        // Input which arrived before the hangup is still handed out first.
+        let drained = revents & libc::POLLIN == 0 || !self.input_pending();
+        if revents & (libc::POLLHUP | libc::POLLERR) != 0 && drained {
+            return Ok(Some(Event::Hangup));
+        }
+        if revents != 0 {
+            self.last_input = Instant::now();
+            self.idle_sent = false;
+            return Ok(Some(Event::Readable));
+        }
+        Ok(self.due_idle())
+    }
+
+    /// Whether there are bytes left to read, as opposed to just the end of the input.
+    fn input_pending(&self) -> bool {
+        let mut pending: c_int = 0;
+        let result = unsafe { libc::ioctl(self.tty, libc::FIONREAD, &mut pending) };
+        result != -1 && pending > 0
+    }
+
+    fn idle_due(&self) -> Option<Instant> {
+        match self.idle_timeout {
+            Some(timeout) if !self.idle_sent => Some(self.last_input + timeout),
+            _ => None,
+        }
+    }
+
+    fn due_idle(&mut self) -> Option<Event> {
+        if Instant::now() < self.idle_due()? {
+            return None;
+        }
+        self.idle_sent = true;
+        Some(Event::Idle)
+    }
+
+    fn due_resize(&mut self) -> Option<Event> {
+        let due = self.resize_due?;
+        if Instant::now() < due {
+            return None;
+        }
+        self.resize_due = None;
+        Some(Event::Resize)
+    }
+
+    fn drain_pipe(&mut self) -> io::Result<()> {
+        let mut buf = [0u8; 64];
+        loop {
+            let n = unsafe {
+                libc::read(
+                    self.pipe[0],
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            match convert_to_result(n) {
+                Ok(0) => return Ok(()),
+                Ok(n) => {
+                    for &signal in &buf[..n as usize] {
+                        if signal == REATTACHED {
+                            self.pending.push_back(Event::Reattached);
+                            continue;
+                        }
+                        let event = match c_int::from(signal) {
+                            libc::SIGWINCH => {
+                                if let Some(interval) = self.resize_debounce {
+                                    self.resize_due = Some(Instant::now() + interval);
+                                    continue;
+                                }
+                                if self.pending.contains(&Event::Resize) {
+                                    continue;
+                                }
+                                Event::Resize
+                            }
+                            libc::SIGTSTP => Event::Suspend,
+                            libc::SIGCONT => Event::Continue,
+                            _ => continue,
+                        };
+                        self.pending.push_back(event);
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for Events {
+    fn drop(&mut self) {
+        for (signal, previous) in self.previous.drain(..) {
+            unsafe { libc::sigaction(signal, &previous, ptr::null_mut()) };
+        }
+        PIPE_WRITE_FD.store(-1, Ordering::SeqCst);
+        for &fd in self.pipe.iter() {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::unix::io::FromRawFd;
+
+    #[test]
+    fn test_signals_and_input() -> io::Result<()> {
+        let _lock = EVENTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut fds = [-1; 2];
+        convert_to_result(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+        let (input, mut writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+        let mut events = Events::new(&input)?;
+        assert!(Events::new(&input).is_err());
+
+        unsafe { libc::raise(libc::SIGWINCH) };
+        assert_eq!(events.next_event()?, Event::Resize);
+
+        writer.write_all(b"x")?;
+        assert_eq!(events.next_event()?, Event::Readable);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hangup() -> io::Result<()> {
+        let _lock = EVENTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let (master, slave) = openpty()?;
+        let mut events = Events::new(&slave)?;
+        drop(master);
+        assert_eq!(events.next_event()?, Event::Hangup);
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_before_hangup() -> io::Result<()> {
+        let _lock = EVENTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut fds = [-1; 2];
+        convert_to_result(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+        let (mut input, mut writer) =
+            unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        let mut events = Events::new(&input)?;
+        writer.write_all(b"last words")?;
+        drop(writer);
+
+        assert_eq!(events.next_event()?, Event::Readable);
+        let mut buf = [0; 4];
+        input.read_exact(&mut buf)?;
+        assert_eq!(events.next_event()?, Event::Readable);
+        let mut rest = Vec::new();
+        input.read_to_end(&mut rest)?;
+        assert_eq!(rest, b" words");
+        assert_eq!(events.next_event()?, Event::Hangup);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resize_coalescing_and_debounce() -> io::Result<()> {
+        let _lock = EVENTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut fds = [-1; 2];
+        convert_to_result(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+        let (input, _writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        let mut events = Events::new(&input)?;
+        let soon = || Some(Instant::now() + Duration::from_millis(20));
+
+        for _ in 0..3 {
+            unsafe { libc::raise(libc::SIGWINCH) };
+        }
+        assert_eq!(events.poll(&mut [], soon())?, Some(Event::Resize));
+        assert_eq!(events.poll(&mut [], soon())?, None);
+
+        events.set_resize_debounce(Some(Duration::from_millis(50)));
+        let start = Instant::now();
+        for _ in 0..3 {
+            unsafe { libc::raise(libc::SIGWINCH) };
+        }
+        assert_eq!(events.next_event()?, Event::Resize);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(events.poll(&mut [], soon())?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_idle_timeout() -> io::Result<()> {
+        let _lock = EVENTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut fds = [-1; 2];
+        convert_to_result(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+        let (mut input, mut writer) =
+            unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        let mut events = Events::new(&input)?;
+        let soon = || Some(Instant::now() + Duration::from_millis(100));
+        assert_eq!(events.poll(&mut [], soon())?, None);
+
+        events.set_idle_timeout(Some(Duration::from_millis(30)));
+        let start = Instant::now();
+        assert_eq!(events.next_event()?, Event::Idle);
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        // Once per idle stretch.
+        assert_eq!(events.poll(&mut [], soon())?, None);
+
+        writer.write_all(b"x")?;
+        assert_eq!(events.next_event()?, Event::Readable);
+        input.read_exact(&mut [0])?;
+        let start = Instant::now();
+        assert_eq!(events.next_event()?, Event::Idle);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        Ok(())
+    }
+}
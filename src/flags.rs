@@ -0,0 +1,143 @@
+//! Typed views of the flag fields of a [`Termios`].
+//!
+//! ```
+//! use raw_tty::{LocalFlags, Termios};
+//!
+//! fn no_echo(ios: &mut Termios) {
+//!     // Instead of ios.c_lflag &= !(libc::ICANON | libc::ECHO).
+//!     ios.clear_flags(LocalFlags::ICANON | LocalFlags::ECHO);
+//! }
+//! # let mut ios = Termios::default();
+//! # ios.set_flags(LocalFlags::ECHO);
+//! # no_echo(&mut ios);
+//! # assert!(ios.local_flags().is_empty());
+//! ```
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::Termios;
+
+use libc::tcflag_t;
+use std::ops;
+
+/// A set of flags from one of the flag fields of a termios.
+pub trait Flags: Copy {
+    /// The raw bits, as in the libc constants.
+    fn bits(self) -> tcflag_t;
+
+    /// The field of `ios` the flags belong to.
+    fn field(ios: &mut libc::termios) -> &mut tcflag_t;
+}
+
+macro_rules! flags {
+    ($(#[$doc:meta])* $name:ident, $field:ident, $getter:ident: $($flag:ident)*) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+        pub struct $name(pub tcflag_t);
+
+        impl $name {
+            $(pub const $flag: $name = $name(libc::$flag);)*
+
+            /// Every flag with a constant above.
+            pub const ALL: $name = $name(0 $(| libc::$flag)*);
+
+            /// Whether all flags in `other` are set.
+            pub fn contains(self, other: $name) -> bool {
+                self.0 & other.0 == other.0
+            }
+
+            pub fn is_empty(self) -> bool {
+                self.0 == 0
+            }
+        }
+
+        impl ops::BitOr for $name {
+            type Output = $name;
+
+            fn bitor(self, other: $name) -> $name {
+                $name(self.0 | other.0)
+            }
+        }
+
+        impl Flags for $name {
+            fn bits(self) -> tcflag_t {
+                self.0
+            }
+
+            fn field(ios: &mut libc::termios) -> &mut tcflag_t {
+                &mut ios.$field
+            }
+        }
+
+        impl Termios {
+            #[doc = concat!("The `", stringify!($field), "` flags.")]
+            pub fn $getter(&self) -> $name {
+                $name(self.$field)
+            }
+        }
+    };
+}
+
+flags! {
+    /// The input flags, `c_iflag`.
+    InputFlags, c_iflag, input_flags:
+    IGNBRK BRKINT IGNPAR PARMRK INPCK ISTRIP INLCR IGNCR ICRNL IXON IXANY IXOFF IMAXBEL
+}
+
+flags! {
+    /// The output flags, `c_oflag`.
+    OutputFlags, c_oflag, output_flags: OPOST ONLCR OCRNL ONOCR ONLRET
+}
+
+flags! {
+    /// The control flags, `c_cflag`.
+    ControlFlags, c_cflag, control_flags: CSTOPB CREAD PARENB PARODD HUPCL CLOCAL
+}
+
+flags! {
+    /// The local flags, `c_lflag`.
+    LocalFlags, c_lflag, local_flags:
+    ISIG ICANON ECHO ECHOE ECHOK ECHONL NOFLSH TOSTOP IEXTEN
+}
+
+impl Termios {
+    /// Sets `flags` in their field, leaving the other flags as they are.
+    pub fn set_flags<F: Flags>(&mut self, flags: F) {
+        *F::field(self.as_libc_mut()) |= flags.bits();
+    }
+
+    /// Clears `flags` in their field.
+    pub fn clear_flags<F: Flags>(&mut self, flags: F) {
+        *F::field(self.as_libc_mut()) &= !flags.bits();
+    }
+
+    /// Flips `flags` in their field.
+    pub fn toggle_flags<F: Flags>(&mut self, flags: F) {
+        *F::field(self.as_libc_mut()) ^= flags.bits();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        let mut ios = Termios::default();
+        ios.set_flags(LocalFlags::ICANON | LocalFlags::ECHO);
+        ios.set_flags(InputFlags::ICRNL);
+        assert_eq!(ios.c_lflag, libc::ICANON | libc::ECHO);
+        assert!(ios.local_flags().contains(LocalFlags::ECHO));
+        assert!(!ios
+            .local_flags()
+            .contains(LocalFlags::ECHO | LocalFlags::ISIG));
+
+        ios.clear_flags(LocalFlags::ECHO);
+        ios.toggle_flags(OutputFlags::OPOST);
+        ios.toggle_flags(InputFlags::ICRNL);
+        assert_eq!(ios.local_flags(), LocalFlags::ICANON);
+        assert_eq!(ios.output_flags(), OutputFlags::OPOST);
+        assert!(ios.input_flags().is_empty());
+        assert!(ios.control_flags().is_empty());
+    }
+}
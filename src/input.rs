@@ -0,0 +1,786 @@
+//! Decoding the bytes read from a raw mode terminal into keys.
+//!
+//! In raw mode every key press arrives as one or more bytes: plain characters as UTF-8,
+//! control keys as C0 control codes and everything else as escape sequences. The
+//! [`Decoder`] turns these back into [`InputEvent`]s. It understands the common xterm
+//! style encodings as well as the kitty keyboard protocol (see
+//! [`Mode::KittyKeyboard`](crate::session::Mode::KittyKeyboard)), which adds reliable
+//! modifier and key release information.
+//!
+//! ```no_run
+//! use raw_tty::input::Decoder;
+//! use raw_tty::IntoRawMode;
+//! use std::io::{self, stdin, Read};
+//!
+//! fn main() -> io::Result<()> {
+//!     let mut stdin = stdin().into_raw_mode()?;
+//!     let mut decoder = Decoder::new();
+//!     let mut buf = [0; 64];
+//!     loop {
+//!         let n = stdin.read(&mut buf)?;
+//!         decoder.feed(&buf[..n]);
+//!         while let Some(event) = decoder.next_event() {
+//!             println!("{:?}\r", event);
+//!         }
+//!     }
+//! }
+//! ```
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use std::ops;
+use std::time::{Duration, Instant};
+
+/// A key on the keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    /// A key producing a character. With [`Modifiers::CTRL`] held, letters are reported
+    /// in lower case.
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    /// A function key, starting at `F(1)`.
+    F(u8),
+}
+
+/// The modifier keys held while a key was pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Modifiers(pub u8);
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers(0);
+    pub const SHIFT: Modifiers = Modifiers(1);
+    pub const ALT: Modifiers = Modifiers(2);
+    pub const CTRL: Modifiers = Modifiers(4);
+    pub const SUPER: Modifiers = Modifiers(8);
+    /// Only reported by the kitty keyboard protocol, like `META`.
+    pub const HYPER: Modifiers = Modifiers(16);
+    pub const META: Modifiers = Modifiers(32);
+
+    /// The bits of all modifiers above. The kitty keyboard protocol also reports the caps
+    /// and num lock states next to them, which are dropped, so that e.g. Ctrl-C compares
+    /// equal whether caps lock is on or not.
+    const ALL: u8 = 0x3f;
+
+    /// Whether all modifiers in `other` are held.
+    pub fn contains(self, other: Modifiers) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether no modifiers are held.
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl ops::BitOr for Modifiers {
+    type Output = Modifiers;
+
+    fn bitor(self, other: Modifiers) -> Modifiers {
+        Modifiers(self.0 | other.0)
+    }
+}
+
+/// Whether a key was pressed, repeated or released.
+///
+/// Only the kitty keyboard protocol reports anything but `Press`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum KeyEventKind {
+    #[default]
+    Press,
+    Repeat,
+    Release,
+}
+
+/// A key together with its modifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub modifiers: Modifiers,
+    pub kind: KeyEventKind,
+}
+
+impl From<Key> for KeyEvent {
+    fn from(key: Key) -> KeyEvent {
+        KeyEvent::new(key, Modifiers::NONE)
+    }
+}
+
+impl KeyEvent {
+    /// A key press.
+    pub fn new(key: Key, modifiers: Modifiers) -> KeyEvent {
+        KeyEvent {
+            key,
+            modifiers,
+            kind: KeyEventKind::Press,
+        }
+    }
+}
+
+/// Something decoded from the input stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    Key(KeyEvent),
+    /// The terminal window gained focus, see
+    /// [`Mode::FocusEvents`](crate::session::Mode::FocusEvents).
+    FocusGained,
+    /// The terminal window lost focus.
+    FocusLost,
+    /// A complete escape sequence which isn't understood, passed through as is.
+    Unknown(Vec<u8>),
+    /// This many bytes of input were discarded by the [throttle](Decoder::with_throttle).
+    Dropped(usize),
+}
+
+const ESC: u8 = 0x1b;
+
+/// Escape sequences longer than this are given up on.
+const MAX_SEQUENCE_LEN: usize = 64;
+
+/// Limits how much input a [`Decoder`] handles per tick of a render loop, see
+/// [`Decoder::with_throttle`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throttle {
+    /// The number of events [`Decoder::next_event`] returns between calls to
+    /// [`Decoder::tick`].
+    pub max_events: usize,
+    /// The number of bytes kept buffered. Input beyond that is discarded.
+    pub max_buffered: usize,
+}
+
+/// Incrementally decodes terminal input into [`InputEvent`]s.
+///
+/// Bytes are added with [`feed`](Decoder::feed) and events taken out with
+/// [`next_event`](Decoder::next_event). Incomplete sequences are kept until more input
+/// arrives, or until the [sequence deadline](Decoder::with_sequence_deadline) passes.
+///
+/// Terminals which send unusual sequences can be taught them with
+/// [`bind`](Decoder::bind), without needing a terminfo database:
+///
+/// ```
+/// use raw_tty::input::{Decoder, InputEvent, Key, KeyEvent};
+///
+/// let mut decoder = Decoder::new();
+/// // The Linux console's F1.
+/// decoder.bind(b"\x1b[[A", Key::F(1));
+/// decoder.feed(b"\x1b[[A");
+/// assert_eq!(decoder.next_event(), Some(InputEvent::Key(KeyEvent::from(Key::F(1)))));
+/// ```
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buf: Vec<u8>,
+    bindings: Vec<(Vec<u8>, KeyEvent)>,
+    sequence_deadline: Option<Duration>,
+    /// When `next_event` first found only an incomplete sequence in `buf`.
+    pending_since: Option<Instant>,
+    throttle: Option<Throttle>,
+    /// The events returned since the last `tick`.
+    tick_events: usize,
+    /// The bytes discarded since the last `InputEvent::Dropped`.
+    dropped: usize,
+}
+
+/// How the buffered input relates to the custom bindings.
+enum Binding {
+    /// The input starts with a bound sequence of this length.
+    Complete(KeyEvent, usize),
+    /// The input is the start of a bound sequence.
+    Prefix,
+    None,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Bounds how long an incomplete sequence is waited for in total. Once `deadline` has
+    /// passed since [`next_event`](Decoder::next_event) first found it incomplete, its bytes
+    /// are taken literally as with [`flush_pending`](Decoder::flush_pending).
+    ///
+    /// The timeout callers usually wait for the next byte restarts with every byte, so a
+    /// broken terminal which trickles out a sequence that never completes would otherwise
+    /// hold up all input after it. [`pending_deadline`](Decoder::pending_deadline) tells
+    /// how long a reader may block.
+    pub fn with_sequence_deadline(mut self, deadline: Duration) -> Decoder {
+        self.sequence_deadline = Some(deadline);
+        self
+    }
+
+    /// Keeps a flood of input, e.g. a runaway program in a pty or `cat /dev/urandom`, from
+    /// starving the render loop.
+    ///
+    /// Once [`next_event`](Decoder::next_event) returned `max_events` events it returns
+    /// `None` until [`tick`](Decoder::tick) is called, typically once per frame. Input fed
+    /// beyond `max_buffered` bytes is discarded, along with everything fed after it until
+    /// the buffered events are taken, and reported as a single [`InputEvent::Dropped`]
+    /// after them.
+    pub fn with_throttle(mut self, throttle: Throttle) -> Decoder {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    /// Starts a new tick of the [throttle](Decoder::with_throttle), allowing `max_events`
+    /// more events.
+    pub fn tick(&mut self) {
+        self.tick_events = 0;
+    }
+
+    /// Whether the [throttle](Decoder::with_throttle) holds back events until the next
+    /// [`tick`](Decoder::tick). A reader shouldn't block waiting for input then.
+    pub fn is_throttled(&self) -> bool {
+        self.throttle
+            .is_some_and(|throttle| self.tick_events >= throttle.max_events)
+    }
+
+    /// When the incomplete sequence which is buffered will be taken literally, if there is
+    /// a [sequence deadline](Decoder::with_sequence_deadline).
+    pub fn pending_deadline(&self) -> Option<Instant> {
+        Some(self.pending_since? + self.sequence_deadline?)
+    }
+
+    /// Decodes `sequence` as `key`, taking precedence over the built-in decoding.
+    ///
+    /// Binding a sequence again replaces the previous binding.
+    pub fn bind<K: Into<KeyEvent>>(&mut self, sequence: &[u8], key: K) {
+        let key = key.into();
+        match self.bindings.iter_mut().find(|(s, _)| s == sequence) {
+            Some(binding) => binding.1 = key,
+            None => self.bindings.push((sequence.to_vec(), key)),
+        }
+    }
+
+    /// Removes the binding for `sequence`, returning the key it was bound to.
+    pub fn unbind(&mut self, sequence: &[u8]) -> Option<KeyEvent> {
+        let index = self.bindings.iter().position(|(s, _)| s == sequence)?;
+        Some(self.bindings.remove(index).1)
+    }
+
+    fn binding(&self) -> Binding {
+        let mut complete: Option<&(Vec<u8>, KeyEvent)> = None;
+        for binding in &self.bindings {
+            let sequence = &binding.0;
+            if sequence.len() > self.buf.len() {
+                if !self.buf.is_empty() && sequence.starts_with(&self.buf) {
+                    return Binding::Prefix;
+                }
+            } else if self.buf.starts_with(sequence)
+                && complete.is_none_or(|c| c.0.len() < sequence.len())
+            {
+                complete = Some(binding);
+            }
+        }
+        match complete {
+            Some((sequence, key)) => Binding::Complete(*key, sequence.len()),
+            None => Binding::None,
+        }
+    }
+
+    fn decode(&mut self, flushing: bool) -> Option<InputEvent> {
+        let (event, len) = match self.binding() {
+            Binding::Prefix if !flushing => return None,
+            Binding::Complete(key, len) => (InputEvent::Key(key), len),
+            _ => parse(&self.buf)?,
+        };
+        self.buf.drain(..len);
+        Some(event)
+    }
+
+    /// Adds input to be decoded.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        let room = match self.throttle {
+            Some(_) if self.dropped > 0 => 0,
+            Some(throttle) => throttle.max_buffered.saturating_sub(self.buf.len()),
+            None => bytes.len(),
+        };
+        let kept = bytes.len().min(room);
+        self.buf.extend_from_slice(&bytes[..kept]);
+        self.dropped += bytes.len() - kept;
+    }
+
+    /// Takes the next complete event, or `None` if there is no input or only the start of
+    /// a sequence.
+    pub fn next_event(&mut self) -> Option<InputEvent> {
+        if self.is_throttled() {
+            return None;
+        }
+        let event = self.next_unthrottled();
+        if event.is_some() {
+            self.tick_events += 1;
+        }
+        event
+    }
+
+    fn next_unthrottled(&mut self) -> Option<InputEvent> {
+        if let Some(event) = self.decode(false) {
+            self.pending_since = None;
+            return Some(event);
+        }
+        if self.dropped > 0 {
+            // What was buffered when the input started to be dropped has to go first, bar
+            // an incomplete sequence, which was cut off.
+            self.buf.clear();
+            self.pending_since = None;
+            return Some(InputEvent::Dropped(std::mem::take(&mut self.dropped)));
+        }
+        let deadline = self.sequence_deadline.filter(|_| self.has_pending())?;
+        let since = *self.pending_since.get_or_insert_with(Instant::now);
+        if since.elapsed() < deadline {
+            return None;
+        }
+        self.flush_pending()
+    }
+
+    /// Whether undecoded bytes are buffered, i.e. the start of an incomplete sequence.
+    pub fn has_pending(&self) -> bool {
+        !self.buf.is_empty()
+    }
+
+    /// Gives up on waiting for the rest of a sequence and takes its first byte literally.
+    ///
+    /// This is how a lone press of the Escape key is told apart from the start of an escape
+    /// sequence: if nothing follows within a short time, it was the key.
+    pub fn flush_pending(&mut self) -> Option<InputEvent> {
+        self.pending_since = None;
+        if let Some(event) = self.decode(true) {
+            return Some(event);
+        }
+        let key = match *self.buf.first()? {
+            ESC => Key::Escape,
+            _ => Key::Char(char::REPLACEMENT_CHARACTER),
+        };
+        self.buf.remove(0);
+        Some(InputEvent::Key(KeyEvent::new(key, Modifiers::NONE)))
+    }
+}
+
+fn key(key: Key, modifiers: Modifiers) -> InputEvent {
+    InputEvent::Key(KeyEvent::new(key, modifiers))
+}
+
+/// Parses one event from the start of `bytes`, returning it with the number of bytes it
+/// took up. Returns `None` if `bytes` is empty or only the start of an event.
+fn parse(bytes: &[u8]) -> Option<(InputEvent, usize)> {
+    match *bytes.first()? {
+        ESC => parse_escape(bytes),
+        _ => parse_char(bytes, Modifiers::NONE),
+    }
+}
+
+fn parse_char(bytes: &[u8], modifiers: Modifiers) -> Option<(InputEvent, usize)> {
+    let byte = *bytes.first()?;
+    let (k, modifiers) = match byte {
+        b'\r' | b'\n' => (Key::Enter, modifiers),
+        b'\t' => (Key::Tab, modifiers),
+        0x7f | 0x08 => (Key::Backspace, modifiers),
+        ESC => (Key::Escape, modifiers),
+        0x00 => (Key::Char(' '), modifiers | Modifiers::CTRL),
+        0x01..=0x1a => (
+            Key::Char((b'a' + byte - 1) as char),
+            modifiers | Modifiers::CTRL,
+        ),
+        0x1c..=0x1f => (
+            Key::Char((byte + 0x40) as char),
+            modifiers | Modifiers::CTRL,
+        ),
+        _ => {
+            let (c, len) = decode_utf8(bytes)?;
+            return Some((key(Key::Char(c), modifiers), len));
+        }
+    };
+    Some((key(k, modifiers), 1))
+}
+
+/// Decodes one character, substituting invalid bytes with U+FFFD one at a time.
+fn decode_utf8(bytes: &[u8]) -> Option<(char, usize)> {
+    let len = match bytes[0] {
+        0x00..=0x7f => 1,
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => return Some((char::REPLACEMENT_CHARACTER, 1)),
+    };
+    let available = &bytes[..len.min(bytes.len())];
+    if available[1..].iter().any(|&b| b & 0xc0 != 0x80) {
+        return Some((char::REPLACEMENT_CHARACTER, 1));
+    }
+    if available.len() < len {
+        return None;
+    }
+    match std::str::from_utf8(available) {
+        Ok(s) => s.chars().next().map(|c| (c, len)),
+        Err(_) => Some((char::REPLACEMENT_CHARACTER, 1)),
+    }
+}
+
+fn parse_escape(bytes: &[u8]) -> Option<(InputEvent, usize)> {
+    match *bytes.get(1)? {
+        b'[' => parse_csi(bytes),
+        b'O' => {
+            let k = match *bytes.get(2)? {
+                b'A' => Key::Up,
+                b'B' => Key::Down,
+                b'C' => Key::Right,
+                b'D' => Key::Left,
+                b'H' => Key::Home,
+                b'F' => Key::End,
+                b'P' => Key::F(1),
+                b'Q' => Key::F(2),
+                b'R' => Key::F(3),
+                b'S' => Key::F(4),
+                // The keypad in application mode, as the keys it has in numeric mode.
+                b'M' => Key::Enter,
+                b'I' => Key::Tab,
+                b' ' => Key::Char(' '),
+                b'X' => Key::Char('='),
+                b'j' => Key::Char('*'),
+                b'k' => Key::Char('+'),
+                b'l' => Key::Char(','),
+                b'm' => Key::Char('-'),
+                b'n' => Key::Char('.'),
+                b'o' => Key::Char('/'),
+                digit @ b'p'..=b'y' => Key::Char(char::from(b'0' + (digit - b'p'))),
+                _ => return Some((InputEvent::Unknown(bytes[..3].to_vec()), 3)),
+            };
+            Some((key(k, Modifiers::NONE), 3))
+        }
+        ESC => Some((key(Key::Escape, Modifiers::NONE), 1)),
+        _ => {
+            let (event, len) = parse_char(&bytes[1..], Modifiers::ALT)?;
+            Some((event, len + 1))
+        }
+    }
+}
+
+/// The parameters of a CSI sequence: `;` separated, each with `:` separated sub-parameters.
+struct Params(Vec<Vec<Option<u32>>>);
+
+impl Params {
+    fn parse(bytes: &[u8]) -> Params {
+        Params(
+            bytes
+                .split(|&b| b == b';')
+                .map(|param| {
+                    param
+                        .split(|&b| b == b':')
+                        .map(|sub| std::str::from_utf8(sub).ok()?.parse().ok())
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    fn get(&self, index: usize, sub: usize) -> Option<u32> {
+        *self.0.get(index)?.get(sub)?
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// The modifiers and event kind, from the second parameter.
+    fn modifiers(&self) -> (Modifiers, KeyEventKind) {
+        let modifiers = self.get(1, 0).unwrap_or(1).saturating_sub(1);
+        let kind = match self.get(1, 1) {
+            Some(2) => KeyEventKind::Repeat,
+            Some(3) => KeyEventKind::Release,
+            _ => KeyEventKind::Press,
+        };
+        (Modifiers(modifiers as u8 & Modifiers::ALL), kind)
+    }
+}
+
+fn parse_csi(bytes: &[u8]) -> Option<(InputEvent, usize)> {
+    let mut end = 2;
+    loop {
+        match *bytes.get(end)? {
+            0x40..=0x7e => break,
+            0x20..=0x3f if end < MAX_SEQUENCE_LEN => end += 1,
+            _ => return Some((InputEvent::Unknown(bytes[..end].to_vec()), end)),
+        }
+    }
+    let len = end + 1;
+    let unknown = || Some((InputEvent::Unknown(bytes[..len].to_vec()), len));
+
+    let params = &bytes[2..end];
+    match (params, bytes[end]) {
+        (b"", b'I') => return Some((InputEvent::FocusGained, len)),
+        (b"", b'O') => return Some((InputEvent::FocusLost, len)),
+        _ => {}
+    }
+    if params.first().is_some_and(|b| (b'<'..=b'?').contains(b)) {
+        return unknown();
+    }
+    let params = Params::parse(params);
+    let (modifiers, kind) = params.modifiers();
+
+    let k = match bytes[end] {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'H' => Key::Home,
+        b'F' => Key::End,
+        b'P' => Key::F(1),
+        b'Q' => Key::F(2),
+        // Without modifiers this would be a cursor position report.
+        b'R' if params.len() > 1 && params.get(0, 0) == Some(1) => Key::F(3),
+        b'S' => Key::F(4),
+        b'Z' => {
+            return Some((key(Key::Tab, modifiers | Modifiers::SHIFT), len));
+        }
+        b'~' => match params.get(0, 0) {
+            Some(1) | Some(7) => Key::Home,
+            Some(2) => Key::Insert,
+            Some(3) => Key::Delete,
+            Some(4) | Some(8) => Key::End,
+            Some(5) => Key::PageUp,
+            Some(6) => Key::PageDown,
+            Some(n @ 11..=15) => Key::F((n - 10) as u8),
+            Some(n @ 17..=21) => Key::F((n - 11) as u8),
+            Some(n @ 23..=24) => Key::F((n - 12) as u8),
+            _ => return unknown(),
+        },
+        b'u' => match params.get(0, 0) {
+            Some(9) => Key::Tab,
+            Some(13) => Key::Enter,
+            Some(27) => Key::Escape,
+            Some(127) => Key::Backspace,
+            // The keypad digits.
+            Some(n @ 57399..=57408) => Key::Char((b'0' + (n - 57399) as u8) as char),
+            Some(n @ 57376..=57398) => Key::F((n - 57376 + 13) as u8),
+            Some(n) => match std::char::from_u32(n) {
+                Some(c) => Key::Char(c),
+                None => return unknown(),
+            },
+            None => return unknown(),
+        },
+        _ => return unknown(),
+    };
+
+    let event = KeyEvent {
+        key: k,
+        modifiers,
+        kind,
+    };
+    Some((InputEvent::Key(event), len))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> Vec<InputEvent> {
+        let mut decoder = Decoder::new();
+        decoder.feed(bytes);
+        let mut events = Vec::new();
+        while let Some(event) = decoder.next_event() {
+            events.push(event);
+        }
+        assert!(!decoder.has_pending());
+        events
+    }
+
+    #[test]
+    fn test_plain_and_control() {
+        assert_eq!(
+            decode(b"a\x03\r\x7f\x1bx"),
+            vec![
+                key(Key::Char('a'), Modifiers::NONE),
+                key(Key::Char('c'), Modifiers::CTRL),
+                key(Key::Enter, Modifiers::NONE),
+                key(Key::Backspace, Modifiers::NONE),
+                key(Key::Char('x'), Modifiers::ALT),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_escape_sequences() {
+        assert_eq!(
+            decode(b"\x1b[A\x1bOB\x1b[1;5C\x1b[3~\x1b[15;2~\x1b[Z"),
+            vec![
+                key(Key::Up, Modifiers::NONE),
+                key(Key::Down, Modifiers::NONE),
+                key(Key::Right, Modifiers::CTRL),
+                key(Key::Delete, Modifiers::NONE),
+                key(Key::F(5), Modifiers::SHIFT),
+                key(Key::Tab, Modifiers::SHIFT),
+            ]
+        );
+        assert_eq!(
+            decode(b"\x1b[12;40R"),
+            vec![InputEvent::Unknown(b"\x1b[12;40R".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_application_keypad() {
+        let keys: Vec<_> = "0123456789*+,-./=\n"
+            .chars()
+            .map(|c| match c {
+                '\n' => key(Key::Enter, Modifiers::NONE),
+                c => key(Key::Char(c), Modifiers::NONE),
+            })
+            .collect();
+        assert_eq!(
+            decode(b"\x1bOp\x1bOq\x1bOr\x1bOs\x1bOt\x1bOu\x1bOv\x1bOw\x1bOx\x1bOy\x1bOj\x1bOk\x1bOl\x1bOm\x1bOn\x1bOo\x1bOX\x1bOM"),
+            keys
+        );
+        assert_eq!(
+            decode(b"\x1bOz"),
+            vec![InputEvent::Unknown(b"\x1bOz".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_kitty_keyboard() {
+        let release = KeyEvent {
+            key: Key::Char('a'),
+            modifiers: Modifiers::CTRL | Modifiers::SHIFT,
+            kind: KeyEventKind::Release,
+        };
+        assert_eq!(
+            decode(b"\x1b[27u\x1b[97;6:3u\x1b[1;1:2A"),
+            vec![
+                key(Key::Escape, Modifiers::NONE),
+                InputEvent::Key(release),
+                InputEvent::Key(KeyEvent {
+                    key: Key::Up,
+                    modifiers: Modifiers::NONE,
+                    kind: KeyEventKind::Repeat,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_kitty_lock_states() {
+        // Ctrl with caps lock, then Shift and Meta with num lock.
+        assert_eq!(
+            decode(b"\x1b[99;69u\x1b[97;162u"),
+            vec![
+                key(Key::Char('c'), Modifiers::CTRL),
+                key(Key::Char('a'), Modifiers::SHIFT | Modifiers::META),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_focus_events() {
+        assert_eq!(
+            decode(b"\x1b[O\x1b[I"),
+            vec![InputEvent::FocusLost, InputEvent::FocusGained]
+        );
+    }
+
+    #[test]
+    fn test_bindings() {
+        let mut decoder = Decoder::new();
+        decoder.bind(b"\x1b[11~", Key::F(11));
+        decoder.bind(b"ab", KeyEvent::new(Key::Char('x'), Modifiers::SUPER));
+
+        decoder.feed(b"\x1b[11~a");
+        assert_eq!(decoder.next_event(), Some(key(Key::F(11), Modifiers::NONE)));
+        assert_eq!(decoder.next_event(), None);
+        assert_eq!(
+            decoder.flush_pending(),
+            Some(key(Key::Char('a'), Modifiers::NONE))
+        );
+        decoder.feed(b"ab");
+        assert_eq!(
+            decoder.next_event(),
+            Some(key(Key::Char('x'), Modifiers::SUPER))
+        );
+
+        decoder.unbind(b"\x1b[11~");
+        decoder.feed(b"\x1b[11~");
+        assert_eq!(decoder.next_event(), Some(key(Key::F(1), Modifiers::NONE)));
+    }
+
+    #[test]
+    fn test_incomplete_input() {
+        let mut decoder = Decoder::new();
+        decoder.feed(b"\x1b[1;");
+        assert_eq!(decoder.next_event(), None);
+        decoder.feed(b"3D\xc3");
+        assert_eq!(decoder.next_event(), Some(key(Key::Left, Modifiers::ALT)));
+        assert_eq!(decoder.next_event(), None);
+        decoder.feed(b"\xa9\x1b");
+        assert_eq!(
+            decoder.next_event(),
+            Some(key(Key::Char('é'), Modifiers::NONE))
+        );
+        assert_eq!(decoder.next_event(), None);
+        assert_eq!(
+            decoder.flush_pending(),
+            Some(key(Key::Escape, Modifiers::NONE))
+        );
+        assert!(!decoder.has_pending());
+    }
+
+    #[test]
+    fn test_sequence_deadline() {
+        let mut decoder = Decoder::new().with_sequence_deadline(Duration::from_millis(20));
+        decoder.feed(b"\x1b[1");
+        assert_eq!(decoder.next_event(), None);
+        let deadline = decoder.pending_deadline().unwrap();
+        // More of the sequence doesn't move the deadline.
+        decoder.feed(b";");
+        assert_eq!(decoder.next_event(), None);
+        assert_eq!(decoder.pending_deadline(), Some(deadline));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(
+            decoder.next_event(),
+            Some(key(Key::Escape, Modifiers::NONE))
+        );
+        assert_eq!(decoder.pending_deadline(), None);
+        let rest: Vec<_> = std::iter::from_fn(|| decoder.next_event()).collect();
+        assert_eq!(
+            rest,
+            vec![
+                key(Key::Char('['), Modifiers::NONE),
+                key(Key::Char('1'), Modifiers::NONE),
+                key(Key::Char(';'), Modifiers::NONE),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_throttle() {
+        let mut decoder = Decoder::new().with_throttle(Throttle {
+            max_events: 2,
+            max_buffered: 4,
+        });
+        decoder.feed(b"abc");
+        decoder.feed(b"de\x1b[A");
+        decoder.feed(b"f");
+        let a = |c| Some(key(Key::Char(c), Modifiers::NONE));
+        assert_eq!(decoder.next_event(), a('a'));
+        assert_eq!(decoder.next_event(), a('b'));
+        assert!(decoder.is_throttled());
+        assert_eq!(decoder.next_event(), None);
+        decoder.tick();
+        assert_eq!(decoder.next_event(), a('c'));
+        assert_eq!(decoder.next_event(), a('d'));
+        decoder.tick();
+        assert_eq!(decoder.next_event(), Some(InputEvent::Dropped(5)));
+        assert_eq!(decoder.next_event(), None);
+        assert!(!decoder.is_throttled());
+        decoder.feed(b"g");
+        assert_eq!(decoder.next_event(), a('g'));
+    }
+}
@@ -0,0 +1,154 @@
+//! One code path for Ctrl-C, whether it arrives as `SIGINT` or as a byte.
+//!
+//! With `ISIG` on, the terminal driver turns Ctrl-C into `SIGINT`; in raw mode it arrives
+//! as the byte 0x03 instead. [`on_ctrl_c`] covers both: the signal is caught, and every
+//! [`RawReader`](crate::RawReader) removes the byte from what it reads. Either way, the
+//! callback runs on a thread of its own.
+
+use crate::util::*;
+
+use libc::c_int;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+use std::{mem, ptr, thread};
+
+/// The byte Ctrl-C sends.
+const ETX: u8 = 0x03;
+
+type Handler = Box<dyn FnMut() + Send>;
+
+static HANDLER: Mutex<Option<Handler>> = Mutex::new(None);
+
+/// The write end of the pipe the handler thread waits on, or -1 before `on_ctrl_c`.
+static PIPE_WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_sigint(_: c_int) {
+    notify();
+}
+
+fn notify() {
+    let fd = PIPE_WRITE_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = 0u8;
+        // A full pipe already has a wakeup pending.
+        unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+    }
+}
+
+/// Calls `f` whenever Ctrl-C is pressed, replacing the previous callback.
+///
+/// The first call installs a `SIGINT` handler and starts the thread which runs the
+/// callback. From then on, every `RawReader` takes 0x03 bytes out of what it reads and
+/// hands them to the callback instead, so don't use this if the input is binary data.
+///
+/// ```no_run
+/// use raw_tty::IntoRawMode;
+/// use std::io::{self, stdin, Read};
+///
+/// fn main() -> io::Result<()> {
+///     raw_tty::on_ctrl_c(|| raw_tty::exit(130))?;
+///     let mut stdin = stdin().into_raw_mode()?;
+///     let mut buf = [0; 64];
+///     loop {
+///         let n = stdin.read(&mut buf)?;
+///         print!("{:?}\r\n", &buf[..n]);
+///     }
+/// }
+/// ```
+pub fn on_ctrl_c<F: FnMut() + Send + 'static>(f: F) -> io::Result<()> {
+    let mut handler = HANDLER.lock().unwrap_or_else(|e| e.into_inner());
+    if PIPE_WRITE_FD.load(Ordering::SeqCst) < 0 {
+        let pipe = self_pipe()?;
+        let installed = unsafe {
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = on_sigint as extern "C" fn(c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            convert_to_result(libc::sigaction(libc::SIGINT, &action, ptr::null_mut()))
+        };
+        let spawned = installed.and_then(|_| {
+            thread::Builder::new()
+                .name("raw_tty ctrl-c".into())
+                .spawn(move || run_handler(pipe[0]))
+        });
+        if let Err(e) = spawned {
+            unsafe {
+                libc::close(pipe[0]);
+                libc::close(pipe[1]);
+            }
+            return Err(e);
+        }
+        PIPE_WRITE_FD.store(pipe[1], Ordering::SeqCst);
+    }
+    *handler = Some(Box::new(f));
+    Ok(())
+}
+
+fn run_handler(pipe: RawFd) {
+    let mut buf = [0u8; 64];
+    loop {
+        if wait_readable(pipe, None).is_err() {
+            return;
+        }
+        let n = unsafe { libc::read(pipe, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        for _ in 0..n.max(0) {
+            // Taken out while running, so that the callback can replace itself.
+            let taken = HANDLER.lock().unwrap_or_else(|e| e.into_inner()).take();
+            if let Some(mut f) = taken {
+                f();
+                HANDLER
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .get_or_insert(f);
+            }
+        }
+    }
+}
+
+/// Removes the Ctrl-C bytes from `buf` if [`on_ctrl_c`] was called, notifying the
+/// callback of each. Returns the remaining length.
+pub(crate) fn intercept(buf: &mut [u8]) -> usize {
+    if PIPE_WRITE_FD.load(Ordering::Relaxed) < 0 {
+        return buf.len();
+    }
+    let mut len = 0;
+    for i in 0..buf.len() {
+        if buf[i] == ETX {
+            notify();
+        } else {
+            buf[len] = buf[i];
+            len += 1;
+        }
+    }
+    len
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::IntoRawMode;
+    use std::io::{Read, Write};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_signal_and_byte() -> io::Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        on_ctrl_c(move || sender.send(()).unwrap())?;
+        let timeout = Duration::from_secs(5);
+
+        unsafe { libc::raise(libc::SIGINT) };
+        assert_eq!(receiver.recv_timeout(timeout), Ok(()));
+
+        let (mut master, slave) = openpty()?;
+        let mut tty = slave.into_raw_mode()?;
+        master.write_all(b"a\x03b")?;
+        let mut buf = [0; 2];
+        tty.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"ab");
+        assert_eq!(receiver.recv_timeout(timeout), Ok(()));
+        Ok(())
+    }
+}
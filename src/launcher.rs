@@ -0,0 +1,160 @@
+//! Taking over fds which a launcher passed to the process.
+//!
+//! Supervisors like systemd hand the processes they start already open fds, following the
+//! `LISTEN_FDS` protocol of socket activation: the fds are numbered from 3 on,
+//! `LISTEN_FDS` says how many there are, `LISTEN_PID` which process they are meant for and
+//! `LISTEN_FDNAMES` optionally names them. Container runtimes attaching a console to a
+//! program can use the same protocol for the tty, which [`listen_tty`] then picks up.
+//!
+//! ```no_run
+//! use raw_tty::launcher;
+//! use std::io::{self, Write};
+//!
+//! fn main() -> io::Result<()> {
+//!     let mut console = match launcher::listen_tty(Some("console"))? {
+//!         Some(console) => console,
+//!         None => return Err(io::Error::other("no console was passed")),
+//!     };
+//!     console.set_raw_mode()?;
+//!     console.write_all(b"attached\r\n")
+//! }
+//! ```
+
+use crate::TtyWithGuard;
+
+use std::env;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+/// The first fd passed by a launcher.
+pub const LISTEN_FDS_START: RawFd = 3;
+
+/// An fd passed by a launcher, see [`listen_fds`].
+#[derive(Debug)]
+pub struct ListenFd {
+    pub file: File,
+    /// The name from `LISTEN_FDNAMES`, if the launcher gave one.
+    pub name: Option<String>,
+}
+
+impl ListenFd {
+    pub fn is_tty(&self) -> bool {
+        unsafe { libc::isatty(self.file.as_raw_fd()) == 1 }
+    }
+
+    /// Guards the fd, which fails if it isn't a tty.
+    pub fn into_tty(self) -> io::Result<TtyWithGuard<File>> {
+        TtyWithGuard::new(self.file)
+    }
+}
+
+/// Takes ownership of the fds passed to this process, like `sd_listen_fds`.
+///
+/// Returns nothing if the variables are missing or meant for another process, e.g. the
+/// parent which started this one. The variables are removed, so that a second call and
+/// child processes don't take the same fds again, and the fds are marked close-on-exec.
+pub fn listen_fds() -> io::Result<Vec<ListenFd>> {
+    const VARS: [&str; 3] = ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"];
+    let vars = VARS.map(env::var);
+    for var in VARS {
+        env::remove_var(var);
+    }
+    let [pid, fds, names] = vars;
+    let (pid, fds) = match (pid, fds) {
+        (Ok(pid), Ok(fds)) => (pid, fds),
+        _ => return Ok(Vec::new()),
+    };
+    let names = names.ok();
+    let passed = parse(&pid, &fds, names.as_deref(), std::process::id())?;
+
+    let mut listen_fds = Vec::with_capacity(passed.len());
+    for (fd, name) in passed {
+        crate::util::convert_to_result(unsafe {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC)
+        })?;
+        listen_fds.push(ListenFd {
+            file: unsafe { File::from_raw_fd(fd) },
+            name,
+        });
+    }
+    Ok(listen_fds)
+}
+
+/// The first passed fd which is a tty, named `name` if given, ready to be switched to raw
+/// mode. All other passed fds are closed, use [`listen_fds`] to keep them.
+pub fn listen_tty(name: Option<&str>) -> io::Result<Option<TtyWithGuard<File>>> {
+    let found = listen_fds()?
+        .into_iter()
+        .find(|fd| fd.is_tty() && name.is_none_or(|name| fd.name.as_deref() == Some(name)));
+    found.map(ListenFd::into_tty).transpose()
+}
+
+/// Checks the values of `LISTEN_PID` and `LISTEN_FDS` and pairs the passed fds with their
+/// names.
+fn parse(
+    pid: &str,
+    fds: &str,
+    names: Option<&str>,
+    own_pid: u32,
+) -> io::Result<Vec<(RawFd, Option<String>)>> {
+    let invalid = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid {} from the launcher", what),
+        )
+    };
+    if pid.parse::<u32>().map_err(|_| invalid("LISTEN_PID"))? != own_pid {
+        return Ok(Vec::new());
+    }
+    let count: RawFd = fds.parse().map_err(|_| invalid("LISTEN_FDS"))?;
+    if !(0..=RawFd::MAX - LISTEN_FDS_START).contains(&count) {
+        return Err(invalid("LISTEN_FDS"));
+    }
+    let mut names = names.into_iter().flat_map(|names| names.split(':'));
+    Ok((LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            let name = names.next().filter(|name| !name.is_empty());
+            (fd, name.map(str::to_owned))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util;
+    use std::os::unix::io::IntoRawFd;
+
+    #[test]
+    fn test_parse() -> io::Result<()> {
+        assert_eq!(
+            parse("42", "2", Some("console:"), 42)?,
+            [(3, Some("console".to_owned())), (4, None)]
+        );
+        assert_eq!(parse("42", "1", None, 42)?, [(3, None)]);
+        assert!(parse("41", "1", None, 42)?.is_empty());
+        assert_eq!(
+            parse("42", "-1", None, 42).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert!(parse("x", "1", None, 42).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_raw_fd_owned() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let mut tty = unsafe { TtyWithGuard::from_raw_fd_owned(slave.into_raw_fd())? };
+        tty.set_raw_mode()?;
+
+        let null = File::open("/dev/null")?;
+        let not_a_tty = ListenFd {
+            file: null,
+            name: None,
+        };
+        assert!(!not_a_tty.is_tty());
+        assert!(not_a_tty.into_tty().is_err());
+        Ok(())
+    }
+}
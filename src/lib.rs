@@ -76,9 +76,69 @@
 //! }
 //!
 //! ```
+//!
+//! # Async runtimes
+//!
+//! `AsyncTty`, with the `async` feature, reads and writes a guarded tty from async code
+//! under any runtime. The guards work the same there: restoring happens synchronously in
+//! `Drop`, on whichever thread drops the guard. To make sure the terminal is restored on
+//! the main thread and not on a worker which may never run again after the runtime shut
+//! down, create the guard outside the runtime and take it back with
+//! `AsyncTty::into_blocking` before the runtime goes away, see the example there.
+//!
+//! # Features
+//!
+//! Without features the crate is the guards and the termios helpers: [`TtyModeGuard`],
+//! [`TtyWithGuard`], [`RawReader`] and its [`ReadCanceller`], the partial guards like
+//! [`InputGuard`], [`CleanupOrchestrator`], [`TtyLock`], finding the tty among the standard
+//! streams with [`first_tty`], the typed flags, [`Speed`] and [`DesiredMode`], the
+//! allocation free [`bare`] functions and the helpers in [`testing`]. Each subsystem on
+//! top of them has to be enabled:
+//!
+//! - `signals`: `events` and `on_ctrl_c`.
+//! - `decoder`: the key decoder in `input`, and `prompt`, `line`, `utf8` and `session`,
+//!   which build on it.
+//! - `queries`: asking the terminal about itself in `query`, with `decoder`.
+//! - `pty`: creating ptys in `pty` and proxying a terminal through one in `proxy` and
+//!   `record`, with `signals`.
+//! - `writer`: `RawWriter` and its wrap tracking, and ringing the bell.
+//! - `event-loop`: waiting on several fds with `ReadSet`, reading on a thread of its own
+//!   with `ChannelReader`, and the frame clock in `EventLoop`.
+//! - `diagnostics`: snapshots of the terminal environment in `diagnostics`, and coping
+//!   with `/dev/console` in `console`.
+//! - `launcher`: taking over fds passed by a launcher, in `launcher`.
+//! - `async`: `AsyncTty`, for async code under any runtime.
+//! - `full`: all of the above.
+//! - `termion-compat`: termion's `raw` API in `compat::termion`, for switching over from
+//!   termion without changing the code first.
+//!
+//! # Unsafe code
+//!
+//! The `unsafe` in this crate is confined to calling libc, in these modules:
+//!
+//! - the crate root: the termios, ioctl, poll and signal calls of the guards.
+//! - `syscall`: `tcgetpgrp` and `getpgrp` for deciding job control.
+//! - `diagnostics`: `ttyname_r`, `isatty` and the process group calls.
+//! - `events` and `interrupt`: installing the signal handlers and writing their pipes.
+//! - `launcher`, `lock` and `testing`: `isatty`, `flock` and `ttyname_r`.
+//! - `pty`: opening and spawning ptys, and the `SIGCHLD` handler.
+//! - `speed`: `cfgetispeed` and friends.
+//! - `utmp`: the `utmpx` entries.
+//!
+//! The `raw_tty_bare` crate, re-exported as [`bare`], wraps the termios syscalls the guards
+//! are built on. `capi` has no `unsafe` blocks, but counts as unsafe code because of its
+//! `#[no_mangle]` exports. Every other module, the decoder, prompts, line editor,
+//! sessions, queries, recording and the writers, is safe code. Enable `sys-unsafe-audited`
+//! to have the compiler check that: those modules then forbid `unsafe_code`, so a review
+//! only has to cover the modules listed here. [`Termios::default`] is the safe way to get
+//! a cleared termios.
 
 mod util {
     use std::io;
+    use std::os::unix::io::RawFd;
+    #[cfg(any(test, feature = "signals", feature = "decoder"))]
+    use std::time::Duration;
+    use std::time::Instant;
 
     pub trait IsMinusOne {
         fn is_minus_one(&self) -> bool;
@@ -101,44 +161,272 @@ mod util {
             Ok(t)
         }
     }
+
+    /// Writes all of `buf` to `fd`, retrying on interrupts.
+    pub fn write_all_fd(fd: RawFd, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let n = unsafe { libc::write(fd, buf.as_ptr() as *const libc::c_void, buf.len()) };
+            match convert_to_result(n) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => buf = &buf[n as usize..],
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates a close-on-exec, nonblocking pipe, for waking up from signal handlers.
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    ))]
+    #[cfg(feature = "signals")]
+    pub fn self_pipe() -> io::Result<[RawFd; 2]> {
+        let mut pipe = [-1; 2];
+        convert_to_result(unsafe {
+            libc::pipe2(pipe.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK)
+        })?;
+        Ok(pipe)
+    }
+
+    /// Creates a close-on-exec, nonblocking pipe, for waking up from signal handlers.
+    ///
+    /// Without `pipe2` there is a short window in which another thread could fork and exec
+    /// with the pipe inherited.
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    )))]
+    #[cfg(feature = "signals")]
+    pub fn self_pipe() -> io::Result<[RawFd; 2]> {
+        let mut pipe = [-1; 2];
+        convert_to_result(unsafe { libc::pipe(pipe.as_mut_ptr()) })?;
+        for &fd in pipe.iter() {
+            let result = unsafe {
+                convert_to_result(libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC)).and_then(|_| {
+                    let flags = convert_to_result(libc::fcntl(fd, libc::F_GETFL))?;
+                    convert_to_result(libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK))
+                })
+            };
+            if let Err(e) = result {
+                unsafe {
+                    libc::close(pipe[0]);
+                    libc::close(pipe[1]);
+                }
+                return Err(e);
+            }
+        }
+        Ok(pipe)
+    }
+
+    /// `fstat(2)` on a borrowed fd.
+    pub fn fstat(fd: RawFd) -> io::Result<libc::stat> {
+        let mut stat = std::mem::MaybeUninit::uninit();
+        convert_to_result(unsafe { libc::fstat(fd, stat.as_mut_ptr()) })?;
+        Ok(unsafe { stat.assume_init() })
+    }
+
+    /// `poll(2)` until `deadline`, or forever if it is `None`, retrying on interrupts.
+    /// Returns the number of ready fds.
+    pub fn poll(fds: &mut [libc::pollfd], deadline: Option<Instant>) -> io::Result<usize> {
+        loop {
+            let ms = match deadline {
+                None => -1,
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    // Round up, so that a sub-millisecond timeout doesn't become a busy loop.
+                    left.as_nanos()
+                        .div_ceil(1_000_000)
+                        .min(libc::c_int::MAX as u128) as libc::c_int
+                }
+            };
+            let n = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, ms) };
+            match convert_to_result(n) {
+                Ok(n) => return Ok(n as usize),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Waits until `fd` is readable or `timeout` passes, returning whether it is readable.
+    /// Waits forever if `timeout` is `None`.
+    #[cfg(any(test, feature = "signals", feature = "decoder"))]
+    pub fn wait_readable(fd: RawFd, timeout: Option<Duration>) -> io::Result<bool> {
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        Ok(poll(std::slice::from_mut(&mut pollfd), deadline)? > 0)
+    }
+
+    /// Serializes tests which need the process wide `events::Events`.
+    #[cfg(all(test, feature = "signals"))]
+    pub static EVENTS_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    /// Serializes tests which change the mode of the controlling terminal.
+    #[cfg(test)]
+    pub static TTY_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    #[cfg(test)]
+    pub fn openpty() -> io::Result<(std::fs::File, std::fs::File)> {
+        use std::os::unix::io::FromRawFd;
+        use std::{fs::File, ptr};
+
+        let (mut master, mut slave) = (-1, -1);
+        convert_to_result(unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                ptr::null_mut(),
+                ptr::null(),
+                ptr::null(),
+            )
+        })?;
+        unsafe { Ok((File::from_raw_fd(master), File::from_raw_fd(slave))) }
+    }
 }
 
 mod attr {
     #[cfg(unix)]
     pub mod unix {
-        use crate::util::*;
+        use crate::bare;
 
-        use libc::c_int;
+        use std::io;
+        use std::ops::{Deref, DerefMut};
+        use std::os::unix::io::RawFd;
 
-        /// Export of libc::termios
-        pub type Termios = libc::termios;
+        /// The mode of a terminal, as `tcgetattr` returns it.
+        ///
+        /// This wraps the `termios` of the platform, so that the public API of this crate
+        /// doesn't change with libc and other platforms can have their own representation.
+        /// The fields of `libc::termios` are available through `Deref`, so closures passed
+        /// to [`modify_mode`](crate::TtyModeGuard::modify_mode) can keep writing
+        /// `ios.c_lflag &= !libc::ECHO`, or use typed flags like
+        /// [`LocalFlags`](crate::LocalFlags). Convert with [`Termios::from_libc`] and
+        /// [`Termios::into_libc`] where a `libc::termios` is needed.
+        #[derive(Clone, Copy, Default)]
+        #[repr(transparent)]
+        pub struct Termios(bare::Termios);
 
-        use std::os::unix::io::RawFd;
-        use std::{io, mem};
+        impl Termios {
+            /// Wraps a `libc::termios`.
+            pub fn from_libc(termios: libc::termios) -> Termios {
+                Termios(bare::Termios::from_libc(termios))
+            }
 
-        pub fn get_terminal_attr(fd: RawFd) -> io::Result<Termios> {
-            extern "C" {
-                pub fn tcgetattr(fd: c_int, termptr: *mut Termios) -> c_int;
+            /// The `libc::termios`, e.g. for passing it to libc directly.
+            pub fn into_libc(self) -> libc::termios {
+                self.0.into_libc()
             }
-            unsafe {
-                let mut termios = mem::zeroed();
-                convert_to_result(tcgetattr(fd, &mut termios))?;
-                Ok(termios)
+
+            /// The `libc::termios`, borrowed.
+            pub fn as_libc(&self) -> &libc::termios {
+                self.0.as_libc()
+            }
+
+            /// The `libc::termios`, borrowed mutably, e.g. for `libc::cfsetospeed`.
+            pub fn as_libc_mut(&mut self) -> &mut libc::termios {
+                self.0.as_libc_mut()
+            }
+
+            /// The termios of [`bare`](crate::bare), borrowed.
+            pub fn as_bare(&self) -> &bare::Termios {
+                &self.0
+            }
+
+            /// The termios of [`bare`](crate::bare), borrowed mutably.
+            pub fn as_bare_mut(&mut self) -> &mut bare::Termios {
+                &mut self.0
             }
         }
 
-        pub fn set_terminal_attr(fd: RawFd, termios: &Termios) -> io::Result<()> {
-            extern "C" {
-                pub fn tcsetattr(fd: c_int, opt: c_int, termptr: *const Termios) -> c_int;
+        impl Deref for Termios {
+            type Target = libc::termios;
+
+            fn deref(&self) -> &libc::termios {
+                &self.0
             }
-            convert_to_result(unsafe { tcsetattr(fd, 0, termios) }).and(Ok(()))
         }
 
-        pub fn raw_terminal_attr(termios: &mut Termios) {
-            extern "C" {
-                pub fn cfmakeraw(termptr: *mut Termios);
+        impl DerefMut for Termios {
+            fn deref_mut(&mut self) -> &mut libc::termios {
+                &mut self.0
+            }
+        }
+
+        impl From<libc::termios> for Termios {
+            fn from(termios: libc::termios) -> Termios {
+                Termios::from_libc(termios)
+            }
+        }
+
+        impl From<Termios> for libc::termios {
+            fn from(termios: Termios) -> libc::termios {
+                termios.into_libc()
+            }
+        }
+
+        impl From<bare::Termios> for Termios {
+            fn from(termios: bare::Termios) -> Termios {
+                Termios(termios)
+            }
+        }
+
+        impl From<Termios> for bare::Termios {
+            fn from(termios: Termios) -> bare::Termios {
+                termios.0
+            }
+        }
+
+        impl std::fmt::Debug for Termios {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                self.0.fmt(f)
             }
-            unsafe { cfmakeraw(termios) }
+        }
+
+        fn from_errno(e: bare::Errno) -> io::Error {
+            io::Error::from_raw_os_error(e.0)
+        }
+
+        pub fn get_terminal_attr(fd: RawFd) -> io::Result<Termios> {
+            crate::syscall::with(fd, |sys| sys.get_attr(fd))
+        }
+
+        pub fn set_terminal_attr(fd: RawFd, termios: &Termios) -> io::Result<()> {
+            crate::syscall::with(fd, |sys| sys.set_attr(fd, termios))
+        }
+
+        pub fn get_window_size(fd: RawFd) -> io::Result<crate::WindowSize> {
+            bare::get_window_size(fd).map_err(from_errno)
+        }
+
+        #[cfg(feature = "pty")]
+        pub fn set_window_size(fd: RawFd, size: &crate::WindowSize) -> io::Result<()> {
+            bare::set_window_size(fd, size).map_err(from_errno)
+        }
+
+        pub fn raw_terminal_attr(termios: &mut Termios) {
+            bare::make_raw(termios.as_bare_mut())
+        }
+
+        pub fn merged_raw_terminal_attr(termios: &mut Termios) {
+            bare::make_raw_merged(termios.as_bare_mut())
+        }
+
+        pub fn eight_bit_clean_terminal_attr(termios: &mut Termios) {
+            bare::make_8bit_clean(termios.as_bare_mut())
         }
     }
 
@@ -146,183 +434,2565 @@ mod attr {
     pub use unix::*;
 }
 
-/// Export of libc::termios
+#[cfg(feature = "async")]
+mod async_tty;
+#[cfg(feature = "writer")]
+mod bell;
+mod cancel;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "event-loop")]
+mod channel;
+mod cleanup;
+#[cfg(feature = "termion-compat")]
+pub mod compat;
+#[cfg(feature = "diagnostics")]
+pub mod console;
+mod desired;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+#[cfg(feature = "event-loop")]
+mod event_loop;
+#[cfg(feature = "signals")]
+pub mod events;
+mod flags;
+#[cfg(feature = "decoder")]
+pub mod input;
+#[cfg(feature = "signals")]
+mod interrupt;
+#[cfg(feature = "launcher")]
+pub mod launcher;
+#[cfg(feature = "decoder")]
+pub mod line;
+mod lock;
+mod partial;
+#[cfg(feature = "decoder")]
+pub mod prompt;
+#[cfg(feature = "pty")]
+pub mod proxy;
+#[cfg(feature = "pty")]
+pub mod pty;
+#[cfg(feature = "queries")]
+pub mod query;
+#[cfg(feature = "event-loop")]
+mod read_set;
+#[cfg(feature = "pty")]
+pub mod record;
+#[cfg(feature = "decoder")]
+pub mod session;
+mod speed;
+mod stdio;
+mod syscall;
+#[cfg(feature = "terminfo")]
+pub mod terminfo;
+pub mod testing;
+#[cfg(feature = "decoder")]
+pub mod utf8;
+#[cfg(all(feature = "utmp", target_os = "linux"))]
+pub mod utmp;
+#[cfg(feature = "writer")]
+mod writer;
+
+#[cfg(feature = "async")]
+pub use async_tty::AsyncTty;
 pub use attr::Termios;
+pub use bare::WindowSize;
+#[cfg(feature = "writer")]
+pub use bell::BellKind;
+pub use cancel::{ReadCancelled, ReadCanceller};
+#[cfg(feature = "event-loop")]
+pub use channel::ChannelReader;
+pub use cleanup::{CleanupOrchestrator, Registered};
+pub use desired::DesiredMode;
+#[cfg(feature = "event-loop")]
+pub use event_loop::{EventLoop, Tick};
+pub use flags::{ControlFlags, Flags, InputFlags, LocalFlags, OutputFlags};
+/// The termios core, on nothing but `core` and `libc`, see the `raw_tty_bare` crate.
+pub use raw_tty_bare as bare;
+
+#[cfg(feature = "signals")]
+pub use interrupt::on_ctrl_c;
+pub use lock::TtyLock;
+pub use partial::{InputGuard, OutputGuard, OutputTweaks};
+#[cfg(feature = "pty")]
+pub use proxy::{proxy, ExitReason};
+#[cfg(feature = "event-loop")]
+pub use read_set::{ReadSet, Readable};
+pub use speed::Speed;
+pub use stdio::{first_tty, guard_first_tty, StdStream, StdTty};
+#[cfg(feature = "writer")]
+pub use writer::{NoAutoWrap, RawWriter, WrapTracker, DEFAULT_WRITE_BUFFER};
 
-use attr::{get_terminal_attr, raw_terminal_attr, set_terminal_attr};
-use derive_more::{Deref, DerefMut};
+use attr::{
+    eight_bit_clean_terminal_attr, get_terminal_attr, merged_raw_terminal_attr, raw_terminal_attr,
+    set_terminal_attr,
+};
+use std::cell::Cell;
 use std::io;
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
 
 /// A terminal restorer, which keeps the previous state of the terminal, and restores it, when
 /// dropped.
 ///
 /// Restoring will entirely bring back the old TTY state.
 pub struct TtyModeGuard {
-    ios: Termios,
     fd: RawFd,
+    /// Shared with [`LIVE_GUARDS`], so that [`exit`] can restore the terminal too.
+    restore: Arc<Mutex<Restore>>,
+    stats: Cell<GuardStats>,
+    /// The mode applied last, which `reattach` applies again.
+    applied: Cell<Option<Termios>>,
+    /// The mode which was applied when yielding with `yield_to_external`.
+    yielded: Option<Termios>,
+    strict: bool,
+    raw_strategy: RawStrategy,
+    error_policy: ErrorPolicy,
+    /// The last [`IOCTL_HISTORY_LEN`] calls of `ioctl`, oldest first.
+    ioctls: Vec<IoctlRecord>,
 }
 
-impl Drop for TtyModeGuard {
-    fn drop(&mut self) {
-        set_terminal_attr(self.fd, &self.ios).unwrap();
-    }
+/// Everything a [`TtyModeGuard`] needs for restoring the terminal.
+struct Restore {
+    fd: RawFd,
+    ios: Termios,
+    /// The [`fingerprint`] of `ios`, checked before it is used.
+    fingerprint: u64,
+    hooks: Vec<(HookOrder, RestoreHook)>,
+    reset_sequence: Option<Vec<u8>>,
+    /// The file status flags from before the first `set_nonblocking`.
+    status_flags: Option<libc::c_int>,
+    history: Option<History>,
 }
 
-impl TtyModeGuard {
-    pub fn new(fd: RawFd) -> io::Result<TtyModeGuard> {
-        let ios = get_terminal_attr(fd)?;
+impl Restore {
+    /// The saved termios, after making sure it wasn't corrupted since it was saved.
+    fn saved(&self) -> io::Result<Termios> {
+        if fingerprint(&self.ios) != self.fingerprint {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the saved termios is corrupt, refusing to apply it",
+            ));
+        }
+        Ok(self.ios)
+    }
 
-        Ok(Self { ios, fd })
+    fn run_hooks(&mut self, order: HookOrder) {
+        for (_, hook) in self.hooks.iter_mut().filter(|(o, _)| *o == order) {
+            hook();
+        }
     }
 
-    /// Switch to raw mode.
-    pub fn set_raw_mode(&mut self) -> io::Result<()> {
-        let mut ios = self.ios;
+    /// Runs the hooks, writes the reset sequence and restores the file status flags around
+    /// applying the saved termios with `set_attr`, recording it in the history as done by
+    /// `what`.
+    fn finish(
+        &mut self,
+        what: &'static str,
+        set_attr: impl FnOnce(&Termios) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.run_hooks(HookOrder::BeforeRestore);
+        if let Some(ref sequence) = self.reset_sequence {
+            let _ = util::write_all_fd(self.fd, sequence);
+        }
+        let result = self.saved().and_then(|ios| {
+            set_attr(&ios)?;
+            if let Some(ref mut history) = self.history {
+                history.record(what, &ios);
+            }
+            Ok(())
+        });
+        if let Some(flags) = self.status_flags {
+            unsafe { libc::fcntl(self.fd, libc::F_SETFL, flags) };
+        }
+        self.run_hooks(HookOrder::AfterRestore);
+        result
+    }
+}
 
-        raw_terminal_attr(&mut ios);
+/// The restore state of every live guard, in the order they were created.
+static LIVE_GUARDS: Mutex<Vec<Arc<Mutex<Restore>>>> = Mutex::new(Vec::new());
 
-        set_terminal_attr(self.fd, &ios)?;
-        Ok(())
+/// Restores the terminal for every live guard, newest first, and exits the process with
+/// `code`.
+///
+/// `std::process::exit` doesn't run destructors, so without this a program which exits
+/// from deep inside its raw mode code leaves the terminal raw. The restore hooks run as
+/// if the guards were dropped. Guards which are being restored by another thread at the
+/// same time are skipped.
+pub fn exit(code: i32) -> ! {
+    let live = std::mem::take(&mut *LIVE_GUARDS.lock().unwrap_or_else(|e| e.into_inner()));
+    for restore in live.iter().rev() {
+        if let Ok(mut restore) = restore.try_lock() {
+            let fd = restore.fd;
+            let _ = restore.finish("exit", |ios| set_terminal_attr(fd, ios));
+        }
     }
+    std::process::exit(code)
+}
 
-    /// Creates a copy of the saved termios and passes it to `f`
-    /// which should return the new termios to apply.
-    ///
-    /// This method can be used to restore the saved ios afterwards
-    /// by using the identity function.
-    pub fn modify_mode<F>(&mut self, f: F) -> io::Result<()>
-    where
-        F: FnOnce(Termios) -> Termios,
-    {
-        let ios = f(self.ios);
-        set_terminal_attr(self.fd, &ios)?;
-        Ok(())
+/// The number of live guards for `fd`.
+#[cfg(feature = "capi")]
+fn live_guards_on(fd: RawFd) -> usize {
+    let live = LIVE_GUARDS.lock().unwrap_or_else(|e| e.into_inner());
+    live.iter()
+        .filter(|restore| restore.lock().unwrap_or_else(|e| e.into_inner()).fd == fd)
+        .count()
+}
+
+/// The device number of the tty `fd` refers to, the same for all its fds.
+fn tty_device(fd: RawFd) -> io::Result<libc::dev_t> {
+    Ok(util::fstat(fd)?.st_rdev)
+}
+
+/// A cheap FNV-1a hash of the parts of `ios` that are restored.
+fn fingerprint(ios: &Termios) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let flags = [ios.c_iflag, ios.c_oflag, ios.c_cflag, ios.c_lflag];
+    let bytes = flags
+        .iter()
+        .flat_map(|flag| (*flag as u64).to_le_bytes())
+        .chain(ios.c_cc.iter().copied());
+    for byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
     }
+    hash
 }
 
-use std::io::Read;
-use std::ops;
+/// How often a [`TtyModeGuard`] touched the terminal driver, see [`TtyModeGuard::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GuardStats {
+    /// Calls of `tcgetattr`.
+    pub get_attr_calls: u64,
+    /// Calls of `tcsetattr`, i.e. mode switches.
+    pub set_attr_calls: u64,
+    /// Times the process was suspended through the guard.
+    pub suspends: u64,
+    /// Times the saved mode was restored.
+    pub restores: u64,
+    /// Calls of [`TtyModeGuard::ioctl`].
+    pub ioctl_calls: u64,
+}
 
-/// Wraps a file descriptor for a TTY with a guard which saves
-/// the terminal mode on creation and restores it on drop.
-pub struct TtyWithGuard<T: AsRawFd> {
-    guard: TtyModeGuard,
-    inner: T,
+/// A call of [`TtyModeGuard::ioctl`], see [`TtyModeGuard::ioctl_history`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoctlRecord {
+    /// The request as passed to [`TtyModeGuard::ioctl`].
+    pub request: u64,
+    /// The `errno` if the call failed.
+    pub errno: Option<i32>,
 }
 
-impl<R: AsRawFd> ops::Deref for TtyWithGuard<R> {
-    type Target = R;
+/// A change of the mode made by a guard, see [`TtyModeGuard::enable_history`].
+#[derive(Debug, Clone, Copy)]
+pub struct Transition {
+    /// When the change was made, counted from the creation of the guard.
+    pub at: Duration,
+    /// What made the change: the name of the guard method, or `"drop"`, `"exit"` or
+    /// `"query"`.
+    pub what: &'static str,
+    pub before: Termios,
+    pub after: Termios,
+}
 
-    #[inline]
-    fn deref(&self) -> &R {
-        &self.inner
+impl std::fmt::Display for Transition {
+    /// The time, the cause and the flags which were cleared and set, e.g.
+    /// `0.012s set_raw_mode: lflag -0x8a3b, iflag -0x500`.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:.3}s {}:", self.at.as_secs_f64(), self.what)?;
+        let (before, after) = (&self.before, &self.after);
+        let mut unchanged = true;
+        for (name, before, after) in [
+            ("iflag", before.c_iflag, after.c_iflag),
+            ("oflag", before.c_oflag, after.c_oflag),
+            ("cflag", before.c_cflag, after.c_cflag),
+            ("lflag", before.c_lflag, after.c_lflag),
+        ] {
+            let (cleared, set) = (before & !after, after & !before);
+            if cleared != 0 || set != 0 {
+                write!(f, "{} {}", if unchanged { "" } else { "," }, name)?;
+                if cleared != 0 {
+                    write!(f, " -{:#x}", cleared)?;
+                }
+                if set != 0 {
+                    write!(f, " +{:#x}", set)?;
+                }
+                unchanged = false;
+            }
+        }
+        for (i, (b, a)) in before.c_cc.iter().zip(after.c_cc.iter()).enumerate() {
+            if b != a {
+                write!(
+                    f,
+                    "{} cc[{}] {}->{}",
+                    if unchanged { "" } else { "," },
+                    i,
+                    b,
+                    a
+                )?;
+                unchanged = false;
+            }
+        }
+        if unchanged {
+            f.write_str(" no change")?;
+        }
+        Ok(())
     }
 }
 
-impl<R: AsRawFd> ops::DerefMut for TtyWithGuard<R> {
-    #[inline]
-    fn deref_mut(&mut self) -> &mut R {
-        &mut self.inner
-    }
+/// The last transitions of a guard, oldest first.
+struct History {
+    capacity: usize,
+    transitions: std::collections::VecDeque<Transition>,
+    created: Instant,
+    /// The mode applied last, the `before` of the next transition.
+    current: Termios,
 }
 
-impl<T: AsRawFd> TtyWithGuard<T> {
-    pub fn new(tty: T) -> io::Result<TtyWithGuard<T>> {
-        Ok(Self {
-            guard: TtyModeGuard::new(tty.as_raw_fd())?,
-            inner: tty,
-        })
+impl History {
+    fn record(&mut self, what: &'static str, ios: &Termios) {
+        if self.transitions.len() == self.capacity {
+            self.transitions.pop_front();
+        }
+        self.transitions.push_back(Transition {
+            at: self.created.elapsed(),
+            what,
+            before: self.current,
+            after: *ios,
+        });
+        self.current = *ios;
     }
+}
 
-    /// Creates a copy of the saved termios and passes it to `f`
-    /// which should return the new termios to apply.
-    ///
-    /// This method can be used to restore the saved ios afterwards
-    /// by using the identity function.
-    pub fn modify_mode<F>(&mut self, f: F) -> io::Result<()>
-    where
-        F: FnOnce(Termios) -> Termios,
-    {
-        self.guard.modify_mode(f)
-    }
+/// Makes panics print the [history](TtyModeGuard::enable_history) of every live guard
+/// before the panic message, so that bug reports about a broken terminal show what was
+/// done to it. Guards without a history are skipped.
+///
+/// The previously installed hook still runs afterwards.
+pub fn install_history_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if let Ok(live) = LIVE_GUARDS.try_lock() {
+            for restore in live.iter() {
+                let restore = match restore.try_lock() {
+                    Ok(restore) => restore,
+                    Err(_) => continue,
+                };
+                if let Some(ref history) = restore.history {
+                    eprint!("raw_tty: mode history of fd {}:\r\n", restore.fd);
+                    for transition in &history.transitions {
+                        eprint!("  {}\r\n", transition);
+                    }
+                }
+            }
+        }
+        previous(info);
+    }));
+}
 
-    /// Switch to raw mode.
-    pub fn set_raw_mode(&mut self) -> io::Result<()> {
-        self.guard.set_raw_mode()
-    }
+/// How many calls [`TtyModeGuard::ioctl_history`] remembers.
+pub const IOCTL_HISTORY_LEN: usize = 16;
+
+/// How a tty relates to the job control of the process, see [`TtyModeGuard::job_control`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobControl {
+    /// The tty isn't the controlling terminal of the process, e.g. the serial console of an
+    /// init-like program on an embedded target, or a pty slave opened by the process itself.
+    /// There is no foreground process group and no job control signals are sent; raw mode
+    /// works all the same.
+    Unavailable,
+    /// The process is in the foreground process group of the tty.
+    Foreground,
+    /// The process is in a background process group of the tty. Changing the mode sends it
+    /// `SIGTTOU`, which stops it unless the signal is ignored or blocked.
+    Background,
 }
 
-/// Types which can save a termios.
-pub trait GuardMode: AsRawFd + Sized {
-    fn guard_mode(self) -> io::Result<TtyWithGuard<Self>>;
+/// Why a [strict](TtyWithGuardBuilder::strict) guard refused to change the mode.
+///
+/// The error's kind is `Other`, use [`StrictViolation::find`] to get at it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrictViolation {
+    /// The process is in a background process group of the tty, so changing the mode
+    /// would stop it with `SIGTTOU`.
+    Background,
+    /// Another guard is live on the same tty, possibly through another fd, so the two
+    /// restore over each other.
+    NestedGuard,
+    /// The tty has no window size, e.g. a pty whose owner never set one.
+    NoWindowSize,
+    /// The terminal didn't take the whole mode: `tcsetattr` succeeds if any of it applied.
+    ModeNotApplied,
 }
 
-impl<T: AsRawFd> GuardMode for T {
-    fn guard_mode(self) -> io::Result<TtyWithGuard<T>> {
-        TtyWithGuard::new(self)
+impl StrictViolation {
+    /// The `StrictViolation` inside `error`, if that is what it is.
+    pub fn find(error: &io::Error) -> Option<&StrictViolation> {
+        error.get_ref()?.downcast_ref()
     }
 }
 
-impl<R: io::Read + AsRawFd> io::Read for TtyWithGuard<R> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner.read(buf)
+impl From<StrictViolation> for io::Error {
+    fn from(violation: StrictViolation) -> io::Error {
+        io::Error::other(violation)
     }
 }
 
-impl<R: io::Write + AsRawFd> io::Write for TtyWithGuard<R> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.inner.write(buf)
+impl std::fmt::Display for StrictViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            StrictViolation::Background => "the process is in the background of the tty",
+            StrictViolation::NestedGuard => "another guard is live on the same tty",
+            StrictViolation::NoWindowSize => "the tty has no window size",
+            StrictViolation::ModeNotApplied => "the terminal didn't take the whole mode",
+        })
     }
+}
 
-    fn flush(&mut self) -> io::Result<()> {
-        self.inner.flush()
+impl std::error::Error for StrictViolation {}
+
+/// How [`TtyModeGuard::set_raw_mode`] switches to raw mode, see
+/// [`TtyModeGuard::set_raw_strategy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RawStrategy {
+    /// Applies `cfmakeraw` to the saved mode, the default.
+    #[default]
+    Overwrite,
+    /// Starts from the mode currently applied and only clears line editing, echo, signals,
+    /// flow control and the newline translations. Everything else set with `stty` or by
+    /// earlier changes, e.g. `ISTRIP`, `IGNBRK`, `IXANY` or parity, stays.
+    Merge,
+}
+
+impl RawStrategy {
+    fn apply(self, ios: &mut Termios) {
+        match self {
+            RawStrategy::Overwrite => raw_terminal_attr(ios),
+            RawStrategy::Merge => merged_raw_terminal_attr(ios),
+        }
     }
 }
 
-#[derive(Deref, DerefMut)]
-pub struct RawReader<T: Read + AsRawFd>(TtyWithGuard<T>);
+/// What [`TtyModeGuard::reattach`] found out about the new terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reattachment {
+    pub window_size: WindowSize,
+    pub job_control: JobControl,
+}
+
+/// What a guard does when restoring the terminal on drop fails, see
+/// [`TtyModeGuard::set_error_policy`].
+///
+/// A hangup (`EIO`) is never an error, there is no terminal left to restore then.
+pub enum ErrorPolicy {
+    /// Panic, the default.
+    Panic,
+    Ignore,
+    /// Pass the error to a callback, e.g. for logging.
+    Report(Box<dyn FnMut(&io::Error) + Send>),
+}
 
-impl<R: Read + AsRawFd> Read for RawReader<R> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
+impl std::fmt::Debug for ErrorPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ErrorPolicy::Panic => f.write_str("Panic"),
+            ErrorPolicy::Ignore => f.write_str("Ignore"),
+            ErrorPolicy::Report(_) => f.write_str("Report(..)"),
+        }
     }
 }
 
-/// Types which can be converted into "raw mode".
-///
-pub trait IntoRawMode: Read + AsRawFd + Sized {
-    /// Switch to raw mode.
-    ///
-    /// Raw mode means that stdin won't be printed (it will instead have to be written manually by
-    /// the program). Furthermore, the input isn't canonicalised or buffered (that is, you can
-    /// read from stdin one byte of a time). The output is neither modified in any way.
-    fn into_raw_mode(self) -> io::Result<RawReader<Self>>;
+/// How often restoring on drop is tried before giving up, see
+/// [`TtyModeGuard::set_error_policy`].
+const RESTORE_ATTEMPTS: u32 = 3;
+
+/// DECSTR followed by an SGR reset.
+const SOFT_RESET: &[u8] = b"\x1b[!p\x1b[0m";
+
+type RestoreHook = Box<dyn FnMut() + Send>;
+
+/// When a hook registered with [`TtyModeGuard::on_restore`] runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookOrder {
+    /// Right before the saved termios is restored for good.
+    BeforeRestore,
+    /// Right after the saved termios is restored for good.
+    AfterRestore,
 }
 
-impl<T: Read + AsRawFd> IntoRawMode for T {
-    fn into_raw_mode(self) -> io::Result<RawReader<T>> {
-        let mut x = TtyWithGuard::new(self)?;
-        x.set_raw_mode()?;
-        Ok(RawReader(x))
+impl Drop for TtyModeGuard {
+    fn drop(&mut self) {
+        LIVE_GUARDS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|restore| !Arc::ptr_eq(restore, &self.restore));
+        let result = self
+            .state()
+            .finish("drop", |ios| self.restore_verified(ios));
+        match (result, &mut self.error_policy) {
+            (Ok(()), _) | (Err(_), ErrorPolicy::Ignore) => {}
+            // The terminal hung up, there is nothing left to restore.
+            (Err(ref e), _) if e.raw_os_error() == Some(libc::EIO) => {}
+            (Err(e), ErrorPolicy::Report(report)) => report(&e),
+            (Err(e), ErrorPolicy::Panic) => panic!("restoring the terminal failed: {}", e),
+        }
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::io::{self, stdin, stdout, Write};
-    use std::os::unix::io::AsRawFd;
+impl TtyModeGuard {
+    pub fn new(fd: RawFd) -> io::Result<TtyModeGuard> {
+        let ios = get_terminal_attr(fd).map_err(|e| match e.raw_os_error() {
+            Some(libc::ENOTTY) => NotATty::error(fd),
+            _ => e,
+        })?;
 
-    #[test]
-    fn test_into_raw_mode() -> io::Result<()> {
-        let mut stdin = stdin().guard_mode()?;
-        stdin.set_raw_mode()?;
-        let mut out = stdout();
+        let restore = Arc::new(Mutex::new(Restore {
+            fd,
+            ios,
+            fingerprint: fingerprint(&ios),
+            hooks: Vec::new(),
+            reset_sequence: None,
+            status_flags: None,
+            history: None,
+        }));
+        LIVE_GUARDS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(restore.clone());
 
-        out.write_all(b"testing, 123\r\n")?;
+        Ok(Self {
+            fd,
+            restore,
+            stats: Cell::new(GuardStats {
+                get_attr_calls: 1,
+                ..GuardStats::default()
+            }),
+            applied: Cell::new(None),
+            yielded: None,
+            strict: false,
+            raw_strategy: RawStrategy::Overwrite,
+            error_policy: ErrorPolicy::Panic,
+            ioctls: Vec::new(),
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, Restore> {
+        self.restore.lock().unwrap_or_else(|e| e.into_inner())
+    }
 
-        drop(out);
+    /// The saved termios, after making sure it wasn't corrupted since it was saved.
+    fn saved(&self) -> io::Result<Termios> {
+        self.state().saved()
+    }
+
+    /// Makes the guard also write a soft terminal reset (DECSTR) and an SGR reset when
+    /// restoring the termios.
+    ///
+    /// This cleans up attributes like reverse video or switched character sets which were
+    /// left behind by e.g. a crashed child process.
+    pub fn restore_with_soft_reset(&mut self) {
+        self.restore_with_reset_sequence(SOFT_RESET);
+    }
+
+    /// Makes the guard write `sequence` right before restoring the termios, replacing any
+    /// previously set reset sequence.
+    ///
+    /// With the `terminfo` feature, `Terminfo::reset_sequence` provides one which matches
+    /// the terminal.
+    pub fn restore_with_reset_sequence(&mut self, sequence: &[u8]) {
+        self.state().reset_sequence = Some(sequence.to_vec());
+    }
+
+    /// Registers `f` to be run when the guard is dropped or on [`exit`], either right before
+    /// or right after the saved termios is restored.
+    ///
+    /// Hooks don't run for the temporary restores of [`restore`](TtyModeGuard::restore),
+    /// [`suspend_process`](TtyModeGuard::suspend_process) or
+    /// [`yield_to_external`](TtyModeGuard::yield_to_external), which keep the guard.
+    ///
+    /// This is the place to emit escape sequences which have to accompany the mode
+    /// restoration, like an SGR reset or leaving keypad mode. Hooks run in the order they
+    /// were registered.
+    pub fn on_restore<F>(&mut self, order: HookOrder, f: F)
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.state().hooks.push((order, Box::new(f)));
+    }
+
+    /// Sets or clears `O_NONBLOCK` on the fd. The original flags are restored on drop.
+    ///
+    /// Note that the flag is shared by every fd referring to the same open file, e.g. the
+    /// shell's copy of stdin.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        let flags = util::convert_to_result(unsafe { libc::fcntl(self.fd, libc::F_GETFL) })?;
+        let new_flags = if nonblocking {
+            flags | libc::O_NONBLOCK
+        } else {
+            flags & !libc::O_NONBLOCK
+        };
+        util::convert_to_result(unsafe { libc::fcntl(self.fd, libc::F_SETFL, new_flags) })?;
+        self.state().status_flags.get_or_insert(flags);
+        Ok(())
+    }
+
+    /// The termios saved on creation, which is restored on drop.
+    pub fn saved_termios(&self) -> Termios {
+        self.state().ios
+    }
+
+    /// The termios currently applied to the tty.
+    pub fn current_termios(&self) -> io::Result<Termios> {
+        self.get_attr()
+    }
+
+    /// Counters of the calls made to the terminal driver so far, including the ones on
+    /// creation.
+    ///
+    /// Every mode switch is visible as flicker on slow serial consoles, so this is a way to
+    /// make sure a program doesn't switch more often than it needs to.
+    pub fn stats(&self) -> GuardStats {
+        self.stats.get()
+    }
+
+    fn count(&self, f: impl FnOnce(&mut GuardStats)) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+
+    /// `tcgetattr`, counted.
+    pub(crate) fn get_attr(&self) -> io::Result<Termios> {
+        self.count(|stats| stats.get_attr_calls += 1);
+        get_terminal_attr(self.fd)
+    }
+
+    /// `tcsetattr`, counted and recorded in the history as done by `what`. In strict mode
+    /// the preconditions are checked first and the mode is read back afterwards.
+    pub(crate) fn set_attr(&self, what: &'static str, ios: &Termios) -> io::Result<()> {
+        if !self.strict {
+            return self.apply(what, ios);
+        }
+        self.check_strict()?;
+        self.apply(what, ios)?;
+        if fingerprint(&self.get_attr()?) != fingerprint(ios) {
+            return Err(StrictViolation::ModeNotApplied.into());
+        }
+        Ok(())
+    }
+
+    /// Applies the mode set last again, e.g. after the process was continued and the shell
+    /// may have reset the terminal. Does nothing while yielded or before any mode was set.
+    #[cfg(feature = "pty")]
+    pub(crate) fn reapply(&self, what: &'static str) -> io::Result<()> {
+        match self.applied.get() {
+            Some(ios) if self.yielded.is_none() => self.set_attr(what, &ios),
+            _ => Ok(()),
+        }
+    }
+
+    fn apply(&self, what: &'static str, ios: &Termios) -> io::Result<()> {
+        self.count(|stats| stats.set_attr_calls += 1);
+        set_terminal_attr(self.fd, ios)?;
+        self.applied.set(Some(*ios));
+        // Locked already while restoring on drop, which records the restore itself.
+        if let Ok(mut state) = self.restore.try_lock() {
+            if let Some(ref mut history) = state.history {
+                history.record(what, ios);
+            }
+        }
+        Ok(())
+    }
+
+    /// How the tty relates to the job control of the process, checked on every call since
+    /// the shell can move the process between foreground and background.
+    pub fn job_control(&self) -> io::Result<JobControl> {
+        syscall::with(self.fd, |sys| sys.job_control(self.fd))
+    }
+
+    /// `set_attr`, with `SIGTTOU` ignored while in the background, so that restoring the
+    /// mode doesn't stop a process which is exiting. Only the restore on drop does this: the
+    /// kernel doesn't send `SIGTTOU` for a tty which isn't the controlling terminal, and a
+    /// foreground process doesn't get it either.
+    fn restore_from_any_pgrp(&self, ios: &Termios) -> io::Result<()> {
+        if self.job_control().ok() != Some(JobControl::Background) {
+            return self.apply("drop", ios);
+        }
+        unsafe {
+            let mut ignore: libc::sigaction = std::mem::zeroed();
+            ignore.sa_sigaction = libc::SIG_IGN;
+            let mut previous = std::mem::zeroed();
+            util::convert_to_result(libc::sigaction(libc::SIGTTOU, &ignore, &mut previous))?;
+            let result = self.apply("drop", ios);
+            libc::sigaction(libc::SIGTTOU, &previous, std::ptr::null_mut());
+            result
+        }
+    }
+
+    /// `restore_from_any_pgrp`, reading the mode back afterwards and trying again if the
+    /// terminal didn't take it, which can happen when it hangs up and reconnects at the
+    /// same time.
+    ///
+    /// Only a read-back which still shows the mode from before the restore counts as not
+    /// taken. Any other mode was set by someone else in the meantime, e.g. another guard or
+    /// another process on the tty, and is left alone, as is everything while another live
+    /// guard is on the same tty.
+    fn restore_verified(&self, ios: &Termios) -> io::Result<()> {
+        for _ in 0..RESTORE_ATTEMPTS {
+            let before = fingerprint(&self.get_attr()?);
+            self.restore_from_any_pgrp(ios)?;
+            let after = fingerprint(&self.get_attr()?);
+            if after == fingerprint(ios)
+                || after != before
+                || self.other_guard_on_tty().unwrap_or(false)
+            {
+                return Ok(());
+            }
+        }
+        Err(io::Error::other(format!(
+            "the terminal didn't take the saved mode in {} attempts",
+            RESTORE_ATTEMPTS
+        )))
+    }
+
+    /// Makes every mode change check its preconditions and fail with a typed error instead
+    /// of doing its best, see [`TtyWithGuardBuilder::strict`].
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Checks what strict mode requires before changing the mode: the fd is still a tty,
+    /// the process isn't in the background, the tty has a window size and no other guard is
+    /// on it.
+    fn check_strict(&self) -> io::Result<()> {
+        if unsafe { libc::isatty(self.fd) } != 1 {
+            return Err(NotATty::error(self.fd));
+        }
+        if self.job_control()? == JobControl::Background {
+            return Err(StrictViolation::Background.into());
+        }
+        let size = attr::get_window_size(self.fd)?;
+        if size.rows == 0 || size.cols == 0 {
+            return Err(StrictViolation::NoWindowSize.into());
+        }
+        if self.other_guard_on_tty()? {
+            return Err(StrictViolation::NestedGuard.into());
+        }
+        Ok(())
+    }
+
+    /// Whether another live guard is on the same tty, possibly through another fd.
+    fn other_guard_on_tty(&self) -> io::Result<bool> {
+        let device = tty_device(self.fd)?;
+        let live = LIVE_GUARDS.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(live
+            .iter()
+            .filter(|restore| !Arc::ptr_eq(restore, &self.restore))
+            .filter_map(|restore| restore.try_lock().ok().map(|restore| restore.fd))
+            .any(|fd| tty_device(fd).ok() == Some(device)))
+    }
+
+    /// Sets what happens when restoring the terminal on drop fails.
+    ///
+    /// Restoring on drop reads the mode back and retries a few times if the terminal didn't
+    /// take it, so this also covers a terminal which accepts the mode but doesn't apply it.
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.error_policy = policy;
+    }
+
+    /// Calls `ioctl(fd, request, arg)` on the guarded tty, for the occasional request this
+    /// crate has no method for, e.g. `TIOCSTI` or a driver specific one.
+    ///
+    /// The call is counted in the [stats](TtyModeGuard::stats) and remembered in the
+    /// [history](TtyModeGuard::ioctl_history), so that it shows up when debugging the
+    /// terminal state. The saved mode isn't touched: if the request changes the termios,
+    /// it is still restored on drop.
+    ///
+    /// # Safety
+    ///
+    /// `arg` has to be what `request` expects, as for calling `libc::ioctl` directly.
+    /// `request` is cast to the request type of the platform, whose width and
+    /// signedness differ between libcs, e.g. `libc::TIOCSTI as u64`.
+    pub unsafe fn ioctl<T>(&mut self, request: u64, arg: *mut T) -> io::Result<i32> {
+        self.count(|stats| stats.ioctl_calls += 1);
+        let result = util::convert_to_result(libc::ioctl(self.fd, request as libc::Ioctl, arg));
+        if self.ioctls.len() == IOCTL_HISTORY_LEN {
+            self.ioctls.remove(0);
+        }
+        self.ioctls.push(IoctlRecord {
+            request,
+            errno: result.as_ref().err().and_then(io::Error::raw_os_error),
+        });
+        result
+    }
+
+    /// The last calls of [`ioctl`](TtyModeGuard::ioctl), oldest first.
+    pub fn ioctl_history(&self) -> &[IoctlRecord] {
+        &self.ioctls
+    }
+
+    /// Starts keeping the last `capacity` mode changes, see [`history`](TtyModeGuard::history)
+    /// and [`install_history_panic_hook`]. Changing the capacity keeps the transitions
+    /// recorded so far, as far as they fit.
+    pub fn enable_history(&mut self, capacity: usize) {
+        let current = self.get_attr().unwrap_or(self.state().ios);
+        let mut state = self.state();
+        let history = state.history.get_or_insert_with(|| History {
+            capacity,
+            transitions: Default::default(),
+            created: Instant::now(),
+            current,
+        });
+        history.capacity = capacity;
+        while history.transitions.len() > capacity {
+            history.transitions.pop_front();
+        }
+    }
+
+    /// The mode changes recorded since [`enable_history`](TtyModeGuard::enable_history),
+    /// oldest first.
+    pub fn history(&self) -> Vec<Transition> {
+        self.state()
+            .history
+            .as_ref()
+            .map_or_else(Vec::new, |history| {
+                history.transitions.iter().copied().collect()
+            })
+    }
+
+    /// Applies the saved termios again right away, without giving up the guard.
+    pub fn restore(&mut self) -> io::Result<()> {
+        self.count(|stats| stats.restores += 1);
+        let ios = self.saved()?;
+        self.set_attr("restore", &ios)
+    }
+
+    /// Hands the terminal over to another library in the same process, e.g. readline in an
+    /// embedded interpreter, by restoring the saved mode while remembering the current one.
+    ///
+    /// Does nothing if already yielded.
+    pub fn yield_to_external(&mut self) -> io::Result<()> {
+        if self.yielded.is_some() {
+            return Ok(());
+        }
+        let current = self.get_attr()?;
+        self.restore()?;
+        self.yielded = Some(current);
+        Ok(())
+    }
+
+    /// Takes the terminal back after [`yield_to_external`](TtyModeGuard::yield_to_external)
+    /// and applies the mode which was active before.
+    ///
+    /// The mode the other library left behind becomes the new saved mode, since that is
+    /// what the host expects to get back when the guard is dropped, e.g. after the user
+    /// changed settings with `stty`. Does nothing if not yielded.
+    pub fn reclaim(&mut self) -> io::Result<()> {
+        let ours = match self.yielded {
+            Some(ours) => ours,
+            None => return Ok(()),
+        };
+        let external = self.get_attr()?;
+        self.set_attr("reclaim", &ours)?;
+        self.yielded = None;
+        let mut state = self.state();
+        state.ios = external;
+        state.fingerprint = fingerprint(&external);
+        Ok(())
+    }
+
+    /// Checks the terminal again and re-applies the mode this guard set last, after the
+    /// terminal on the other side of the fd was replaced, e.g. when mosh roamed to another
+    /// network or a different tmux client attached.
+    ///
+    /// Fails with a [`NotATty`] error if the fd stopped being a terminal. While yielded
+    /// the mode is left to the other library: [`reclaim`](TtyModeGuard::reclaim) applies
+    /// it. With the `signals` feature, the active [`Events`](crate::events::Events) then
+    /// returns [`Event::Reattached`](crate::events::Event::Reattached), so that the
+    /// program redraws wherever it waits for events.
+    pub fn reattach(&mut self) -> io::Result<Reattachment> {
+        if unsafe { libc::isatty(self.fd) } != 1 {
+            return Err(NotATty::error(self.fd));
+        }
+        let window_size = attr::get_window_size(self.fd)?;
+        let job_control = self.job_control()?;
+        if self.yielded.is_none() {
+            if let Some(ios) = self.applied.get() {
+                self.set_attr("reattach", &ios)?;
+            }
+        }
+        #[cfg(feature = "signals")]
+        events::notify_reattached();
+        Ok(Reattachment {
+            window_size,
+            job_control,
+        })
+    }
+
+    /// Whether the terminal is currently yielded to another library.
+    pub fn is_yielded(&self) -> bool {
+        self.yielded.is_some()
+    }
+
+    /// Does what Ctrl-Z does in cooked mode: restores the saved mode, stops the process
+    /// group with `SIGTSTP` and re-applies the current mode once the shell continues it.
+    ///
+    /// For programs which switched off `ISIG` and read Ctrl-Z as a key, or which got
+    /// [`Event::Suspend`](crate::events::Event::Suspend). The signal is delivered with its
+    /// default action even if it is caught, e.g. by [`events::Events`](crate::events::Events).
+    ///
+    /// Fails with `Unsupported` if the tty isn't the controlling terminal, since there is
+    /// no shell to return to then, see [`JobControl::Unavailable`].
+    pub fn suspend_process(&mut self) -> io::Result<()> {
+        if self.job_control()? == JobControl::Unavailable {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "can't suspend, the tty isn't the controlling terminal",
+            ));
+        }
+        let current = self.get_attr()?;
+        self.restore()?;
+        self.count(|stats| stats.suspends += 1);
+        unsafe {
+            let mut default: libc::sigaction = std::mem::zeroed();
+            default.sa_sigaction = libc::SIG_DFL;
+            let mut previous = std::mem::zeroed();
+            util::convert_to_result(libc::sigaction(libc::SIGTSTP, &default, &mut previous))?;
+            let mut unblock = std::mem::zeroed();
+            libc::sigemptyset(&mut unblock);
+            libc::sigaddset(&mut unblock, libc::SIGTSTP);
+            let mut mask = std::mem::zeroed();
+            libc::pthread_sigmask(libc::SIG_UNBLOCK, &unblock, &mut mask);
+            // Stops right here, until the shell sends SIGCONT.
+            libc::kill(0, libc::SIGTSTP);
+            libc::pthread_sigmask(libc::SIG_SETMASK, &mask, std::ptr::null_mut());
+            libc::sigaction(libc::SIGTSTP, &previous, std::ptr::null_mut());
+        }
+        self.set_attr("suspend_process", &current)
+    }
+
+    /// Switch to raw mode.
+    pub fn set_raw_mode(&mut self) -> io::Result<()> {
+        let mut ios = match self.raw_strategy {
+            RawStrategy::Overwrite => self.saved()?,
+            RawStrategy::Merge => self.get_attr()?,
+        };
+
+        self.raw_strategy.apply(&mut ios);
+
+        self.set_attr("set_raw_mode", &ios)?;
+        Ok(())
+    }
+
+    /// Chooses how [`set_raw_mode`](TtyModeGuard::set_raw_mode) gets to raw mode, for
+    /// users who customized their terminal with `stty` and expect it to stay that way.
+    pub fn set_raw_strategy(&mut self, strategy: RawStrategy) {
+        self.raw_strategy = strategy;
+    }
+
+    /// Switch to raw mode with every input translation and parity handling disabled, so that
+    /// all 8 bits of every byte make it through unchanged.
+    ///
+    /// Intended for file transfer protocols such as ZMODEM/XMODEM over a terminal or serial
+    /// line.
+    pub fn set_8bit_clean(&mut self) -> io::Result<()> {
+        let mut ios = self.saved()?;
+
+        eight_bit_clean_terminal_attr(&mut ios);
+
+        self.set_attr("set_8bit_clean", &ios)?;
+        Ok(())
+    }
+
+    /// Creates a copy of the saved termios and passes it to `f`
+    /// which should return the new termios to apply.
+    ///
+    /// This method can be used to restore the saved ios afterwards
+    /// by using the identity function.
+    pub fn modify_mode<F>(&mut self, f: F) -> io::Result<()>
+    where
+        F: FnOnce(Termios) -> Termios,
+    {
+        let ios = f(self.saved()?);
+        self.set_attr("modify_mode", &ios)?;
+        Ok(())
+    }
+
+    /// Passes the termios currently applied to `f` to be changed in place, and applies the
+    /// result.
+    ///
+    /// Unlike [`modify_mode`](TtyModeGuard::modify_mode) this builds on top of the previous
+    /// changes, e.g. to tweak a single flag after [`set_raw_mode`](TtyModeGuard::set_raw_mode).
+    pub fn update_mode<F>(&mut self, f: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut Termios),
+    {
+        let mut ios = self.get_attr()?;
+        f(&mut ios);
+        self.set_attr("update_mode", &ios)?;
+        Ok(())
+    }
+
+    /// Brings the mode applied in line with `desired`, changing only what differs and not
+    /// calling `tcsetattr` at all if nothing does. Returns whether the mode changed.
+    pub fn reconcile(&mut self, desired: &DesiredMode) -> io::Result<bool> {
+        let live = self.get_attr()?;
+        if desired.is_satisfied_by(&live) {
+            return Ok(false);
+        }
+        self.set_attr("reconcile", &desired.apply(&live))?;
+        Ok(true)
+    }
+}
+
+use std::io::Read;
+use std::ops;
+
+/// Wraps a file descriptor for a TTY with a guard which saves
+/// the terminal mode on creation and restores it on drop.
+pub struct TtyWithGuard<T: AsRawFd> {
+    guard: TtyModeGuard,
+    inner: T,
+}
+
+impl<R: AsRawFd> ops::Deref for TtyWithGuard<R> {
+    type Target = R;
+
+    #[inline]
+    fn deref(&self) -> &R {
+        &self.inner
+    }
+}
+
+impl<R: AsRawFd> ops::DerefMut for TtyWithGuard<R> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+}
+
+impl<T: AsRawFd> TtyWithGuard<T> {
+    pub fn new(tty: T) -> io::Result<TtyWithGuard<T>> {
+        Ok(Self {
+            guard: TtyModeGuard::new(tty.as_raw_fd())?,
+            inner: tty,
+        })
+    }
+
+    /// Replaces the wrapped value with one for the same fd, keeping the guard.
+    fn map_inner<U: AsRawFd>(self, f: impl FnOnce(T) -> U) -> TtyWithGuard<U> {
+        TtyWithGuard {
+            guard: self.guard,
+            inner: f(self.inner),
+        }
+    }
+
+    /// Starts building a guard which applies its initial mode with a single `tcsetattr`.
+    ///
+    /// ```no_run
+    /// use raw_tty::TtyWithGuard;
+    /// use std::io::{self, stdin};
+    ///
+    /// fn main() -> io::Result<()> {
+    ///     let stdin = TtyWithGuard::builder(stdin()).raw().vmin(1).vtime(0).build()?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn builder(tty: T) -> TtyWithGuardBuilder<T> {
+        TtyWithGuardBuilder {
+            tty,
+            raw: false,
+            vmin: None,
+            vtime: None,
+            nonblocking: None,
+            strict: false,
+            raw_strategy: RawStrategy::Overwrite,
+        }
+    }
+
+    /// Sets or clears `O_NONBLOCK`, see [`TtyModeGuard::set_nonblocking`].
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.guard.set_nonblocking(nonblocking)
+    }
+
+    /// Creates a copy of the saved termios and passes it to `f`
+    /// which should return the new termios to apply.
+    ///
+    /// This method can be used to restore the saved ios afterwards
+    /// by using the identity function.
+    pub fn modify_mode<F>(&mut self, f: F) -> io::Result<()>
+    where
+        F: FnOnce(Termios) -> Termios,
+    {
+        self.guard.modify_mode(f)
+    }
+
+    /// Changes the current termios in place, see [`TtyModeGuard::update_mode`].
+    pub fn update_mode<F>(&mut self, f: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut Termios),
+    {
+        self.guard.update_mode(f)
+    }
+
+    /// Brings the mode in line with `desired`, see [`TtyModeGuard::reconcile`].
+    pub fn reconcile(&mut self, desired: &DesiredMode) -> io::Result<bool> {
+        self.guard.reconcile(desired)
+    }
+
+    /// Switch to raw mode.
+    pub fn set_raw_mode(&mut self) -> io::Result<()> {
+        self.guard.set_raw_mode()
+    }
+
+    /// Chooses how to switch to raw mode, see [`TtyModeGuard::set_raw_strategy`].
+    pub fn set_raw_strategy(&mut self, strategy: RawStrategy) {
+        self.guard.set_raw_strategy(strategy)
+    }
+
+    /// Hands the terminal to another library, see [`TtyModeGuard::yield_to_external`].
+    pub fn yield_to_external(&mut self) -> io::Result<()> {
+        self.guard.yield_to_external()
+    }
+
+    /// Takes the terminal back, see [`TtyModeGuard::reclaim`].
+    pub fn reclaim(&mut self) -> io::Result<()> {
+        self.guard.reclaim()
+    }
+
+    /// Re-applies the mode after the terminal was replaced, see
+    /// [`TtyModeGuard::reattach`].
+    pub fn reattach(&mut self) -> io::Result<Reattachment> {
+        self.guard.reattach()
+    }
+
+    /// Stops the process like Ctrl-Z, see [`TtyModeGuard::suspend_process`].
+    pub fn suspend_process(&mut self) -> io::Result<()> {
+        self.guard.suspend_process()
+    }
+
+    /// Applies the saved termios right away, see [`TtyModeGuard::restore`].
+    pub fn restore(&mut self) -> io::Result<()> {
+        self.guard.restore()
+    }
+
+    /// How the tty relates to job control, see [`TtyModeGuard::job_control`].
+    pub fn job_control(&self) -> io::Result<JobControl> {
+        self.guard.job_control()
+    }
+
+    /// Calls `ioctl` on the tty with bookkeeping, see [`TtyModeGuard::ioctl`].
+    ///
+    /// # Safety
+    ///
+    /// `arg` has to be what `request` expects.
+    pub unsafe fn ioctl<A>(&mut self, request: u64, arg: *mut A) -> io::Result<i32> {
+        self.guard.ioctl(request, arg)
+    }
+
+    /// The last calls of [`ioctl`](TtyWithGuard::ioctl), see
+    /// [`TtyModeGuard::ioctl_history`].
+    pub fn ioctl_history(&self) -> &[IoctlRecord] {
+        self.guard.ioctl_history()
+    }
+
+    /// Starts recording the mode changes, see [`TtyModeGuard::enable_history`].
+    pub fn enable_history(&mut self, capacity: usize) {
+        self.guard.enable_history(capacity)
+    }
+
+    /// The recorded mode changes, see [`TtyModeGuard::history`].
+    pub fn history(&self) -> Vec<Transition> {
+        self.guard.history()
+    }
+
+    /// Makes mode changes fail fast, see [`TtyWithGuardBuilder::strict`].
+    pub fn set_strict(&mut self, strict: bool) {
+        self.guard.set_strict(strict);
+    }
+
+    /// Sets what happens when restoring on drop fails, see
+    /// [`TtyModeGuard::set_error_policy`].
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.guard.set_error_policy(policy);
+    }
+
+    /// Counters of the calls to the terminal driver, see [`TtyModeGuard::stats`].
+    pub fn stats(&self) -> GuardStats {
+        self.guard.stats()
+    }
+
+    /// The termios which will be restored, see [`TtyModeGuard::saved_termios`].
+    pub fn saved_termios(&self) -> Termios {
+        self.guard.saved_termios()
+    }
+
+    /// The termios currently applied, see [`TtyModeGuard::current_termios`].
+    pub fn current_termios(&self) -> io::Result<Termios> {
+        self.guard.current_termios()
+    }
+
+    /// The current window size of the tty.
+    pub fn window_size(&self) -> io::Result<WindowSize> {
+        attr::get_window_size(self.guard.fd)
+    }
+
+    /// Registers a hook to run when the guard is dropped or on [`exit`], see
+    /// [`TtyModeGuard::on_restore`].
+    pub fn on_restore<F>(&mut self, order: HookOrder, f: F)
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.guard.on_restore(order, f)
+    }
+
+    /// Also reset the terminal's attributes when restoring, see
+    /// [`TtyModeGuard::restore_with_soft_reset`].
+    pub fn restore_with_soft_reset(&mut self) {
+        self.guard.restore_with_soft_reset()
+    }
+
+    /// Write `sequence` when restoring, see [`TtyModeGuard::restore_with_reset_sequence`].
+    pub fn restore_with_reset_sequence(&mut self, sequence: &[u8]) {
+        self.guard.restore_with_reset_sequence(sequence)
+    }
+
+    /// Switch to 8-bit clean raw mode, see [`TtyModeGuard::set_8bit_clean`].
+    pub fn set_8bit_clean(&mut self) -> io::Result<()> {
+        self.guard.set_8bit_clean()
+    }
+
+    /// Set the line speed, see [`TtyModeGuard::set_speed`].
+    pub fn set_speed(&mut self, speed: Speed) -> io::Result<()> {
+        self.guard.set_speed(speed)
+    }
+
+    /// The output speed, see [`TtyModeGuard::speed`].
+    pub fn speed(&self) -> io::Result<Speed> {
+        self.guard.speed()
+    }
+
+    /// The input speed, see [`TtyModeGuard::input_speed`].
+    pub fn input_speed(&self) -> io::Result<Speed> {
+        self.guard.input_speed()
+    }
+
+    /// Disables output processing (`OPOST`) while `f` runs, e.g. to write sixel images or
+    /// a zmodem transfer without `\n` being translated.
+    ///
+    /// Only the output flags are touched: afterwards the oflag that was active before is put
+    /// back, while any input side changes made in the meantime are kept.
+    pub fn binary_output_scope<F, R>(&mut self, f: F) -> io::Result<R>
+    where
+        F: FnOnce(&mut T) -> io::Result<R>,
+    {
+        let mut ios = self.guard.get_attr()?;
+        let oflag = ios.c_oflag;
+        ios.c_oflag &= !libc::OPOST;
+        self.guard.set_attr("binary_output_scope", &ios)?;
+
+        let result = f(&mut self.inner);
+
+        let mut ios = self.guard.get_attr()?;
+        ios.c_oflag = oflag;
+        self.guard.set_attr("binary_output_scope", &ios)?;
+        result
+    }
+
+    /// Turns `ECHO` on or off while `f` runs, e.g. to echo a user name, hide the password
+    /// and then echo again, without leaving raw mode.
+    ///
+    /// Afterwards the lflag that was active before is put back exactly, also when `f`
+    /// fails, while the other fields keep any changes made in the meantime.
+    ///
+    /// ```no_run
+    /// use raw_tty::GuardMode;
+    /// use std::io::{self, stdin, BufRead};
+    ///
+    /// fn main() -> io::Result<()> {
+    ///     let mut tty = stdin().guard_mode()?;
+    ///     let mut password = String::new();
+    ///     tty.with_echo(false, |_| stdin().lock().read_line(&mut password))?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn with_echo<F, R>(&mut self, echo: bool, f: F) -> io::Result<R>
+    where
+        F: FnOnce(&mut T) -> io::Result<R>,
+    {
+        let mut ios = self.guard.get_attr()?;
+        let lflag = ios.c_lflag;
+        if echo {
+            ios.c_lflag |= libc::ECHO;
+        } else {
+            ios.c_lflag &= !libc::ECHO;
+        }
+        self.guard.set_attr("with_echo", &ios)?;
+
+        let result = f(&mut self.inner);
+
+        let mut ios = self.guard.get_attr()?;
+        ios.c_lflag = lflag;
+        self.guard.set_attr("with_echo", &ios)?;
+        result
+    }
+}
+
+impl TtyWithGuard<std::fs::File> {
+    /// Takes ownership of an already open tty fd and guards it, e.g. a console fd passed by
+    /// a container runtime or supervisor, see [`launcher`].
+    ///
+    /// The fd is closed when the result is dropped, or right away if it isn't a tty.
+    ///
+    /// # Safety
+    ///
+    /// `fd` has to be open and not owned by anything else, as for
+    /// [`FromRawFd::from_raw_fd`](std::os::unix::io::FromRawFd::from_raw_fd). Where the fd
+    /// already is an `OwnedFd`, `TtyWithGuard::new(File::from(fd))` does the same without
+    /// `unsafe`.
+    pub unsafe fn from_raw_fd_owned(fd: RawFd) -> io::Result<TtyWithGuard<std::fs::File>> {
+        use std::os::unix::io::FromRawFd;
+        TtyWithGuard::new(std::fs::File::from_raw_fd(fd))
+    }
+}
+/// Builds a [`TtyWithGuard`] with an initial mode, see [`TtyWithGuard::builder`].
+pub struct TtyWithGuardBuilder<T: AsRawFd> {
+    tty: T,
+    raw: bool,
+    vmin: Option<u8>,
+    vtime: Option<u8>,
+    nonblocking: Option<bool>,
+    strict: bool,
+    raw_strategy: RawStrategy,
+}
+
+impl<T: AsRawFd> TtyWithGuardBuilder<T> {
+    /// Start in raw mode.
+    pub fn raw(mut self) -> TtyWithGuardBuilder<T> {
+        self.raw = true;
+        self
+    }
+
+    /// How raw mode is reached, see [`TtyModeGuard::set_raw_strategy`].
+    pub fn raw_strategy(mut self, strategy: RawStrategy) -> TtyWithGuardBuilder<T> {
+        self.raw_strategy = strategy;
+        self
+    }
+
+    /// The minimum number of bytes for a non-canonical read.
+    pub fn vmin(mut self, vmin: u8) -> TtyWithGuardBuilder<T> {
+        self.vmin = Some(vmin);
+        self
+    }
+
+    /// The timeout of a non-canonical read, in tenths of a second.
+    pub fn vtime(mut self, vtime: u8) -> TtyWithGuardBuilder<T> {
+        self.vtime = Some(vtime);
+        self
+    }
+
+    /// The preset for games and renderers which poll for input once per frame: raw mode
+    /// with `VMIN` and `VTIME` at 0, so that a read returns right away with whatever
+    /// arrived, and `O_NONBLOCK` cleared so that the fd doesn't need special handling.
+    ///
+    /// Poll with [`KeyPoller`](crate::prompt::KeyPoller).
+    pub fn game_mode(self) -> TtyWithGuardBuilder<T> {
+        self.raw().vmin(0).vtime(0).nonblocking(false)
+    }
+
+    /// Sets or clears `O_NONBLOCK`, see [`TtyModeGuard::set_nonblocking`].
+    pub fn nonblocking(mut self, nonblocking: bool) -> TtyWithGuardBuilder<T> {
+        self.nonblocking = Some(nonblocking);
+        self
+    }
+
+    /// Fail fast instead of doing the best possible: building and every later mode change
+    /// fail with a [`StrictViolation`] or [`NotATty`] error when the terminal is in a state
+    /// where the change might not do what was asked.
+    ///
+    /// That is if the fd stopped being a tty, the process is in the background, the tty has
+    /// no window size, another guard is live on the same tty, or the terminal didn't take
+    /// the whole mode. Restoring on drop is never refused.
+    pub fn strict(mut self) -> TtyWithGuardBuilder<T> {
+        self.strict = true;
+        self
+    }
+
+    /// Saves the current mode and applies the configured one.
+    pub fn build(self) -> io::Result<TtyWithGuard<T>> {
+        let mut tty = TtyWithGuard::new(self.tty)?;
+        tty.set_raw_strategy(self.raw_strategy);
+        if self.strict {
+            tty.guard.set_strict(true);
+            tty.guard.check_strict()?;
+        }
+        let (raw, vmin, vtime) = (self.raw, self.vmin, self.vtime);
+        let strategy = self.raw_strategy;
+        if raw || vmin.is_some() || vtime.is_some() {
+            tty.modify_mode(|mut ios| {
+                if raw {
+                    strategy.apply(&mut ios);
+                }
+                if let Some(vmin) = vmin {
+                    ios.c_cc[libc::VMIN] = vmin as libc::cc_t;
+                }
+                if let Some(vtime) = vtime {
+                    ios.c_cc[libc::VTIME] = vtime as libc::cc_t;
+                }
+                ios
+            })?;
+        }
+        if let Some(nonblocking) = self.nonblocking {
+            tty.set_nonblocking(nonblocking)?;
+        }
+        Ok(tty)
+    }
+}
+
+/// A [`TtyModeGuard`] on a borrowed tty, for when the handle stays owned elsewhere, e.g.
+/// by a framework. Unlike [`TtyWithGuard`] it can't outlive the handle, so the fd can't be
+/// closed before the mode is restored.
+///
+/// Derefs to the guard.
+///
+/// ```no_run
+/// use raw_tty::TtyModeGuardRef;
+/// use std::io::{self, stdin};
+///
+/// fn main() -> io::Result<()> {
+///     let stdin = stdin();
+///     let mut guard = TtyModeGuardRef::new(&stdin)?;
+///     guard.set_raw_mode()?;
+///     // The mode is restored here, and stdin is still usable afterwards.
+///     Ok(())
+/// }
+/// ```
+pub struct TtyModeGuardRef<'fd> {
+    guard: TtyModeGuard,
+    fd: BorrowedFd<'fd>,
+}
+
+impl<'fd> TtyModeGuardRef<'fd> {
+    pub fn new<T: AsFd>(tty: &'fd T) -> io::Result<TtyModeGuardRef<'fd>> {
+        let fd = tty.as_fd();
+        Ok(TtyModeGuardRef {
+            guard: TtyModeGuard::new(fd.as_raw_fd())?,
+            fd,
+        })
+    }
+}
+
+impl ops::Deref for TtyModeGuardRef<'_> {
+    type Target = TtyModeGuard;
+
+    #[inline]
+    fn deref(&self) -> &TtyModeGuard {
+        &self.guard
+    }
+}
+
+impl ops::DerefMut for TtyModeGuardRef<'_> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut TtyModeGuard {
+        &mut self.guard
+    }
+}
+
+impl AsFd for TtyModeGuardRef<'_> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd
+    }
+}
+
+impl AsRawFd for TtyModeGuardRef<'_> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// Types which can save a termios.
+pub trait GuardMode: AsRawFd + Sized {
+    fn guard_mode(self) -> io::Result<TtyWithGuard<Self>>;
+}
+
+impl<T: AsRawFd> GuardMode for T {
+    fn guard_mode(self) -> io::Result<TtyWithGuard<T>> {
+        TtyWithGuard::new(self)
+    }
+}
+
+impl<R: io::Read + AsRawFd> io::Read for TtyWithGuard<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<R: io::Write + AsRawFd> io::Write for TtyWithGuard<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The default for [`RawReader::buffer_size`].
+pub const DEFAULT_BUFFER_SIZE: usize = 4096;
+
+/// A tty in raw mode, which is restored when dropped.
+///
+/// Reading requires `T: Read`, but any fd works, e.g. an `OwnedFd` that is read from
+/// elsewhere.
+pub struct RawReader<T: AsRawFd> {
+    tty: TtyWithGuard<T>,
+    buffer_size: usize,
+    eof_on_hangup: bool,
+    /// Set once a [`ReadCanceller`] was asked for.
+    cancel: Option<std::sync::Arc<cancel::Cancel>>,
+}
+
+impl<R: AsRawFd> ops::Deref for RawReader<R> {
+    type Target = TtyWithGuard<R>;
+
+    #[inline]
+    fn deref(&self) -> &TtyWithGuard<R> {
+        &self.tty
+    }
+}
+
+impl<R: AsRawFd> ops::DerefMut for RawReader<R> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut TtyWithGuard<R> {
+        &mut self.tty
+    }
+}
+
+impl<R: AsRawFd> RawReader<R> {
+    /// The minimum amount of space [`read_available`](RawReader::read_available) reads
+    /// into.
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Sets the minimum amount of space [`read_available`](RawReader::read_available)
+    /// reads into.
+    pub fn set_buffer_size(&mut self, size: usize) {
+        self.buffer_size = size.max(1);
+    }
+
+    /// Sets whether a hangup of the terminal reads as end of file, which is the default.
+    ///
+    /// After a hangup, e.g. a dropped ssh connection or a closed pty master, every read
+    /// fails with `EIO`. Reading it as end of file lets the usual `Ok(0)` handling shut the
+    /// program down instead of spinning on the error.
+    pub fn set_eof_on_hangup(&mut self, eof: bool) {
+        self.eof_on_hangup = eof;
+    }
+
+    fn map_hangup(&self, result: io::Result<usize>) -> io::Result<usize> {
+        match result {
+            Err(ref e) if self.eof_on_hangup && e.raw_os_error() == Some(libc::EIO) => Ok(0),
+            result => result,
+        }
+    }
+}
+
+impl<R: Read + AsRawFd> RawReader<R> {
+    /// Appends everything currently pending on the tty to `buf` using a single `read`,
+    /// returning how many bytes were appended.
+    ///
+    /// Blocks like `read` if nothing is pending. This avoids a syscall per
+    /// [`buffer_size`](RawReader::buffer_size) bytes when copying bulk data, e.g. between
+    /// a pty master and the terminal.
+    pub fn read_available(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let mut pending: libc::c_int = 0;
+        let pending = match unsafe { libc::ioctl(self.as_raw_fd(), libc::FIONREAD, &mut pending) } {
+            -1 => 0,
+            _ => pending as usize,
+        };
+
+        let start = buf.len();
+        buf.resize(start + pending.max(self.buffer_size), 0);
+        let result = self.read(&mut buf[start..]);
+        buf.truncate(start + *result.as_ref().unwrap_or(&0));
+        result
+    }
+
+    /// Fills `buf` like `read_exact`, but gives up at `deadline`, e.g. for a fixed length
+    /// reply to a query or a binary protocol spoken over the terminal.
+    ///
+    /// If the deadline passes first, the error's kind is `TimedOut` and it contains a
+    /// [`ReadTimedOut`] with the number of bytes that arrived, which are at the start of
+    /// `buf`. The terminal closing early is `UnexpectedEof` as with `read_exact`. Works
+    /// whether or not the fd is nonblocking, and whatever `VMIN` and `VTIME` are.
+    pub fn read_exact_timeout(&mut self, buf: &mut [u8], deadline: Instant) -> io::Result<()> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let mut pollfd = libc::pollfd {
+                fd: self.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if util::poll(std::slice::from_mut(&mut pollfd), Some(deadline))? == 0 {
+                return Err(ReadTimedOut::error(filled, buf.len()));
+            }
+            match self.read(&mut buf[filled..]) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "failed to fill whole buffer",
+                    ))
+                }
+                Ok(n) => filled += n,
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The error of [`RawReader::read_exact_timeout`] when the deadline passed, its kind is
+/// `TimedOut`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadTimedOut {
+    read: usize,
+    wanted: usize,
+}
+
+impl ReadTimedOut {
+    fn error(read: usize, wanted: usize) -> io::Error {
+        io::Error::new(io::ErrorKind::TimedOut, ReadTimedOut { read, wanted })
+    }
+
+    /// The `ReadTimedOut` inside `error`, if that is what it is.
+    pub fn find(error: &io::Error) -> Option<&ReadTimedOut> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    /// How many bytes were read before the deadline, they are at the start of the buffer.
+    pub fn read(&self) -> usize {
+        self.read
+    }
+
+    /// The length of the buffer.
+    pub fn wanted(&self) -> usize {
+        self.wanted
+    }
+}
+
+impl std::fmt::Display for ReadTimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "timed out after reading {} of {} bytes",
+            self.read, self.wanted
+        )
+    }
+}
+
+impl std::error::Error for ReadTimedOut {}
+
+impl<T: AsRawFd> AsRawFd for TtyWithGuard<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl<T: AsRawFd> AsRawFd for RawReader<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.tty.as_raw_fd()
+    }
+}
+
+impl<R: Read + AsRawFd> Read for RawReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(ref cancel) = self.cancel {
+                cancel.wait_readable(self.tty.as_raw_fd())?;
+            }
+            let result = self.tty.read(buf);
+            let n = self.map_hangup(result)?;
+            #[cfg(feature = "signals")]
+            let len = interrupt::intercept(&mut buf[..n]);
+            #[cfg(not(feature = "signals"))]
+            let len = n;
+            // Only Ctrl-C was read, which isn't end of file.
+            if n == 0 || len > 0 {
+                return Ok(len);
+            }
+        }
+    }
+}
+
+/// Types which can be converted into "raw mode".
+///
+pub trait IntoRawMode: AsRawFd + Sized {
+    /// Switch to raw mode.
+    ///
+    /// Raw mode means that stdin won't be printed (it will instead have to be written manually by
+    /// the program). Furthermore, the input isn't canonicalised or buffered (that is, you can
+    /// read from stdin one byte of a time). The output is neither modified in any way.
+    fn into_raw_mode(self) -> io::Result<RawReader<Self>>;
+}
+
+impl<T: AsRawFd> IntoRawMode for T {
+    fn into_raw_mode(self) -> io::Result<RawReader<T>> {
+        let mut x = TtyWithGuard::new(self)?;
+        x.set_raw_mode()?;
+        Ok(RawReader {
+            tty: x,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            eof_on_hangup: true,
+            cancel: None,
+        })
+    }
+}
+
+/// The error inside the `io::Error` returned when a guard is created for an fd which isn't
+/// a terminal, typically stdin or stdout redirected from or to a file or pipe.
+///
+/// Its kind is `InvalidInput`, use [`NotATty::find`] to get at the details or
+/// [`OrControllingTty`] to fall back to the controlling terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotATty {
+    fd: RawFd,
+    controlling_tty: bool,
+}
+
+impl NotATty {
+    fn error(fd: RawFd) -> io::Error {
+        let controlling_tty = open_controlling_tty().is_ok();
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            NotATty {
+                fd,
+                controlling_tty,
+            },
+        )
+    }
+
+    /// The `NotATty` inside `error`, if that is what it is.
+    pub fn find(error: &io::Error) -> Option<&NotATty> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    /// The fd which isn't a terminal.
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Whether `/dev/tty` could be opened when the error happened, i.e. whether the process
+    /// has a controlling terminal to fall back to.
+    pub fn controlling_tty_available(&self) -> bool {
+        self.controlling_tty
+    }
+}
+
+impl std::fmt::Display for NotATty {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "fd {} is not a terminal, it is probably redirected",
+            self.fd
+        )?;
+        if self.controlling_tty {
+            write!(f, "; /dev/tty is available, see or_controlling_tty()")
+        } else {
+            write!(f, "; there is no controlling terminal (/dev/tty) either")
+        }
+    }
+}
+
+impl std::error::Error for NotATty {}
+
+fn open_controlling_tty() -> io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+}
+
+/// Either the stream a guard was requested for or the controlling terminal, as returned by
+/// [`OrControllingTty::or_controlling_tty`].
+#[derive(Debug)]
+pub enum Fallback<T> {
+    /// The original stream, which is a terminal.
+    Original(T),
+    /// `/dev/tty`, because the original stream wasn't a terminal.
+    ControllingTty(std::fs::File),
+}
+
+impl<T: AsRawFd> AsRawFd for Fallback<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            Fallback::Original(ref tty) => tty.as_raw_fd(),
+            Fallback::ControllingTty(ref tty) => tty.as_raw_fd(),
+        }
+    }
+}
+
+impl<R: Read> Read for Fallback<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Fallback::Original(ref mut tty) => tty.read(buf),
+            Fallback::ControllingTty(ref mut tty) => tty.read(buf),
+        }
+    }
+}
+
+impl<W: io::Write> io::Write for Fallback<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Fallback::Original(ref mut tty) => tty.write(buf),
+            Fallback::ControllingTty(ref mut tty) => tty.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Fallback::Original(ref mut tty) => tty.flush(),
+            Fallback::ControllingTty(ref mut tty) => tty.flush(),
+        }
+    }
+}
+
+/// Falling back to the controlling terminal when a guard can't be created because the
+/// stream isn't a terminal.
+///
+/// ```no_run
+/// use raw_tty::{IntoRawMode, OrControllingTty};
+/// use std::io::{self, stdin, Read};
+///
+/// fn main() -> io::Result<()> {
+///     // Works with `< input.txt` too, reading the keys from /dev/tty instead.
+///     let mut keys = stdin().into_raw_mode().or_controlling_tty()?;
+///     let mut key = [0];
+///     keys.read_exact(&mut key)?;
+///     Ok(())
+/// }
+/// ```
+pub trait OrControllingTty {
+    type Output;
+
+    /// Replaces a [`NotATty`] error with the same kind of guard for `/dev/tty`, if it is
+    /// available. Other errors are passed through.
+    fn or_controlling_tty(self) -> io::Result<Self::Output>;
+}
+
+impl<T: AsRawFd> OrControllingTty for io::Result<TtyWithGuard<T>> {
+    type Output = TtyWithGuard<Fallback<T>>;
+
+    fn or_controlling_tty(self) -> io::Result<TtyWithGuard<Fallback<T>>> {
+        match self {
+            Ok(tty) => Ok(tty.map_inner(Fallback::Original)),
+            Err(ref e) if NotATty::find(e).is_some_and(|e| e.controlling_tty) => {
+                Fallback::ControllingTty(open_controlling_tty()?).guard_mode()
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<T: AsRawFd> OrControllingTty for io::Result<RawReader<T>> {
+    type Output = RawReader<Fallback<T>>;
+
+    fn or_controlling_tty(self) -> io::Result<RawReader<Fallback<T>>> {
+        match self {
+            Ok(raw) => Ok(RawReader {
+                tty: raw.tty.map_inner(Fallback::Original),
+                buffer_size: raw.buffer_size,
+                eof_on_hangup: raw.eof_on_hangup,
+                cancel: raw.cancel,
+            }),
+            Err(ref e) if NotATty::find(e).is_some_and(|e| e.controlling_tty) => {
+                Fallback::ControllingTty(open_controlling_tty()?).into_raw_mode()
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// A guard for stdin shared by the whole process, created on first use.
+///
+/// For architectures where several independent components may want stdin in raw mode:
+/// they all lock the same guard instead of each saving and restoring the mode on their own,
+/// which would restore an intermediate state. Since statics are never dropped, the saved
+/// mode is restored when the process exits through `exit` or by returning from `main`.
+pub fn global_stdin() -> io::Result<&'static Mutex<TtyWithGuard<io::Stdin>>> {
+    static GLOBAL: OnceLock<Mutex<TtyWithGuard<io::Stdin>>> = OnceLock::new();
+    static INIT: Mutex<()> = Mutex::new(());
+
+    extern "C" fn restore() {
+        if let Some(global) = GLOBAL.get() {
+            if let Ok(tty) = global.try_lock() {
+                if let Ok(ios) = tty.guard.saved() {
+                    let _ = set_terminal_attr(tty.guard.fd, &ios);
+                }
+            }
+        }
+    }
+
+    if let Some(global) = GLOBAL.get() {
+        return Ok(global);
+    }
+    let _init = INIT.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(global) = GLOBAL.get() {
+        return Ok(global);
+    }
+    let tty = TtyWithGuard::new(io::stdin())?;
+    unsafe { libc::atexit(restore) };
+    Ok(GLOBAL.get_or_init(|| Mutex::new(tty)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{self, stdin, stdout, Write};
+
+    #[test]
+    fn test_into_raw_mode() -> io::Result<()> {
+        require_tty!(Ok(()));
+        let _lock = util::TTY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut stdin = stdin().guard_mode()?;
+        stdin.set_raw_mode()?;
+        let mut out = stdout();
+
+        out.write_all(b"testing, 123\r\n")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_guard_file_and_owned_fd() -> io::Result<()> {
+        require_tty!(Ok(()));
+        use std::fs::{self, File};
+        use std::os::unix::io::OwnedFd;
+
+        let _lock = util::TTY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let is_raw = |fd| get_terminal_attr(fd).map(|ios| ios.c_lflag & libc::ICANON == 0);
+
+        let tty: File = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")?;
+        let fd = tty.as_raw_fd();
+        let saved = get_terminal_attr(fd)?.c_lflag;
+        let mut tty = tty.guard_mode()?;
+        tty.set_raw_mode()?;
+        assert!(is_raw(fd)?);
+        tty.modify_mode(|ios| ios)?;
+        assert_eq!(get_terminal_attr(fd)?.c_lflag, saved);
+        drop(tty);
+
+        let (_master, slave) = util::openpty()?;
+        let slave = OwnedFd::from(slave);
+        let fd = slave.as_raw_fd();
+        let raw = slave.into_raw_mode()?;
+        assert!(is_raw(fd)?);
+        // Keep the fd open to look at it after the guard restored it.
+        let dup = raw.try_clone()?;
+        drop(raw);
+        assert!(!is_raw(dup.as_raw_fd())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_saved_and_current_termios() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let mut tty = slave.guard_mode()?;
+        tty.set_raw_mode()?;
+
+        assert_ne!(tty.saved_termios().c_lflag & libc::ICANON, 0);
+        assert_eq!(tty.current_termios()?.c_lflag & libc::ICANON, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_strategy() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let fd = slave.as_raw_fd();
+        let mut ios = get_terminal_attr(fd)?;
+        ios.c_iflag |= libc::ISTRIP | libc::IGNBRK;
+        set_terminal_attr(fd, &ios)?;
+
+        let mut tty = slave.guard_mode()?;
+        tty.update_mode(|ios| ios.c_iflag |= libc::IXANY)?;
+        tty.set_raw_mode()?;
+        let overwritten = get_terminal_attr(fd)?;
+        assert_eq!(overwritten.c_iflag & (libc::ISTRIP | libc::IXANY), 0);
+
+        tty.restore()?;
+        tty.update_mode(|ios| ios.c_iflag |= libc::IXANY)?;
+        tty.set_raw_strategy(RawStrategy::Merge);
+        tty.set_raw_mode()?;
+        let merged = get_terminal_attr(fd)?;
+        let kept = libc::ISTRIP | libc::IGNBRK | libc::IXANY;
+        assert_eq!(merged.c_iflag & kept, kept);
+        assert_eq!(merged.c_iflag & (libc::ICRNL | libc::IXON), 0);
+        assert_eq!(merged.c_lflag & (libc::ICANON | libc::ECHO | libc::ISIG), 0);
+        assert_eq!(merged.c_oflag & libc::OPOST, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let dup = slave.try_clone()?;
+        let tty = TtyWithGuard::builder(slave)
+            .raw()
+            .vmin(0)
+            .vtime(3)
+            .nonblocking(true)
+            .build()?;
+
+        let ios = tty.current_termios()?;
+        assert_eq!(ios.c_lflag & libc::ICANON, 0);
+        assert_eq!((ios.c_cc[libc::VMIN], ios.c_cc[libc::VTIME]), (0, 3));
+        let flags = unsafe { libc::fcntl(dup.as_raw_fd(), libc::F_GETFL) };
+        assert_ne!(flags & libc::O_NONBLOCK, 0);
+
+        drop(tty);
+        let flags = unsafe { libc::fcntl(dup.as_raw_fd(), libc::F_GETFL) };
+        assert_eq!(flags & libc::O_NONBLOCK, 0);
+        assert_ne!(
+            get_terminal_attr(dup.as_raw_fd())?.c_lflag & libc::ICANON,
+            0
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let mut tty = slave.guard_mode()?;
+        tty.set_raw_mode()?;
+        tty.update_mode(|ios| ios.c_cc[libc::VMIN] = 0)?;
+        tty.restore()?;
+
+        assert_eq!(
+            tty.stats(),
+            GuardStats {
+                get_attr_calls: 2,
+                set_attr_calls: 3,
+                suspends: 0,
+                restores: 1,
+                ioctl_calls: 0,
+            }
+        );
+        assert_ne!(tty.current_termios()?.c_lflag & libc::ICANON, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_saved_termios() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let mut tty = slave.guard_mode()?;
+        tty.guard.state().ios.c_lflag ^= libc::ECHO;
+
+        let error = tty.restore().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(tty.set_raw_mode().is_err());
+        tty.guard.state().ios.c_lflag ^= libc::ECHO;
+        tty.restore()
+    }
+
+    #[test]
+    fn test_yield_and_reclaim() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let dup = slave.try_clone()?;
+        let fd = dup.as_raw_fd();
+        let mut tty = slave.guard_mode()?;
+        tty.set_raw_mode()?;
+
+        tty.yield_to_external()?;
+        let mut external = get_terminal_attr(fd)?;
+        assert_ne!(external.c_lflag & libc::ICANON, 0);
+        // The other library turns off echo for good.
+        external.c_lflag &= !libc::ECHO;
+        set_terminal_attr(fd, &external)?;
+
+        tty.reclaim()?;
+        assert_eq!(tty.current_termios()?.c_lflag & libc::ICANON, 0);
+        assert_eq!(tty.saved_termios().c_lflag & libc::ECHO, 0);
+        // Reclaiming again doesn't take the raw mode for the one to restore.
+        tty.reclaim()?;
+        assert_ne!(tty.saved_termios().c_lflag & libc::ICANON, 0);
+        drop(tty);
+        assert_eq!(
+            get_terminal_attr(fd)?.c_lflag & (libc::ICANON | libc::ECHO),
+            libc::ICANON
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reclaim_without_yield() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let dup = slave.try_clone()?;
+        let mut tty = slave.guard_mode()?;
+        tty.set_raw_mode()?;
+        tty.reclaim()?;
+        assert_eq!(tty.current_termios()?.c_lflag & libc::ICANON, 0);
+        drop(tty);
+        assert_ne!(
+            get_terminal_attr(dup.as_raw_fd())?.c_lflag & libc::ICANON,
+            0
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_mode_composes_with_presets() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let mut tty = slave.guard_mode()?;
+        tty.set_raw_mode()?;
+        tty.update_mode(|ios| ios.c_cc[libc::VMIN] = 0)?;
+
+        let ios = tty.current_termios()?;
+        assert_eq!(ios.c_lflag & libc::ICANON, 0);
+        assert_eq!(ios.c_cc[libc::VMIN], 0);
+
+        tty.modify_mode(|ios| ios)?;
+        assert_ne!(tty.current_termios()?.c_lflag & libc::ICANON, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_global_stdin() -> io::Result<()> {
+        require_tty!(Ok(()));
+        let _lock = util::TTY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let first = global_stdin()?;
+        assert!(std::ptr::eq(first, global_stdin()?));
+        let tty = first.lock().unwrap();
+        assert_eq!(tty.as_raw_fd(), libc::STDIN_FILENO);
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_output_scope() -> io::Result<()> {
+        let (mut master, slave) = util::openpty()?;
+        let mut tty = slave.guard_mode()?;
+        let oflag = get_terminal_attr(tty.as_raw_fd())?.c_oflag;
+
+        tty.binary_output_scope(|w| w.write_all(b"\n"))?;
+        assert_eq!(get_terminal_attr(tty.as_raw_fd())?.c_oflag, oflag);
+        tty.write_all(b"\n")?;
+
+        let mut buf = [0; 3];
+        master.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"\n\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_reattach() -> io::Result<()> {
+        #[cfg(feature = "signals")]
+        let _lock = util::EVENTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let (_master, slave) = util::openpty()?;
+        let fd = slave.as_raw_fd();
+        let mut tty = slave.guard_mode()?;
+        tty.set_raw_mode()?;
+        let raw = get_terminal_attr(fd)?;
+        // What a new terminal session would start with.
+        set_terminal_attr(fd, &tty.saved_termios())?;
+        #[cfg(feature = "signals")]
+        let mut events = events::Events::new(&*tty)?;
+
+        let reattached = tty.reattach()?;
+        assert_eq!(get_terminal_attr(fd)?.c_lflag, raw.c_lflag);
+        assert_eq!(reattached.window_size, tty.window_size()?);
+        assert_eq!(reattached.job_control, JobControl::Unavailable);
+        #[cfg(feature = "signals")]
+        assert_eq!(events.next_event()?, events::Event::Reattached);
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let fd = slave.as_raw_fd();
+        let other = slave.try_clone()?;
+        let err = TtyWithGuard::builder(slave.try_clone()?)
+            .strict()
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(
+            StrictViolation::find(&err),
+            Some(&StrictViolation::NoWindowSize)
+        );
+
+        let size = libc::winsize {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        util::convert_to_result(unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) })?;
+        let mut tty = TtyWithGuard::builder(slave).strict().raw().build()?;
+        tty.update_mode(|ios| ios.c_lflag |= libc::ECHO)?;
+
+        let _nested = other.guard_mode()?;
+        let err = tty.set_raw_mode().unwrap_err();
+        assert_eq!(
+            StrictViolation::find(&err),
+            Some(&StrictViolation::NestedGuard)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_echo() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let mut tty = slave.guard_mode()?;
+        tty.set_raw_mode()?;
+        let lflag = get_terminal_attr(tty.as_raw_fd())?.c_lflag;
+        assert_eq!(lflag & libc::ECHO, 0);
+
+        let echoed = tty.with_echo(true, |t| {
+            let inner = get_terminal_attr(t.as_raw_fd())?.c_lflag;
+            assert_eq!(inner, lflag | libc::ECHO);
+            Ok(inner)
+        })?;
+        assert_ne!(echoed & libc::ECHO, 0);
+        assert_eq!(get_terminal_attr(tty.as_raw_fd())?.c_lflag, lflag);
+
+        let err = tty.with_echo(true, |_| -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::Interrupted, "failed"))
+        });
+        assert!(err.is_err());
+        assert_eq!(get_terminal_attr(tty.as_raw_fd())?.c_lflag, lflag);
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_hooks() -> io::Result<()> {
+        use std::sync::{Arc, Mutex};
+
+        let (_master, slave) = util::openpty()?;
+        let fd = slave.as_raw_fd();
+        let mut tty = slave.guard_mode()?;
+        tty.set_raw_mode()?;
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        for &order in &[HookOrder::AfterRestore, HookOrder::BeforeRestore] {
+            let calls = calls.clone();
+            tty.on_restore(order, move || {
+                let raw = get_terminal_attr(fd).unwrap().c_lflag & libc::ICANON == 0;
+                calls.lock().unwrap().push((order, raw));
+            });
+        }
+        // Not for the restores which keep the guard.
+        tty.restore()?;
+        tty.set_raw_mode()?;
+        tty.yield_to_external()?;
+        tty.reclaim()?;
+        assert!(calls.lock().unwrap().is_empty());
+        drop(tty);
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                (HookOrder::BeforeRestore, true),
+                (HookOrder::AfterRestore, false)
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_with_soft_reset() -> io::Result<()> {
+        let (mut master, slave) = util::openpty()?;
+        let mut tty = slave.guard_mode()?;
+        tty.set_raw_mode()?;
+        tty.restore_with_soft_reset();
+        drop(tty);
+
+        let mut buf = [0; 8];
+        master.read_exact(&mut buf)?;
+        assert_eq!(&buf, SOFT_RESET);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_available() -> io::Result<()> {
+        let (mut master, slave) = util::openpty()?;
+        let mut tty = slave.into_raw_mode()?;
+        tty.set_buffer_size(8);
+
+        master.write_all(&[b'x'; 100])?;
+        // Wait for the input to arrive at the slave side.
+        util::wait_readable(tty.as_raw_fd(), None)?;
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        let mut buf = b"abc".to_vec();
+        assert_eq!(tty.read_available(&mut buf)?, 100);
+        assert_eq!(buf.len(), 103);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_exact_timeout() -> io::Result<()> {
+        let (mut master, slave) = util::openpty()?;
+        let mut tty = slave.into_raw_mode()?;
+        let soon = || Instant::now() + Duration::from_millis(100);
+
+        master.write_all(b"abc")?;
+        let mut buf = [0; 3];
+        tty.read_exact_timeout(&mut buf, soon())?;
+        assert_eq!(&buf, b"abc");
+
+        master.write_all(b"de")?;
+        let mut buf = [0; 4];
+        let err = tty.read_exact_timeout(&mut buf, soon()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        let timed_out = ReadTimedOut::find(&err).unwrap();
+        assert_eq!((timed_out.read(), timed_out.wanted()), (2, 4));
+        assert_eq!(&buf[..2], b"de");
+        Ok(())
+    }
+
+    #[test]
+    fn test_hangup_reads_as_eof() -> io::Result<()> {
+        // Linux reports a hangup of a pty as EIO on the master side.
+        let (master, slave) = util::openpty()?;
+        let mut tty = master.into_raw_mode()?;
+        drop(slave);
+
+        let mut buf = [0; 8];
+        assert_eq!(tty.read(&mut buf)?, 0);
+        tty.set_eof_on_hangup(false);
+        assert_eq!(
+            tty.read(&mut buf).unwrap_err().raw_os_error(),
+            Some(libc::EIO)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_8bit_clean() -> io::Result<()> {
+        let (mut master, slave) = util::openpty()?;
+        let mut tty = slave.guard_mode()?;
+        tty.set_8bit_clean()?;
+
+        let ios = get_terminal_attr(tty.as_raw_fd())?;
+        assert_eq!(ios.c_iflag & (libc::ISTRIP | libc::INPCK | libc::ICRNL), 0);
+        assert_eq!(ios.c_cflag & libc::CSIZE, libc::CS8);
+
+        master.write_all(b"\r\xff")?;
+        let mut buf = [0; 2];
+        tty.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"\r\xff");
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_a_tty_and_fallback() -> io::Result<()> {
+        use std::fs::File;
+        use std::os::unix::io::FromRawFd;
+
+        let _lock = util::TTY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut fds = [-1; 2];
+        util::convert_to_result(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+        let (input, _writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+        let error = input.try_clone()?.into_raw_mode().err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let not_a_tty = *NotATty::find(&error).unwrap();
+        assert_ne!(not_a_tty.fd(), -1);
+        let controlling_tty = not_a_tty.controlling_tty_available();
+
+        match input.into_raw_mode().or_controlling_tty() {
+            Ok(raw) => {
+                assert!(controlling_tty);
+                assert!(matches!(**raw, Fallback::ControllingTty(_)));
+            }
+            Err(e) => {
+                assert!(!controlling_tty);
+                assert!(NotATty::find(&e).is_some());
+            }
+        }
+
+        let (_master, slave) = util::openpty()?;
+        let tty = slave.guard_mode().or_controlling_tty()?;
+        assert!(matches!(*tty, Fallback::Original(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_job_control() -> io::Result<()> {
+        // Like the serial console of an init-like program, the pty isn't the controlling
+        // terminal of this process.
+        let (_master, slave) = util::openpty()?;
+        let dup = slave.try_clone()?;
+        let mut console = slave.into_raw_mode()?;
+        assert_eq!(console.job_control()?, JobControl::Unavailable);
+        console.restore()?;
+        console.set_raw_mode()?;
+
+        // There is no shell to return to.
+        let error = console.suspend_process().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(console.stats().suspends, 0);
+        assert_eq!(
+            get_terminal_attr(dup.as_raw_fd())?.c_lflag & libc::ICANON,
+            0
+        );
+        drop(console);
+        assert_ne!(
+            get_terminal_attr(dup.as_raw_fd())?.c_lflag & libc::ICANON,
+            0
+        );
+
+        require_tty!(Ok(()));
+        let _lock = util::TTY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let tty = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")?
+            .guard_mode()?;
+        assert_ne!(tty.job_control()?, JobControl::Unavailable);
+        Ok(())
+    }
+
+    #[test]
+    fn test_exit_restores_guards() -> io::Result<()> {
+        if std::env::var_os("RAW_TTY_TEST_EXIT").is_some() {
+            let (_master, slave) = util::openpty()?;
+            let fd = slave.as_raw_fd();
+            let mut tty = slave.into_raw_mode()?;
+            tty.on_restore(HookOrder::AfterRestore, move || {
+                let ios = get_terminal_attr(fd).unwrap();
+                println!("restored, cooked: {}", ios.c_lflag & libc::ICANON != 0);
+            });
+            exit(7);
+        }
+
+        let output = std::process::Command::new(std::env::current_exe()?)
+            .args(["--exact", "test::test_exit_restores_guards", "--nocapture"])
+            .env("RAW_TTY_TEST_EXIT", "1")
+            .output()?;
+        assert_eq!(output.status.code(), Some(7));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("restored, cooked: true"), "{}", stdout);
+        Ok(())
+    }
+
+    #[test]
+    fn test_termios_conversions() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let mut tty = slave.guard_mode()?;
+        tty.modify_mode(|mut ios| {
+            ios.c_lflag &= !libc::ECHO;
+            ios
+        })?;
+        let ios = tty.current_termios()?;
+        assert_eq!(ios.c_lflag & libc::ECHO, 0);
+
+        let raw: libc::termios = ios.into();
+        assert_eq!(raw.c_lflag, ios.as_libc().c_lflag);
+        assert_eq!(Termios::from_libc(raw).c_lflag, ios.c_lflag);
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_verified() -> io::Result<()> {
+        use crate::testing::FakeTty;
+        use std::sync::mpsc;
+
+        let tty = FakeTty::new();
+        let mut guard = TtyModeGuard::new(tty.fd())?;
+        guard.set_raw_mode()?;
+        tty.ignore_mode_changes(2);
+        drop(guard);
+        assert_ne!(tty.termios().c_lflag & libc::ICANON, 0);
+
+        let (sender, receiver) = mpsc::channel();
+        let mut guard = TtyModeGuard::new(tty.fd())?;
+        guard.set_raw_mode()?;
+        guard.set_error_policy(ErrorPolicy::Report(Box::new(move |e| {
+            sender.send(e.to_string()).unwrap()
+        })));
+        tty.ignore_mode_changes(RESTORE_ATTEMPTS);
+        drop(guard);
+        assert!(receiver.try_recv().unwrap().contains("didn't take"));
+        assert_eq!(tty.termios().c_lflag & libc::ICANON, 0);
+        Ok(())
+    }
+
+    #[test]
+    // The request type is `u64` on glibc, but not with every libc.
+    #[allow(clippy::unnecessary_cast)]
+    fn test_ioctl() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let mut tty = slave.guard_mode()?;
+        let mut size = libc::winsize {
+            ws_row: 12,
+            ws_col: 34,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        unsafe { tty.ioctl(libc::TIOCSWINSZ as u64, &mut size)? };
+        assert_eq!(tty.window_size()?.cols, 34);
+        let bogus = unsafe { tty.ioctl(0, std::ptr::null_mut::<u8>()) };
+        assert!(bogus.is_err());
+
+        assert_eq!(tty.stats().ioctl_calls, 2);
+        let history = tty.ioctl_history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].request, libc::TIOCSWINSZ as u64);
+        assert_eq!(history[0].errno, None);
+        assert!(history[1].errno.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_history() -> io::Result<()> {
+        use crate::testing::FakeTty;
+
+        let tty = FakeTty::new();
+        let mut guard = TtyModeGuard::new(tty.fd())?;
+        guard.set_raw_mode()?;
+        assert!(guard.history().is_empty());
+        guard.enable_history(2);
+        guard.restore()?;
+        guard.set_raw_mode()?;
+        guard.update_mode(|ios| ios.c_cc[libc::VMIN] = 0)?;
+
+        let history = guard.history();
+        let what: Vec<_> = history.iter().map(|t| t.what).collect();
+        assert_eq!(what, ["set_raw_mode", "update_mode"]);
+        assert_eq!(history[0].before.c_lflag, guard.saved_termios().c_lflag);
+        assert_eq!(history[1].after.c_lflag, tty.termios().c_lflag);
+        let line = history[0].to_string();
+        assert!(line.contains("set_raw_mode: iflag -"), "{}", line);
+        assert!(history[1]
+            .to_string()
+            .ends_with(&format!("update_mode: cc[{}] 1->0", libc::VMIN)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_guard_ref() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        {
+            let mut guard = TtyModeGuardRef::new(&slave)?;
+            guard.set_raw_mode()?;
+            assert_eq!(guard.as_raw_fd(), slave.as_raw_fd());
+            assert_eq!(
+                get_terminal_attr(slave.as_raw_fd())?.c_lflag & libc::ICANON,
+                0
+            );
+        }
+        assert_ne!(
+            get_terminal_attr(slave.as_raw_fd())?.c_lflag & libc::ICANON,
+            0
+        );
         Ok(())
     }
 }
@@ -0,0 +1,356 @@
+//! Editing a single line of input in raw mode.
+//!
+//! Switching to raw mode also turns off the line editing of the terminal driver, so even a
+//! simple prompt loses backspace and the arrow keys. [`LineEditor`] brings back the basics
+//! on top of the [`Decoder`]: inserting, deleting, moving the cursor and optionally
+//! recalling previous lines.
+//!
+//! ```no_run
+//! use raw_tty::line::{Edit, LineEditor};
+//! use raw_tty::IntoRawMode;
+//! use std::io::{self, stdin, stdout};
+//!
+//! fn main() -> io::Result<()> {
+//!     let mut stdin = stdin().into_raw_mode()?;
+//!     let mut editor = LineEditor::with_termios(&stdin.saved_termios());
+//!     editor.enable_history();
+//!     while let Edit::Done(line) = editor.read_line("> ", &mut stdin, &mut stdout())? {
+//!         print!("you typed {:?}\r\n", line);
+//!     }
+//!     Ok(())
+//! }
+//! ```
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::input::{Decoder, InputEvent, Key, KeyEvent, KeyEventKind, Modifiers};
+use crate::prompt::ESCAPE_TIMEOUT;
+use crate::util::wait_readable;
+use crate::Termios;
+
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+
+/// The state of the line after handling an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    /// The line is still being edited.
+    Pending,
+    /// Enter was pressed.
+    Done(String),
+    /// Ctrl+C was pressed.
+    Interrupted,
+    /// Ctrl+D was pressed on an empty line.
+    Eof,
+}
+
+/// A single line of input being edited, see the [module docs](self).
+///
+/// Every character is assumed to take up one column.
+#[derive(Debug, Default)]
+pub struct LineEditor {
+    line: Vec<char>,
+    cursor: usize,
+    history: Option<Vec<String>>,
+    /// The position in the history while recalling, with the line being edited before.
+    recalling: Option<(usize, Vec<char>)>,
+    erase: Option<KeyEvent>,
+    kill: Option<KeyEvent>,
+    decoder: Decoder,
+}
+
+/// The key which sends `byte`, if it is a single key.
+fn key_for(byte: libc::cc_t) -> Option<KeyEvent> {
+    // _POSIX_VDISABLE
+    if byte == 0 {
+        return None;
+    }
+    let mut decoder = Decoder::new();
+    decoder.feed(&[byte]);
+    match decoder.next_event() {
+        Some(InputEvent::Key(key)) => Some(key),
+        _ => None,
+    }
+}
+
+impl LineEditor {
+    pub fn new() -> LineEditor {
+        LineEditor::default()
+    }
+
+    /// Creates an editor which also honors the erase (`VERASE`) and kill (`VKILL`)
+    /// characters of `ios`, usually the termios from before switching to raw mode.
+    pub fn with_termios(ios: &Termios) -> LineEditor {
+        LineEditor {
+            erase: key_for(ios.c_cc[libc::VERASE]),
+            kill: key_for(ios.c_cc[libc::VKILL]),
+            ..LineEditor::default()
+        }
+    }
+
+    /// Remembers finished lines, to be recalled with Up and Down.
+    pub fn enable_history(&mut self) {
+        self.history.get_or_insert_with(Vec::new);
+    }
+
+    /// The remembered lines, oldest first. Empty unless history is enabled.
+    pub fn history(&self) -> &[String] {
+        self.history.as_deref().unwrap_or(&[])
+    }
+
+    /// The line as currently edited.
+    pub fn line(&self) -> String {
+        self.line.iter().collect()
+    }
+
+    /// The position of the cursor, in characters.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Applies one input event to the line.
+    pub fn handle(&mut self, event: &InputEvent) -> Edit {
+        let key = match *event {
+            InputEvent::Key(key) if key.kind != KeyEventKind::Release => key,
+            _ => return Edit::Pending,
+        };
+        let ctrl = |c| KeyEvent::new(Key::Char(c), Modifiers::CTRL);
+        let plain = KeyEvent {
+            kind: KeyEventKind::Press,
+            ..key
+        };
+
+        if Some(plain) == self.erase {
+            self.backspace();
+        } else if Some(plain) == self.kill || plain == ctrl('u') {
+            self.line.drain(..self.cursor);
+            self.cursor = 0;
+        } else if plain == ctrl('c') {
+            self.clear();
+            return Edit::Interrupted;
+        } else if plain == ctrl('d') {
+            if self.line.is_empty() {
+                return Edit::Eof;
+            }
+            self.delete();
+        } else if plain == ctrl('a') {
+            self.cursor = 0;
+        } else if plain == ctrl('e') {
+            self.cursor = self.line.len();
+        } else if plain == ctrl('b') {
+            self.cursor = self.cursor.saturating_sub(1);
+        } else if plain == ctrl('f') {
+            self.cursor = (self.cursor + 1).min(self.line.len());
+        } else {
+            match key.key {
+                Key::Enter => return Edit::Done(self.finish()),
+                Key::Backspace => self.backspace(),
+                Key::Delete => self.delete(),
+                Key::Left => self.cursor = self.cursor.saturating_sub(1),
+                Key::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+                Key::Home => self.cursor = 0,
+                Key::End => self.cursor = self.line.len(),
+                Key::Up => self.recall(true),
+                Key::Down => self.recall(false),
+                Key::Char(c)
+                    if !key.modifiers.contains(Modifiers::CTRL)
+                        && !key.modifiers.contains(Modifiers::ALT) =>
+                {
+                    self.line.insert(self.cursor, c);
+                    self.cursor += 1;
+                }
+                _ => {}
+            }
+        }
+        Edit::Pending
+    }
+
+    /// Redraws the line with `prompt` in front of it, on the current row of `out`.
+    pub fn render<W: Write>(&self, prompt: &str, out: &mut W) -> io::Result<()> {
+        let back = self.line.len() - self.cursor;
+        out.write_all(frame(prompt, &self.line(), back).as_bytes())?;
+        out.flush()
+    }
+
+    /// Reads and edits a line from a raw mode `input`, drawing it on `output`, until it is
+    /// finished.
+    pub fn read_line<R, W>(
+        &mut self,
+        prompt: &str,
+        input: &mut R,
+        output: &mut W,
+    ) -> io::Result<Edit>
+    where
+        R: Read + AsRawFd,
+        W: Write,
+    {
+        let mut buf = [0; 64];
+        loop {
+            self.render(prompt, output)?;
+            let n = input.read(&mut buf)?;
+            if n == 0 {
+                return Ok(Edit::Eof);
+            }
+            self.decoder.feed(&buf[..n]);
+            loop {
+                let event = match self.decoder.next_event() {
+                    Some(event) => event,
+                    None if self.decoder.has_pending()
+                        && !wait_readable(input.as_raw_fd(), Some(ESCAPE_TIMEOUT))? =>
+                    {
+                        match self.decoder.flush_pending() {
+                            Some(event) => event,
+                            None => break,
+                        }
+                    }
+                    None => break,
+                };
+                match self.handle(&event) {
+                    Edit::Pending => {}
+                    edit => {
+                        if let Edit::Done(ref line) = edit {
+                            output.write_all(frame(prompt, line, 0).as_bytes())?;
+                        }
+                        output.write_all(b"\r\n")?;
+                        output.flush()?;
+                        return Ok(edit);
+                    }
+                }
+            }
+        }
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.line.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.line.len() {
+            self.line.remove(self.cursor);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.line.clear();
+        self.cursor = 0;
+        self.recalling = None;
+    }
+
+    fn finish(&mut self) -> String {
+        let line = self.line();
+        if let Some(ref mut history) = self.history {
+            if !line.is_empty() && history.last() != Some(&line) {
+                history.push(line.clone());
+            }
+        }
+        self.clear();
+        line
+    }
+
+    /// Moves one entry back or forward in the history.
+    fn recall(&mut self, back: bool) {
+        let len = self.history().len();
+        let index = match (self.recalling.as_ref().map(|r| r.0), back) {
+            (None, true) if len > 0 => len - 1,
+            (Some(index), true) => index.saturating_sub(1),
+            (Some(index), false) if index + 1 < len => index + 1,
+            (Some(_), false) => {
+                // Past the newest entry, back to what was being edited.
+                if let Some((_, line)) = self.recalling.take() {
+                    self.line = line;
+                    self.cursor = self.line.len();
+                }
+                return;
+            }
+            _ => return,
+        };
+        if self.recalling.is_none() {
+            self.recalling = Some((index, self.line.clone()));
+        }
+        if let Some(recalling) = self.recalling.as_mut() {
+            recalling.0 = index;
+        }
+        self.line = self.history()[index].chars().collect();
+        self.cursor = self.line.len();
+    }
+}
+
+/// Draws `line` after `prompt` over the current row, with the cursor `back` characters from
+/// the end.
+fn frame(prompt: &str, line: &str, back: usize) -> String {
+    let mut frame = format!("\r{}{}\x1b[K", prompt, line);
+    if back > 0 {
+        frame.push_str(&format!("\x1b[{}D", back));
+    }
+    frame
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{util, IntoRawMode};
+
+    fn type_keys(editor: &mut LineEditor, bytes: &[u8]) -> Vec<Edit> {
+        let mut decoder = Decoder::new();
+        decoder.feed(bytes);
+        let mut edits = Vec::new();
+        while let Some(event) = decoder.next_event() {
+            match editor.handle(&event) {
+                Edit::Pending => {}
+                edit => edits.push(edit),
+            }
+        }
+        edits
+    }
+
+    #[test]
+    fn test_editing_and_history() {
+        let mut editor = LineEditor::new();
+        editor.enable_history();
+        assert_eq!(
+            type_keys(&mut editor, b"helo\x1b[Dl\x1b[Fs\x7f!\r"),
+            vec![Edit::Done("hello!".into())]
+        );
+        assert_eq!(
+            type_keys(&mut editor, b"abc\x01x\x1b[3~\r"),
+            vec![Edit::Done("xbc".into())]
+        );
+
+        type_keys(&mut editor, b"draft\x1b[A\x1b[A");
+        assert_eq!(editor.line(), "hello!");
+        type_keys(&mut editor, b"\x1b[B\x1b[B");
+        assert_eq!(editor.line(), "draft");
+        assert_eq!(
+            type_keys(&mut editor, b"\x03\x04"),
+            vec![Edit::Interrupted, Edit::Eof]
+        );
+    }
+
+    #[test]
+    fn test_erase_character() {
+        let mut ios = Termios::default();
+        // Ctrl+W as the erase character.
+        ios.c_cc[libc::VERASE] = 0x17;
+        let mut editor = LineEditor::with_termios(&ios);
+        assert_eq!(
+            type_keys(&mut editor, b"ab\x17c\r"),
+            vec![Edit::Done("ac".into())]
+        );
+    }
+
+    #[test]
+    fn test_read_line() -> io::Result<()> {
+        let (mut master, slave) = util::openpty()?;
+        let mut input = slave.into_raw_mode()?;
+        master.write_all(b"hi\x7fo\r")?;
+
+        let mut output = Vec::new();
+        let edit = LineEditor::new().read_line("> ", &mut input, &mut output)?;
+        assert_eq!(edit, Edit::Done("ho".into()));
+        assert!(output.ends_with(b"\r> ho\x1b[K\r\n"));
+        Ok(())
+    }
+}
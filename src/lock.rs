@@ -0,0 +1,111 @@
+//! An advisory lock on a terminal, for processes which take turns changing its mode.
+
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+/// An advisory `flock` on a terminal device, released when dropped.
+///
+/// When a TUI spawns a helper which brings up a TUI of its own, both change the mode of
+/// the same terminal and their changes interleave. If every cooperating process takes
+/// the lock before saving the mode and only releases it after restoring it, e.g. through
+/// [`SessionBuilder::lock`](crate::session::SessionBuilder::lock), the helper's session
+/// waits until the parent is done, and the other way around. Processes which don't take
+/// the lock aren't held up.
+///
+/// The device is opened again for the lock. A lock on the fd itself would be shared with
+/// every process that inherited it, which is exactly the helper.
+///
+/// ```no_run
+/// use raw_tty::{IntoRawMode, TtyLock};
+/// use std::io::{self, stdin};
+///
+/// fn main() -> io::Result<()> {
+///     let _lock = TtyLock::acquire(&stdin())?;
+///     let _stdin = stdin().into_raw_mode()?;
+///     // The mode is restored before the lock is released.
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct TtyLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl TtyLock {
+    /// Blocks until the lock on the terminal `tty` refers to is available and takes it.
+    pub fn acquire<T: AsRawFd>(tty: &T) -> io::Result<TtyLock> {
+        let lock = TtyLock::open(tty.as_raw_fd())?;
+        lock.flock(libc::LOCK_EX)?;
+        Ok(lock)
+    }
+
+    /// Takes the lock if nobody else holds it, returning `None` otherwise.
+    pub fn try_acquire<T: AsRawFd>(tty: &T) -> io::Result<Option<TtyLock>> {
+        let lock = TtyLock::open(tty.as_raw_fd())?;
+        match lock.flock(libc::LOCK_EX | libc::LOCK_NB) {
+            Ok(()) => Ok(Some(lock)),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The path of the locked terminal device.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn open(fd: RawFd) -> io::Result<TtyLock> {
+        let mut name = [0 as libc::c_char; 256];
+        match unsafe { libc::ttyname_r(fd, name.as_mut_ptr(), name.len()) } {
+            0 => {}
+            errno => return Err(io::Error::from_raw_os_error(errno)),
+        }
+        let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+        let path = PathBuf::from(std::ffi::OsStr::from_bytes(name.to_bytes()));
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&path)?;
+        Ok(TtyLock { file, path })
+    }
+
+    fn flock(&self, operation: libc::c_int) -> io::Result<()> {
+        loop {
+            match unsafe { libc::flock(self.file.as_raw_fd(), operation) } {
+                -1 => match io::Error::last_os_error() {
+                    ref e if e.kind() == io::ErrorKind::Interrupted => continue,
+                    e => return Err(e),
+                },
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util;
+
+    #[test]
+    fn test_exclusive() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let lock = TtyLock::acquire(&slave)?;
+        assert!(lock.path().starts_with("/dev"));
+        // Even an inherited fd can't take the lock.
+        let inherited = slave.try_clone()?;
+        assert!(TtyLock::try_acquire(&inherited)?.is_none());
+        drop(lock);
+        assert!(TtyLock::try_acquire(&inherited)?.is_some());
+
+        let null = File::open("/dev/null")?;
+        assert!(TtyLock::acquire(&null).is_err());
+        Ok(())
+    }
+}
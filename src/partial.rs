@@ -0,0 +1,335 @@
+//! Guards which own only the input or only the output side of the mode.
+//!
+//! A program which reads keys from the tty but writes its output to a pipe has no business
+//! restoring the output flags, and the other way around: another process sharing the
+//! terminal, e.g. the reader at the end of the pipe, may have changed them in the
+//! meantime. An [`InputGuard`] saves and restores the input flags, the local flags and the
+//! control characters; an [`OutputGuard`] only the output flags. When restoring, the
+//! current mode is read again and only the owned groups are replaced.
+//!
+//! [`OutputTweaks`] goes further for write-only programs like progress bars: it only
+//! restores the few flags it was asked to change.
+//!
+//! Unlike [`TtyModeGuard`](crate::TtyModeGuard), these aren't restored by
+//! [`exit`](crate::exit). They borrow the tty, like
+//! [`TtyModeGuardRef`](crate::TtyModeGuardRef), so the fd can't be closed while they are
+//! alive, and failures when restoring on drop are ignored: call `restore` to see them.
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::attr::{get_terminal_attr, raw_terminal_attr, set_terminal_attr};
+use crate::{OutputFlags, Termios};
+
+use libc::tcflag_t;
+use std::io;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+
+/// The parts of a termios a partial guard owns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Groups {
+    /// `c_iflag`, `c_lflag` and `c_cc`.
+    Input,
+    /// `c_oflag`.
+    Output,
+}
+
+impl Groups {
+    /// `current` with the owned groups taken from `owned`.
+    fn merge(self, current: &Termios, owned: &Termios) -> Termios {
+        let mut merged = *current;
+        match self {
+            Groups::Input => {
+                merged.c_iflag = owned.c_iflag;
+                merged.c_lflag = owned.c_lflag;
+                merged.c_cc = owned.c_cc;
+            }
+            Groups::Output => merged.c_oflag = owned.c_oflag,
+        }
+        merged
+    }
+}
+
+#[derive(Debug)]
+struct Partial<'fd> {
+    fd: BorrowedFd<'fd>,
+    groups: Groups,
+    saved: Termios,
+}
+
+impl<'fd> Partial<'fd> {
+    fn new(fd: BorrowedFd<'fd>, groups: Groups) -> io::Result<Partial<'fd>> {
+        Ok(Partial {
+            fd,
+            groups,
+            saved: get_terminal_attr(fd.as_raw_fd())?,
+        })
+    }
+
+    fn raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+
+    /// Applies the owned groups of `f(current mode)`, leaving the others as they are now.
+    fn modify(&self, f: impl FnOnce(&mut Termios)) -> io::Result<()> {
+        let current = get_terminal_attr(self.raw_fd())?;
+        let mut ios = current;
+        f(&mut ios);
+        set_terminal_attr(self.raw_fd(), &self.groups.merge(&current, &ios))
+    }
+
+    fn restore(&self) -> io::Result<()> {
+        let saved = self.saved;
+        self.modify(|ios| *ios = saved)
+    }
+}
+
+impl Drop for Partial<'_> {
+    fn drop(&mut self) {
+        let _ = self.restore();
+    }
+}
+
+/// Saves the input side of the mode of a tty and restores it on drop, see the
+/// [module docs](self).
+///
+/// ```no_run
+/// use raw_tty::InputGuard;
+/// use std::io::{self, stdin, Read};
+///
+/// fn main() -> io::Result<()> {
+///     let stdin = stdin();
+///     let mut guard = InputGuard::new(&stdin)?;
+///     guard.set_raw_mode()?;
+///     let mut key = [0];
+///     stdin.lock().read_exact(&mut key)?;
+///     println!("{:?}", key);
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct InputGuard<'fd>(Partial<'fd>);
+
+impl<'fd> InputGuard<'fd> {
+    pub fn new<T: AsFd>(tty: &'fd T) -> io::Result<InputGuard<'fd>> {
+        Partial::new(tty.as_fd(), Groups::Input).map(InputGuard)
+    }
+
+    /// Switches the input side to raw mode: no line editing, echo or signal keys.
+    pub fn set_raw_mode(&mut self) -> io::Result<()> {
+        self.0.modify(raw_terminal_attr)
+    }
+
+    /// Passes the current mode to `f` and applies the input flags, local flags and control
+    /// characters it leaves behind. Other changes are ignored.
+    pub fn modify_mode<F: FnOnce(&mut Termios)>(&mut self, f: F) -> io::Result<()> {
+        self.0.modify(f)
+    }
+
+    /// Applies the saved input side again right away.
+    pub fn restore(&mut self) -> io::Result<()> {
+        self.0.restore()
+    }
+}
+
+/// Saves the output side of the mode of a tty and restores it on drop, see the
+/// [module docs](self).
+#[derive(Debug)]
+pub struct OutputGuard<'fd>(Partial<'fd>);
+
+impl<'fd> OutputGuard<'fd> {
+    pub fn new<T: AsFd>(tty: &'fd T) -> io::Result<OutputGuard<'fd>> {
+        Partial::new(tty.as_fd(), Groups::Output).map(OutputGuard)
+    }
+
+    /// Switches off output processing (`OPOST`), so that `\n` is no longer written as
+    /// `\r\n`.
+    pub fn set_raw_mode(&mut self) -> io::Result<()> {
+        self.0.modify(|ios| ios.c_oflag &= !libc::OPOST)
+    }
+
+    /// Passes the current mode to `f` and applies the output flags it leaves behind.
+    /// Other changes are ignored.
+    pub fn modify_mode<F: FnOnce(&mut Termios)>(&mut self, f: F) -> io::Result<()> {
+        self.0.modify(f)
+    }
+
+    /// Applies the saved output flags again right away.
+    pub fn restore(&mut self) -> io::Result<()> {
+        self.0.restore()
+    }
+}
+
+/// Changes to how output shows up, e.g. on stderr while drawing a progress bar, which
+/// are undone on drop.
+///
+/// Only the flags changed through it are restored, to what they were when it was
+/// created; everything else, in particular the whole input side, is never touched. If
+/// nothing was changed, dropping doesn't call into the terminal at all.
+///
+/// ```no_run
+/// use raw_tty::OutputTweaks;
+/// use std::io::{self, stderr, Write};
+///
+/// fn main() -> io::Result<()> {
+///     let stderr = stderr();
+///     let mut tweaks = OutputTweaks::new(&stderr)?;
+///     // Typed control characters don't show up as ^C in the bar.
+///     tweaks.set_echoctl(false)?;
+///     for percent in 0..=100 {
+///         write!(stderr.lock(), "\r{:3}%", percent)?;
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct OutputTweaks<'fd> {
+    fd: BorrowedFd<'fd>,
+    saved: Termios,
+    /// The output and local flags changed so far.
+    changed: (tcflag_t, tcflag_t),
+}
+
+impl<'fd> OutputTweaks<'fd> {
+    pub fn new<T: AsFd>(tty: &'fd T) -> io::Result<OutputTweaks<'fd>> {
+        let fd = tty.as_fd();
+        Ok(OutputTweaks {
+            fd,
+            saved: get_terminal_attr(fd.as_raw_fd())?,
+            changed: (0, 0),
+        })
+    }
+
+    /// Switches the output flags `flags` on or off.
+    pub fn set_output_flags(&mut self, flags: OutputFlags, on: bool) -> io::Result<()> {
+        self.tweak(flags.0, 0, on)
+    }
+
+    /// Switches writing `\n` as `\r\n` (`ONLCR`) on or off.
+    pub fn set_onlcr(&mut self, on: bool) -> io::Result<()> {
+        self.tweak(libc::ONLCR, 0, on)
+    }
+
+    /// Switches echoing control characters as `^X` (`ECHOCTL`) on or off. While it is off,
+    /// keys like Ctrl-C don't leave their echo in the middle of the output.
+    pub fn set_echoctl(&mut self, on: bool) -> io::Result<()> {
+        self.tweak(0, libc::ECHOCTL, on)
+    }
+
+    fn tweak(&mut self, oflag: tcflag_t, lflag: tcflag_t, on: bool) -> io::Result<()> {
+        let mut ios = get_terminal_attr(self.fd.as_raw_fd())?;
+        if on {
+            ios.c_oflag |= oflag;
+            ios.c_lflag |= lflag;
+        } else {
+            ios.c_oflag &= !oflag;
+            ios.c_lflag &= !lflag;
+        }
+        set_terminal_attr(self.fd.as_raw_fd(), &ios)?;
+        self.changed.0 |= oflag;
+        self.changed.1 |= lflag;
+        Ok(())
+    }
+
+    /// Puts the changed flags back right away.
+    pub fn restore(&mut self) -> io::Result<()> {
+        let (oflag, lflag) = self.changed;
+        if (oflag, lflag) == (0, 0) {
+            return Ok(());
+        }
+        let mut ios = get_terminal_attr(self.fd.as_raw_fd())?;
+        ios.c_oflag = (ios.c_oflag & !oflag) | (self.saved.c_oflag & oflag);
+        ios.c_lflag = (ios.c_lflag & !lflag) | (self.saved.c_lflag & lflag);
+        set_terminal_attr(self.fd.as_raw_fd(), &ios)?;
+        self.changed = (0, 0);
+        Ok(())
+    }
+}
+
+impl Drop for OutputTweaks<'_> {
+    fn drop(&mut self) {
+        let _ = self.restore();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util;
+
+    #[test]
+    fn test_restore_owned_groups_only() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let fd = slave.as_raw_fd();
+        let saved = get_terminal_attr(fd)?;
+
+        let mut input = InputGuard::new(&slave)?;
+        input.set_raw_mode()?;
+        let raw = get_terminal_attr(fd)?;
+        assert_eq!(raw.c_lflag & libc::ICANON, 0);
+        assert_eq!(raw.c_oflag, saved.c_oflag);
+        // Another process switches off output processing in the meantime.
+        let mut external = raw;
+        external.c_oflag &= !libc::OPOST;
+        set_terminal_attr(fd, &external)?;
+        drop(input);
+        let ios = get_terminal_attr(fd)?;
+        assert_eq!(ios.c_lflag, saved.c_lflag);
+        assert_eq!(ios.c_oflag, external.c_oflag);
+
+        let mut output = OutputGuard::new(&slave)?;
+        output.modify_mode(|ios| {
+            ios.c_oflag |= libc::OPOST;
+            ios.c_lflag &= !libc::ECHO;
+        })?;
+        let ios = get_terminal_attr(fd)?;
+        assert_ne!(ios.c_oflag & libc::OPOST, 0);
+        assert_ne!(ios.c_lflag & libc::ECHO, 0);
+        drop(output);
+        assert_eq!(get_terminal_attr(fd)?.c_oflag, external.c_oflag);
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_after_hangup() -> io::Result<()> {
+        let (master, slave) = util::openpty()?;
+        let mut input = InputGuard::new(&slave)?;
+        input.set_raw_mode()?;
+        let output = OutputGuard::new(&slave)?;
+        let mut tweaks = OutputTweaks::new(&slave)?;
+        tweaks.set_onlcr(false)?;
+        drop(master);
+        drop(tweaks);
+        drop(output);
+        drop(input);
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_tweaks() -> io::Result<()> {
+        let (_master, slave) = util::openpty()?;
+        let fd = slave.as_raw_fd();
+        let saved = get_terminal_attr(fd)?;
+        assert_ne!(saved.c_oflag & libc::ONLCR, 0);
+
+        let mut tweaks = OutputTweaks::new(&slave)?;
+        tweaks.set_onlcr(false)?;
+        tweaks.set_echoctl(false)?;
+        let ios = get_terminal_attr(fd)?;
+        assert_eq!(ios.c_oflag & libc::ONLCR, 0);
+        assert_eq!(ios.c_lflag & libc::ECHOCTL, 0);
+        // Someone else switches to raw input in the meantime.
+        let mut external = ios;
+        external.c_lflag &= !libc::ICANON;
+        external.c_oflag &= !libc::OPOST;
+        set_terminal_attr(fd, &external)?;
+
+        drop(tweaks);
+        let ios = get_terminal_attr(fd)?;
+        assert_eq!(ios.c_oflag, external.c_oflag | libc::ONLCR);
+        assert_eq!(
+            ios.c_lflag,
+            external.c_lflag | (saved.c_lflag & libc::ECHOCTL)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+//! Blocking helpers for the most common interactions of small programs: reading a single
+//! key, "press any key to continue" and yes/no questions.
+//!
+//! The tty is expected to be in raw mode. Input is read one byte at a time, so nothing
+//! typed after the key in question is consumed.
+//!
+//! Games and other programs which render frames instead of waiting for input use a
+//! [`KeyPoller`] instead, which never blocks.
+//!
+//! ```no_run
+//! use raw_tty::prompt;
+//! use raw_tty::IntoRawMode;
+//! use std::io::{self, stdin, stdout};
+//!
+//! fn main() -> io::Result<()> {
+//!     let mut stdin = stdin().into_raw_mode()?;
+//!     if prompt::confirm("Delete everything?", &mut stdin, &mut stdout())? {
+//!         print!("Press any key to start\r\n");
+//!         prompt::wait_for_any_key(&mut stdin)?;
+//!     }
+//!     Ok(())
+//! }
+//! ```
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::input::{Decoder, InputEvent, Key, KeyEvent, KeyEventKind, Modifiers};
+use crate::util::wait_readable;
+
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+/// How long to wait for the rest of an escape sequence before taking a lone Escape.
+pub(crate) const ESCAPE_TIMEOUT: Duration = Duration::from_millis(25);
+
+/// Blocks until a key is pressed and returns it. Other input events are skipped.
+///
+/// Fails with `UnexpectedEof` if the tty reaches end of file. `tty` shouldn't buffer, like
+/// `Stdin` does: the Escape key timeout polls the fd, which doesn't see buffered bytes.
+pub fn read_key_blocking<R: Read + AsRawFd>(tty: &mut R) -> io::Result<KeyEvent> {
+    let mut decoder = Decoder::new();
+    let mut byte = [0];
+    loop {
+        let event = match decoder.next_event() {
+            Some(event) => Some(event),
+            None if decoder.has_pending()
+                && !wait_readable(tty.as_raw_fd(), Some(ESCAPE_TIMEOUT))? =>
+            {
+                decoder.flush_pending()
+            }
+            None => {
+                if tty.read(&mut byte)? == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                decoder.feed(&byte);
+                None
+            }
+        };
+        if let Some(InputEvent::Key(key)) = event {
+            if key.kind != KeyEventKind::Release {
+                return Ok(key);
+            }
+        }
+    }
+}
+
+/// Blocks until any key is pressed.
+pub fn wait_for_any_key<R: Read + AsRawFd>(tty: &mut R) -> io::Result<()> {
+    read_key_blocking(tty).map(|_| ())
+}
+
+/// Asks a yes/no question, returning whether it was answered with yes.
+///
+/// `y` answers yes; `n`, Enter, Escape and Ctrl+C answer no. Other keys are ignored. The
+/// answer is echoed after the prompt.
+pub fn confirm<R, W>(prompt: &str, tty: &mut R, out: &mut W) -> io::Result<bool>
+where
+    R: Read + AsRawFd,
+    W: Write,
+{
+    write!(out, "{} [y/N] ", prompt)?;
+    out.flush()?;
+    let yes = loop {
+        let key = read_key_blocking(tty)?;
+        match (key.key, key.modifiers) {
+            (Key::Char('y'), m) | (Key::Char('Y'), m) if !m.contains(Modifiers::CTRL) => {
+                break true
+            }
+            (Key::Char('n'), m) | (Key::Char('N'), m) if !m.contains(Modifiers::CTRL) => {
+                break false
+            }
+            (Key::Char('c'), Modifiers::CTRL) | (Key::Enter, _) | (Key::Escape, _) => break false,
+            _ => {}
+        }
+    };
+    out.write_all(if yes { b"y\r\n" } else { b"n\r\n" })?;
+    out.flush()?;
+    Ok(yes)
+}
+
+/// Takes the keys pressed since the last frame without ever blocking, for game loops and
+/// renderers.
+///
+/// Meant for a tty in [game mode](crate::TtyWithGuardBuilder::game_mode), where a read
+/// returns right away with whatever arrived, possibly nothing. A lone Escape is told apart
+/// from the start of a sequence by the [sequence deadline](Decoder::with_sequence_deadline)
+/// instead of waiting, so it is reported a frame or two late rather than holding up the
+/// frame.
+///
+/// ```no_run
+/// use raw_tty::input::Key;
+/// use raw_tty::prompt::KeyPoller;
+/// use raw_tty::TtyWithGuard;
+/// use std::io::{self, stdin};
+///
+/// fn main() -> io::Result<()> {
+///     let mut tty = TtyWithGuard::builder(stdin()).game_mode().build()?;
+///     let mut keys = KeyPoller::new();
+///     loop {
+///         while let Some(key) = keys.poll_key(&mut *tty)? {
+///             if key.key == Key::Char('q') {
+///                 return Ok(());
+///             }
+///         }
+///         // Update and render the frame.
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct KeyPoller {
+    decoder: Decoder,
+    buf: [u8; 256],
+}
+
+impl Default for KeyPoller {
+    fn default() -> KeyPoller {
+        KeyPoller::new()
+    }
+}
+
+impl KeyPoller {
+    pub fn new() -> KeyPoller {
+        KeyPoller {
+            decoder: Decoder::new().with_sequence_deadline(ESCAPE_TIMEOUT),
+            buf: [0; 256],
+        }
+    }
+
+    /// The next key pressed, or `None` if there is none right now. Key releases and other
+    /// input events are skipped.
+    ///
+    /// Reads at most once per call, and treats both a read of nothing and `WouldBlock` as
+    /// no input, so this works with `O_NONBLOCK` set as well.
+    pub fn poll_key<R: Read>(&mut self, tty: &mut R) -> io::Result<Option<KeyEvent>> {
+        let mut read = false;
+        loop {
+            match self.decoder.next_event() {
+                Some(InputEvent::Key(key)) if key.kind != KeyEventKind::Release => {
+                    return Ok(Some(key))
+                }
+                Some(_) => continue,
+                None if read => return Ok(None),
+                None => {}
+            }
+            read = true;
+            match tty.read(&mut self.buf) {
+                Ok(n) => self.decoder.feed(&self.buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// The decoder, e.g. for adding bindings.
+    pub fn decoder(&mut self) -> &mut Decoder {
+        &mut self.decoder
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util;
+    use crate::{IntoRawMode, TtyWithGuard};
+    use std::io::Write;
+    use std::time::Instant;
+
+    #[test]
+    fn test_read_key_leaves_rest() -> io::Result<()> {
+        let (mut terminal, slave) = util::openpty()?;
+        let mut tty = slave.into_raw_mode()?;
+        terminal.write_all(b"\x1b[Ax")?;
+
+        assert_eq!(read_key_blocking(&mut tty)?, KeyEvent::from(Key::Up));
+        let mut rest = [0];
+        tty.read_exact(&mut rest)?;
+        assert_eq!(&rest, b"x");
+
+        terminal.write_all(b"\x1b")?;
+        assert_eq!(read_key_blocking(&mut tty)?, KeyEvent::from(Key::Escape));
+        Ok(())
+    }
+
+    #[test]
+    fn test_confirm() -> io::Result<()> {
+        let (mut terminal, slave) = util::openpty()?;
+        let mut tty = slave.into_raw_mode()?;
+        terminal.write_all(b"qYn")?;
+
+        let mut out = Vec::new();
+        assert!(confirm("Sure?", &mut tty, &mut out)?);
+        assert_eq!(out, b"Sure? [y/N] y\r\n");
+        assert!(!confirm("Sure?", &mut tty, &mut Vec::new())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_poll_key() -> io::Result<()> {
+        let (mut terminal, slave) = util::openpty()?;
+        let mut tty = TtyWithGuard::builder(slave).game_mode().build()?;
+        let ios = tty.current_termios()?;
+        assert_eq!((ios.c_cc[libc::VMIN], ios.c_cc[libc::VTIME]), (0, 0));
+        let mut keys = KeyPoller::new();
+
+        let start = Instant::now();
+        assert_eq!(keys.poll_key(&mut *tty)?, None);
+        assert!(start.elapsed() < ESCAPE_TIMEOUT);
+
+        terminal.write_all(b"a\x1b[B\x1b")?;
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(
+            keys.poll_key(&mut *tty)?,
+            Some(KeyEvent::from(Key::Char('a')))
+        );
+        assert_eq!(keys.poll_key(&mut *tty)?, Some(KeyEvent::from(Key::Down)));
+        assert_eq!(keys.poll_key(&mut *tty)?, None);
+        std::thread::sleep(ESCAPE_TIMEOUT);
+        assert_eq!(keys.poll_key(&mut *tty)?, Some(KeyEvent::from(Key::Escape)));
+        Ok(())
+    }
+}
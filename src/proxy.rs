@@ -0,0 +1,458 @@
+//! Pumping bytes between a pty and a terminal.
+//!
+//! This is the core loop of `script`, `ssh`-like tools and container attach commands: the
+//! user's terminal is put into raw mode, a child program runs on the slave side of a pty,
+//! and everything typed is forwarded to the master while everything the child prints is
+//! forwarded to the terminal.
+//!
+//! ```no_run
+//! use raw_tty::{pty, GuardMode};
+//! use std::io;
+//! use std::fs;
+//!
+//! fn main() -> io::Result<()> {
+//!     let (mut master, slave) = pty::open()?;
+//!     // ... start a child program on `slave` ...
+//!     # drop(slave);
+//!
+//!     let tty = fs::OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+//!     let mut tty = tty.guard_mode()?;
+//!     tty.set_raw_mode()?;
+//!     let reason = raw_tty::proxy(&mut master, &mut tty)?;
+//!     println!("{:?}", reason);
+//!     Ok(())
+//! }
+//! ```
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::events::{Event, Events};
+use crate::pty::{PtyChild, PtyMaster};
+use crate::record::Recorder;
+use crate::util::wait_readable;
+use crate::TtyWithGuard;
+
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+/// Why [`proxy`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// The terminal reached end of file or hung up.
+    TtyClosed,
+    /// Every slave side of the pty was closed, usually because the child exited.
+    MasterClosed,
+    /// A hook returned [`Flow::Stop`].
+    Stopped,
+    /// The child given to [`Proxy::child`] exited. Its remaining output was forwarded.
+    ChildExited(ExitStatus),
+}
+
+/// Whether a [`Proxy`] should keep running after a hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Stop,
+}
+
+/// Whether a read error just means the other side went away.
+fn is_hangup(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(libc::EIO)
+}
+
+type DataHook<'a> = Box<dyn FnMut(&[u8]) + 'a>;
+type IdleHook<'a> = Box<dyn FnMut(Duration) -> Flow + 'a>;
+
+/// The proxy loop of [`proxy`], with hooks for observing the traffic.
+///
+/// Useful for session recording, keystroke logging or inactivity timeouts:
+///
+/// ```no_run
+/// use raw_tty::events::Events;
+/// use raw_tty::proxy::{Flow, Proxy};
+/// use raw_tty::{pty, GuardMode};
+/// use std::io;
+/// use std::fs;
+/// use std::time::Duration;
+///
+/// fn main() -> io::Result<()> {
+///     let (mut master, _slave) = pty::open()?;
+///     let tty = fs::OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+///     let mut tty = tty.guard_mode()?;
+///     tty.set_raw_mode()?;
+///
+///     let mut events = Events::new(&*tty)?;
+///     let mut typed = 0;
+///     Proxy::new()
+///         .on_input(|input| typed += input.len())
+///         .on_idle(Duration::from_secs(600), |_| Flow::Stop)
+///         .run(&mut master, &mut tty, &mut events)?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Default)]
+pub struct Proxy<'a> {
+    on_input: Option<DataHook<'a>>,
+    on_output: Option<DataHook<'a>>,
+    record: Option<DataHook<'a>>,
+    on_idle: Option<(Duration, IdleHook<'a>)>,
+    child: Option<&'a mut PtyChild>,
+}
+
+impl<'a> Proxy<'a> {
+    pub fn new() -> Proxy<'a> {
+        Proxy::default()
+    }
+
+    /// Calls `f` with everything read from the terminal, before it is forwarded.
+    pub fn on_input<F: FnMut(&[u8]) + 'a>(mut self, f: F) -> Proxy<'a> {
+        self.on_input = Some(Box::new(f));
+        self
+    }
+
+    /// Calls `f` with everything read from the pty, before it is forwarded.
+    pub fn on_output<F: FnMut(&[u8]) + 'a>(mut self, f: F) -> Proxy<'a> {
+        self.on_output = Some(Box::new(f));
+        self
+    }
+
+    /// Records the output into `recorder`, independently of any
+    /// [`on_output`](Proxy::on_output) hook. Errors are reported by
+    /// [`Recorder::finish`].
+    pub fn record<W: Write>(mut self, recorder: &'a mut Recorder<W>) -> Proxy<'a> {
+        self.record = Some(Box::new(move |data| recorder.output_or_keep_error(data)));
+        self
+    }
+
+    /// Calls `f` every `interval` without any traffic in either direction, with the time
+    /// since the last traffic.
+    pub fn on_idle<F: FnMut(Duration) -> Flow + 'a>(
+        mut self,
+        interval: Duration,
+        f: F,
+    ) -> Proxy<'a> {
+        self.on_idle = Some((interval, Box::new(f)));
+        self
+    }
+
+    /// Stops the loop once `child` exits, even if other processes still hold the slave open,
+    /// e.g. daemons it started in the background.
+    pub fn child(mut self, child: &'a mut PtyChild) -> Proxy<'a> {
+        self.child = Some(child);
+        self
+    }
+
+    /// Runs the loop, see [`proxy`], with `events` listening on `tty`.
+    ///
+    /// Taking the caller's [`Events`] lets a program which has one for its own loop hand it
+    /// over while proxying. Fails with `InvalidInput` if `events` listens on another fd.
+    ///
+    /// Input for the pty is buffered and written as the pty takes it, so a child which is
+    /// busy printing can't block the loop. On [`Event::Suspend`] the terminal is restored
+    /// and the process stopped like Ctrl-Z would, see [`TtyWithGuard::suspend_process`];
+    /// once continued, the mode is applied again and the window size copied, since either
+    /// may have changed in the meantime.
+    pub fn run<T: Read + Write + AsRawFd>(
+        &mut self,
+        master: &mut PtyMaster,
+        tty: &mut TtyWithGuard<T>,
+        events: &mut Events,
+    ) -> io::Result<ExitReason> {
+        if events.tty() != tty.as_raw_fd() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the events listen on another fd than the tty",
+            ));
+        }
+        let _nonblocking = master.nonblocking()?;
+        if let Ok(size) = tty.window_size() {
+            master.set_window_size(size)?;
+        }
+
+        let mut buf = [0; 4096];
+        // Typed but not yet taken by the pty.
+        let mut to_master = Vec::new();
+        let mut last_activity = Instant::now();
+        let mut next_idle = self
+            .on_idle
+            .as_ref()
+            .map(|(interval, _)| last_activity + *interval);
+        loop {
+            let mut fds = [
+                libc::pollfd {
+                    fd: master.as_raw_fd(),
+                    events: if to_master.is_empty() {
+                        libc::POLLIN
+                    } else {
+                        libc::POLLIN | libc::POLLOUT
+                    },
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: self.child.as_ref().map_or(-1, |child| child.exit_fd()),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            let event = events.poll(&mut fds, next_idle)?;
+            match event {
+                Some(Event::Resize) => {
+                    if let Ok(size) = tty.window_size() {
+                        master.set_window_size(size)?;
+                    }
+                }
+                Some(Event::Readable) => {
+                    let n = match tty.read(&mut buf) {
+                        Ok(n) => n,
+                        Err(ref e) if is_hangup(e) => 0,
+                        Err(e) => return Err(e),
+                    };
+                    if n == 0 {
+                        return Ok(ExitReason::TtyClosed);
+                    }
+                    if let Some(ref mut on_input) = self.on_input {
+                        on_input(&buf[..n]);
+                    }
+                    to_master.extend_from_slice(&buf[..n]);
+                    if !write_available(master, &mut to_master)? {
+                        return Ok(ExitReason::MasterClosed);
+                    }
+                }
+                Some(Event::Hangup) => return Ok(ExitReason::TtyClosed),
+                Some(Event::Suspend) => {
+                    match tty.suspend_process() {
+                        // Without a shell to return to, like a cooked tty without job control.
+                        Err(ref e) if e.kind() == io::ErrorKind::Unsupported => {}
+                        result => result?,
+                    }
+                    resume(master, tty)?;
+                }
+                Some(Event::Continue) => resume(master, tty)?,
+                Some(Event::Idle) | Some(Event::Reattached) | None => {}
+            }
+
+            if fds[0].revents & libc::POLLOUT != 0 && !write_available(master, &mut to_master)? {
+                return Ok(ExitReason::MasterClosed);
+            }
+            if fds[0].revents & !libc::POLLOUT != 0
+                && !self.forward_output(master, tty, &mut buf)?
+            {
+                return Ok(ExitReason::MasterClosed);
+            }
+
+            if fds[1].revents != 0 {
+                let status = match self.child {
+                    Some(ref mut child) => child.exit_status_nonblocking()?,
+                    None => None,
+                };
+                if let Some(status) = status {
+                    while wait_readable(master.as_raw_fd(), Some(Duration::from_millis(0)))?
+                        && self.forward_output(master, tty, &mut buf)?
+                    {}
+                    return Ok(ExitReason::ChildExited(status));
+                }
+            }
+
+            let now = Instant::now();
+            if let Some((interval, ref mut on_idle)) = self.on_idle {
+                if event == Some(Event::Readable) || fds[0].revents != 0 {
+                    last_activity = now;
+                    next_idle = Some(now + interval);
+                } else if next_idle.is_some_and(|deadline| now >= deadline) {
+                    if on_idle(now - last_activity) == Flow::Stop {
+                        return Ok(ExitReason::Stopped);
+                    }
+                    next_idle = Some(now + interval);
+                }
+            }
+        }
+    }
+
+    /// Forwards one read of output from `master` to `tty`, returning `false` if the master
+    /// was closed.
+    fn forward_output<T: Write + AsRawFd>(
+        &mut self,
+        master: &mut PtyMaster,
+        tty: &mut TtyWithGuard<T>,
+        buf: &mut [u8],
+    ) -> io::Result<bool> {
+        let n = match master.read(buf) {
+            Ok(n) => n,
+            Err(ref e) if is_hangup(e) => 0,
+            // Nothing after all, or only a packet mode status change.
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+            Err(e) => return Err(e),
+        };
+        if n == 0 {
+            return Ok(false);
+        }
+        if let Some(ref mut on_output) = self.on_output {
+            on_output(&buf[..n]);
+        }
+        if let Some(ref mut record) = self.record {
+            record(&buf[..n]);
+        }
+        tty.write_all(&buf[..n])?;
+        tty.flush()?;
+        Ok(true)
+    }
+}
+
+/// Copies input from `tty` to `master` and output from `master` to `tty`, until either
+/// side is closed.
+///
+/// The window size of `tty` is copied to the pty at the start and whenever it changes.
+/// Since this listens for signals through its own [`Events`], there can't be another
+/// `Events` alive while the proxy runs. [`Proxy::run`] takes an existing one, and has
+/// hooks into the loop.
+pub fn proxy<T: Read + Write + AsRawFd>(
+    master: &mut PtyMaster,
+    tty: &mut TtyWithGuard<T>,
+) -> io::Result<ExitReason> {
+    let mut events = Events::new(&**tty)?;
+    Proxy::new().run(master, tty, &mut events)
+}
+
+/// Writes as much of `pending` as the nonblocking `master` takes, returning `false` if it
+/// was closed.
+fn write_available(master: &mut PtyMaster, pending: &mut Vec<u8>) -> io::Result<bool> {
+    while !pending.is_empty() {
+        match master.write(pending) {
+            Ok(n) => {
+                pending.drain(..n);
+            }
+            Err(ref e) if is_hangup(e) => return Ok(false),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+/// Takes the terminal back after the process was stopped.
+fn resume<T: AsRawFd>(master: &mut PtyMaster, tty: &mut TtyWithGuard<T>) -> io::Result<()> {
+    tty.guard.reapply("proxy_resume")?;
+    if let Ok(size) = tty.window_size() {
+        master.set_window_size(size)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::EVENTS_LOCK;
+    use crate::{pty, GuardMode};
+    use std::process::Command;
+
+    #[test]
+    fn test_proxy_until_master_closed() -> io::Result<()> {
+        let _lock = EVENTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let (mut master, mut slave) = pty::open()?;
+        let (mut terminal, user_side) = pty::open()?;
+        let mut tty = user_side.guard_mode()?;
+
+        slave.write_all(b"output")?;
+        drop(slave);
+
+        assert_eq!(proxy(&mut master, &mut tty)?, ExitReason::MasterClosed);
+        let mut buf = [0; 6];
+        terminal.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"output");
+        Ok(())
+    }
+
+    #[test]
+    fn test_hooks() -> io::Result<()> {
+        let _lock = EVENTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let (mut master, slave) = pty::open()?;
+        let (mut terminal, user_side) = pty::open()?;
+        let mut tty = user_side.guard_mode()?;
+        tty.set_raw_mode()?;
+        // Keep the input from being echoed back as output.
+        let mut slave = slave.guard_mode()?;
+        slave.set_raw_mode()?;
+
+        terminal.write_all(b"in")?;
+        slave.write_all(b"out")?;
+        let mut events = Events::new(&*tty)?;
+        let (mut input, mut output, mut idle) = (Vec::new(), Vec::new(), 0);
+        let reason = Proxy::new()
+            .on_input(|data| input.extend_from_slice(data))
+            .on_output(|data| output.extend_from_slice(data))
+            .on_idle(Duration::from_millis(20), |_| {
+                idle += 1;
+                Flow::Stop
+            })
+            .run(&mut master, &mut tty, &mut events)?;
+
+        assert_eq!(reason, ExitReason::Stopped);
+        assert_eq!(
+            (&input[..], &output[..], idle),
+            (&b"in"[..], &b"out"[..], 1)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_while_child_doesnt_read() -> io::Result<()> {
+        let _lock = EVENTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let (mut master, slave) = pty::open()?;
+        let (mut terminal, user_side) = pty::open()?;
+        let mut tty = user_side.guard_mode()?;
+        tty.set_raw_mode()?;
+        let mut slave = slave.guard_mode()?;
+        slave.set_raw_mode()?;
+
+        // Far more than the pty buffers, which nobody reads on the slave side.
+        let typing = std::thread::spawn(move || {
+            terminal.write_all(&[b'x'; 64 * 1024])?;
+            Ok::<_, io::Error>(terminal)
+        });
+        slave.write_all(b"out")?;
+        let mut events = Events::new(&*tty)?;
+        let mut output = Vec::new();
+        let reason = Proxy::new()
+            .on_output(|data| output.extend_from_slice(data))
+            .on_idle(Duration::from_millis(100), |_| Flow::Stop)
+            .run(&mut master, &mut tty, &mut events)?;
+        assert_eq!(reason, ExitReason::Stopped);
+        assert_eq!(output, b"out");
+        typing.join().unwrap()?;
+
+        // The same events work for another run, and only for their own tty.
+        let (other, _other_side) = pty::open()?;
+        let mut other = other.guard_mode()?;
+        let error = Proxy::new()
+            .run(&mut master, &mut other, &mut events)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn test_child_exited() -> io::Result<()> {
+        let _lock = EVENTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // The background sleep keeps the slave open after the shell exited.
+        let (mut master, mut child) =
+            pty::spawn(Command::new("sh").args(["-c", "sleep 1 & printf done; exit 2"]))?;
+        let (mut terminal, user_side) = pty::open()?;
+        let mut tty = user_side.guard_mode()?;
+
+        let mut events = Events::new(&*tty)?;
+        let reason = Proxy::new()
+            .child(&mut child)
+            .run(&mut master, &mut tty, &mut events)?;
+        match reason {
+            ExitReason::ChildExited(status) => assert_eq!(status.code(), Some(2)),
+            reason => panic!("unexpected {:?}", reason),
+        }
+        let mut buf = [0; 4];
+        terminal.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"done");
+        Ok(())
+    }
+}
@@ -0,0 +1,788 @@
+//! Pseudo terminals.
+//!
+//! A pty is a pair of connected devices: the slave side behaves like a terminal for the
+//! program running on it, while the master side is held by the program emulating the
+//! terminal (a terminal emulator, `script`, `ssh`, a multiplexer, ...). Whatever is written
+//! to the master arrives as input on the slave and vice versa.
+//!
+//! ```no_run
+//! use raw_tty::pty;
+//! use std::io::{self, Read, Write};
+//!
+//! fn main() -> io::Result<()> {
+//!     let (mut master, mut slave) = pty::open()?;
+//!     slave.write_all(b"hello\n")?;
+//!
+//!     let mut buf = [0; 7];
+//!     master.read_exact(&mut buf)?;
+//!     assert_eq!(&buf, b"hello\r\n");
+//!     Ok(())
+//! }
+//! ```
+
+use crate::attr::{
+    get_terminal_attr, get_window_size, raw_terminal_attr, set_terminal_attr, set_window_size,
+};
+use crate::util::*;
+use crate::WindowSize;
+
+use libc::c_int;
+use std::ffi::{CStr, OsStr};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use std::{mem, ptr};
+
+mod expect;
+
+pub use expect::Match;
+
+/// The master side of a pty.
+#[derive(Debug)]
+pub struct PtyMaster {
+    file: File,
+    slave_name: PathBuf,
+    slave_open: SlaveOpen,
+    grant: Grant,
+    /// Output read by an `expect` beyond its match, which reads return first.
+    unmatched: Vec<u8>,
+}
+
+/// Whether `grantpt` could set up the ownership of the slave, see [`PtyMaster::grant`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grant {
+    /// `grantpt` succeeded.
+    Granted,
+    /// `grantpt` failed, e.g. in a sandbox without the `pt_chown` helper or with seccomp
+    /// denying it, but the slave already belongs to the calling user, as devpts sets it up
+    /// on Linux.
+    Devpts,
+    /// `grantpt` failed with this errno, and the slave belongs to another user or group.
+    /// It works for this process, but other users may be able to open it too.
+    Ungranted(i32),
+}
+
+/// How the slave of a pty was opened, see [`PtyMaster::slave_open`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlaveOpen {
+    /// With the `TIOCGPTPEER` ioctl on the master (Linux 4.13 and later), which can't open
+    /// anything but the slave of that master.
+    Peer,
+    /// By opening the path `ptsname` returns. In a container, or with a `/dev/pts` mounted
+    /// by someone else, the path may resolve to a device other than the slave.
+    Path,
+}
+
+/// What a read from a master in packet mode returned, see [`PtyMaster::read_packet`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    /// This many bytes of output from the slave were read.
+    Data(usize),
+    /// The state of the slave changed. Never empty.
+    Control(Vec<PacketEvent>),
+}
+
+/// A change of the slave's state, reported inline in packet mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketEvent {
+    /// The slave's input queue was flushed.
+    FlushRead,
+    /// The slave's output queue was flushed, so the master should drop any output it
+    /// still has buffered.
+    FlushWrite,
+    /// Output was stopped, e.g. with Ctrl-S.
+    Stop,
+    /// Output was restarted, e.g. with Ctrl-Q.
+    Start,
+    /// Ctrl-S and Ctrl-Q are no longer the stop and start characters, or `IXON` was
+    /// switched off: the master should pass them through instead of doing flow control.
+    NoStop,
+    /// `IXON` is on with the usual Ctrl-S and Ctrl-Q, so the master may do flow control
+    /// locally.
+    DoStop,
+    /// The termios of the slave changed (Linux with `EXTPROC` only).
+    Ioctl,
+}
+
+const PACKET_EVENTS: [(u8, PacketEvent); 7] = [
+    (0x01, PacketEvent::FlushRead),
+    (0x02, PacketEvent::FlushWrite),
+    (0x04, PacketEvent::Stop),
+    (0x08, PacketEvent::Start),
+    (0x10, PacketEvent::NoStop),
+    (0x20, PacketEvent::DoStop),
+    (0x40, PacketEvent::Ioctl),
+];
+
+/// The slave side of a pty, i.e. the terminal device programs run on.
+#[derive(Debug)]
+pub struct PtySlave {
+    file: File,
+}
+
+/// Flags for `posix_openpt`. Linux accepts `O_CLOEXEC`, elsewhere it is set afterwards.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const OPENPT_FLAGS: c_int = libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const OPENPT_FLAGS: c_int = libc::O_RDWR | libc::O_NOCTTY;
+
+/// Opens a new pty pair.
+///
+/// Neither side becomes the controlling terminal of the calling process, and both are
+/// closed on exec. See [`PtySlave::set_inheritable`] for passing the slave on to a child.
+pub fn open() -> io::Result<(PtyMaster, PtySlave)> {
+    let master = unsafe {
+        let fd = convert_to_result(libc::posix_openpt(OPENPT_FLAGS))?;
+        // Owning it right away closes it again on any of the errors below.
+        let file = File::from_raw_fd(fd);
+        convert_to_result(libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC))?;
+        file
+    };
+    let slave_name = slave_name(master.as_raw_fd())?;
+    let grant = grant(master.as_raw_fd(), &slave_name);
+    convert_to_result(unsafe { libc::unlockpt(master.as_raw_fd()) })?;
+    let (slave, slave_open) = open_slave(&master, &slave_name)?;
+
+    Ok((
+        PtyMaster {
+            file: master,
+            slave_name,
+            slave_open,
+            grant,
+            unmatched: Vec::new(),
+        },
+        PtySlave { file: slave },
+    ))
+}
+
+/// Calls `grantpt`, falling back to checking the ownership the slave already has.
+fn grant(master: RawFd, slave_name: &Path) -> Grant {
+    use std::os::unix::fs::MetadataExt;
+
+    let errno = match convert_to_result(unsafe { libc::grantpt(master) }) {
+        Ok(_) => return Grant::Granted,
+        Err(e) => e.raw_os_error().unwrap_or(libc::EIO),
+    };
+    match fs::metadata(slave_name) {
+        // Not writable by others, which is what grantpt would have made sure of.
+        Ok(meta) if meta.uid() == unsafe { libc::getuid() } && meta.mode() & 0o002 == 0 => {
+            Grant::Devpts
+        }
+        _ => Grant::Ungranted(errno),
+    }
+}
+
+/// Opens the slave of `master`, through `TIOCGPTPEER` where the kernel has it and through
+/// `name` otherwise.
+fn open_slave(master: &File, name: &Path) -> io::Result<(File, SlaveOpen)> {
+    #[cfg(target_os = "linux")]
+    {
+        let flags = libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC;
+        let fd = unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCGPTPEER, flags) };
+        match convert_to_result(fd) {
+            Ok(fd) => return Ok((unsafe { File::from_raw_fd(fd) }, SlaveOpen::Peer)),
+            // Older kernels don't know the ioctl.
+            Err(ref e) if matches!(e.raw_os_error(), Some(libc::EINVAL | libc::ENOTTY)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = master;
+    let slave = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(name)?;
+    Ok((slave, SlaveOpen::Path))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn slave_name(master: RawFd) -> io::Result<PathBuf> {
+    let mut buf = [0 as libc::c_char; 128];
+    let result = unsafe { libc::ptsname_r(master, buf.as_mut_ptr(), buf.len()) };
+    if result != 0 {
+        return Err(io::Error::from_raw_os_error(result));
+    }
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Ok(PathBuf::from(OsStr::from_bytes(name.to_bytes())))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn slave_name(master: RawFd) -> io::Result<PathBuf> {
+    // ptsname returns a static buffer.
+    static LOCK: Mutex<()> = Mutex::new(());
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let name = unsafe { libc::ptsname(master) };
+    if name.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let name = unsafe { CStr::from_ptr(name) };
+    Ok(PathBuf::from(OsStr::from_bytes(name.to_bytes())))
+}
+
+impl PtyMaster {
+    /// The path of the slave device, e.g. `/dev/pts/3`.
+    pub fn slave_name(&self) -> &Path {
+        &self.slave_name
+    }
+
+    /// How [`open`] opened the slave. Reopening it, e.g. to change its mode, takes the same
+    /// way.
+    pub fn slave_open(&self) -> SlaveOpen {
+        self.slave_open
+    }
+
+    /// Whether [`open`] could make sure that only the calling user can use the slave.
+    /// A failing `grantpt` isn't an error, since the slave is usable all the same.
+    pub fn grant(&self) -> Grant {
+        self.grant
+    }
+
+    /// The window size of the pty.
+    pub fn window_size(&self) -> io::Result<WindowSize> {
+        get_window_size(self.as_raw_fd())
+    }
+
+    /// Sets the window size of the pty, which sends `SIGWINCH` to the slave's foreground
+    /// process group.
+    pub fn set_window_size(&self, size: WindowSize) -> io::Result<()> {
+        set_window_size(self.as_raw_fd(), &size)
+    }
+
+    /// Switches packet mode (`TIOCPKT`) on or off.
+    ///
+    /// In packet mode every read from the master starts with a header byte, telling output
+    /// of the slave apart from notifications about flushes and flow control, which
+    /// multiplexers need to forward to the real terminal. Use
+    /// [`read_packet`](PtyMaster::read_packet) instead of `read` while it is on.
+    #[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "dragonfly"))]
+    pub fn set_packet_mode(&self, enabled: bool) -> io::Result<()> {
+        let enabled = c_int::from(enabled);
+        convert_to_result(unsafe { libc::ioctl(self.as_raw_fd(), libc::TIOCPKT, &enabled) })
+            .and(Ok(()))
+    }
+
+    /// Switches packet mode (`TIOCPKT`) on or off, which isn't supported on this platform.
+    #[cfg(not(any(target_os = "linux", target_os = "freebsd", target_os = "dragonfly")))]
+    pub fn set_packet_mode(&self, _enabled: bool) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "pty packet mode isn't supported on this platform",
+        ))
+    }
+
+    /// Reads one packet in packet mode. Output of the slave is read into `buf`, without the
+    /// header byte.
+    ///
+    /// Returns `Packet::Data(0)` at end of file or if `buf` is empty.
+    pub fn read_packet(&mut self, buf: &mut [u8]) -> io::Result<Packet> {
+        if buf.is_empty() {
+            return Ok(Packet::Data(0));
+        }
+        let mut header = 0u8;
+        let iov = [
+            libc::iovec {
+                iov_base: &mut header as *mut u8 as *mut libc::c_void,
+                iov_len: 1,
+            },
+            libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            },
+        ];
+        let n = convert_to_result(unsafe { libc::readv(self.as_raw_fd(), iov.as_ptr(), 2) })?;
+        if n == 0 || header == 0 {
+            return Ok(Packet::Data((n as usize).saturating_sub(1)));
+        }
+        Ok(Packet::Control(
+            PACKET_EVENTS
+                .iter()
+                .filter(|&&(bit, _)| header & bit != 0)
+                .map(|&(_, event)| event)
+                .collect(),
+        ))
+    }
+
+    /// Puts the slave into raw mode, for driving a child which doesn't do so itself, e.g.
+    /// to pass binary data through or to see every key without line editing.
+    ///
+    /// Unlike the guards this isn't undone: the mode belongs to the slave from now on, just
+    /// as if the child had changed it.
+    pub fn set_slave_raw(&self) -> io::Result<()> {
+        self.modify_slave_mode(raw_terminal_attr)
+    }
+
+    /// Switches the echo of the slave on or off, e.g. before typing a password into the
+    /// child so that it doesn't show up in the output read from the master.
+    pub fn set_slave_echo(&self, echo: bool) -> io::Result<()> {
+        self.modify_slave_mode(|ios| {
+            if echo {
+                ios.c_lflag |= libc::ECHO;
+            } else {
+                ios.c_lflag &= !(libc::ECHO | libc::ECHONL);
+            }
+        })
+    }
+
+    /// Changes the termios of the slave through a fresh fd for it, since not every
+    /// platform forwards `tcsetattr` on the master to the slave.
+    fn modify_slave_mode(&self, f: impl FnOnce(&mut crate::Termios)) -> io::Result<()> {
+        let (slave, _) = open_slave(&self.file, &self.slave_name)?;
+        let mut ios = get_terminal_attr(slave.as_raw_fd())?;
+        f(&mut ios);
+        set_terminal_attr(slave.as_raw_fd(), &ios)
+    }
+}
+
+impl PtySlave {
+    /// Sets whether the slave stays open in programs started with `exec`, under its current
+    /// fd number. It is closed on exec by default.
+    ///
+    /// Not needed for [`spawn`], which passes the slave as stdin, stdout and stderr anyway.
+    pub fn set_inheritable(&self, inheritable: bool) -> io::Result<()> {
+        let fd = self.as_raw_fd();
+        unsafe {
+            let flags = convert_to_result(libc::fcntl(fd, libc::F_GETFD))?;
+            let flags = if inheritable {
+                flags & !libc::FD_CLOEXEC
+            } else {
+                flags | libc::FD_CLOEXEC
+            };
+            convert_to_result(libc::fcntl(fd, libc::F_SETFD, flags))?;
+        }
+        Ok(())
+    }
+
+    /// Unwraps the underlying file.
+    pub fn into_file(self) -> File {
+        self.file
+    }
+}
+
+/// Starts `command` on a new pty, as the session leader with the slave as its controlling
+/// terminal and as its stdin, stdout and stderr.
+///
+/// ```no_run
+/// use raw_tty::pty;
+/// use std::io;
+/// use std::process::Command;
+/// use std::time::Duration;
+///
+/// fn main() -> io::Result<()> {
+///     let (_master, mut child) = pty::spawn(&mut Command::new("vi"))?;
+///     if child.wait_timeout(Duration::from_secs(1))?.is_none() {
+///         child.kill()?;
+///     }
+///     Ok(())
+/// }
+/// ```
+///
+/// The slave is only lent to `command`: its stdin, stdout and stderr are reset to
+/// [`Stdio::null`] once the child is started, so that the master sees the end of the slave
+/// when the child exits. The `pre_exec` hook making the child a session leader with the
+/// slave as its controlling terminal (`setsid`, then `TIOCSCTTY`) stays on `command`, so
+/// spawning it again directly fails in the child unless it gets a tty as stdin.
+pub fn spawn(command: &mut Command) -> io::Result<(PtyMaster, PtyChild)> {
+    let (master, slave) = open()?;
+    // Registered before the child exists, so that its SIGCHLD can't be missed.
+    let exited = ChildExited::new()?;
+    command
+        .stdin(slave.file.try_clone()?)
+        .stdout(slave.file.try_clone()?)
+        .stderr(slave.file);
+    unsafe {
+        command.pre_exec(|| {
+            convert_to_result(libc::setsid())?;
+            convert_to_result(libc::ioctl(0, libc::TIOCSCTTY, 0))?;
+            Ok(())
+        });
+    }
+    let child = command.spawn();
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    Ok((
+        master,
+        PtyChild {
+            child: child?,
+            exited,
+        },
+    ))
+}
+
+/// A child process started by [`spawn`].
+///
+/// Exits are noticed through `SIGCHLD`, for which a handler is installed the first time a
+/// child is spawned. It stays installed, and calls the handler it replaced, if any.
+#[derive(Debug)]
+pub struct PtyChild {
+    child: Child,
+    exited: ChildExited,
+}
+
+impl PtyChild {
+    /// The process id of the child.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Sends `SIGKILL` to the child.
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.child.kill()
+    }
+
+    /// Waits for the child to exit.
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.child.wait()
+    }
+
+    /// Returns the exit status if the child has exited, without blocking.
+    pub fn exit_status_nonblocking(&mut self) -> io::Result<Option<ExitStatus>> {
+        self.exited.drain();
+        self.child.try_wait()
+    }
+
+    /// Waits up to `timeout` for the child to exit, returning `None` if it is still running.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(status) = self.exit_status_nonblocking()? {
+                return Ok(Some(status));
+            }
+            let mut fd = libc::pollfd {
+                fd: self.exit_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if poll(std::slice::from_mut(&mut fd), Some(deadline))? == 0 {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// An fd which becomes readable when any child may have exited.
+    pub(crate) fn exit_fd(&self) -> RawFd {
+        self.exited.pipe[0]
+    }
+}
+
+/// The most children which can be waited for through `SIGCHLD` at once.
+const MAX_CHILDREN: usize = 64;
+
+/// The write ends of the pipes of all live `ChildExited`, or -1 for a free slot.
+static CHILD_PIPES: [AtomicI32; MAX_CHILDREN] = [const { AtomicI32::new(-1) }; MAX_CHILDREN];
+
+/// The `SIGCHLD` action before `install_child_handler`, set before the handler is.
+static PREVIOUS_CHILD_ACTION: OnceLock<libc::sigaction> = OnceLock::new();
+
+extern "C" fn on_child_signal(
+    signal: c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    for slot in CHILD_PIPES.iter() {
+        let fd = slot.load(Ordering::Relaxed);
+        if fd >= 0 {
+            let byte = 0u8;
+            // A full pipe already has a wakeup pending.
+            unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+        }
+    }
+    let previous = match PREVIOUS_CHILD_ACTION.get() {
+        Some(previous) => previous,
+        None => return,
+    };
+    let handler = previous.sa_sigaction;
+    if handler == libc::SIG_DFL || handler == libc::SIG_IGN {
+        return;
+    }
+    unsafe {
+        if previous.sa_flags & libc::SA_SIGINFO != 0 {
+            let handler: extern "C" fn(c_int, *mut libc::siginfo_t, *mut libc::c_void) =
+                mem::transmute(handler);
+            handler(signal, info, context);
+        } else {
+            let handler: extern "C" fn(c_int) = mem::transmute(handler);
+            handler(signal);
+        }
+    }
+}
+
+/// A self-pipe which is written to on every `SIGCHLD`.
+///
+/// Every child has its own, so that waiting for one child doesn't consume the wakeups of
+/// another.
+#[derive(Debug)]
+struct ChildExited {
+    slot: usize,
+    pipe: [RawFd; 2],
+}
+
+impl ChildExited {
+    fn new() -> io::Result<ChildExited> {
+        install_child_handler()?;
+        let pipe = self_pipe()?;
+        let slot = CHILD_PIPES.iter().position(|slot| {
+            slot.compare_exchange(-1, pipe[1], Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        });
+        let slot = match slot {
+            Some(slot) => slot,
+            None => {
+                unsafe {
+                    libc::close(pipe[0]);
+                    libc::close(pipe[1]);
+                }
+                return Err(io::Error::other("too many raw_tty::pty children"));
+            }
+        };
+        Ok(ChildExited { slot, pipe })
+    }
+
+    fn drain(&self) {
+        let mut buf = [0u8; 64];
+        while unsafe {
+            libc::read(
+                self.pipe[0],
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        } > 0
+        {}
+    }
+}
+
+impl Drop for ChildExited {
+    fn drop(&mut self) {
+        CHILD_PIPES[self.slot].store(-1, Ordering::SeqCst);
+        for &fd in self.pipe.iter() {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+fn install_child_handler() -> io::Result<()> {
+    static INSTALLED: Mutex<bool> = Mutex::new(false);
+    let mut installed = INSTALLED.lock().unwrap_or_else(|e| e.into_inner());
+    if !*installed {
+        unsafe {
+            let mut previous: libc::sigaction = mem::zeroed();
+            convert_to_result(libc::sigaction(libc::SIGCHLD, ptr::null(), &mut previous))?;
+            let _ = PREVIOUS_CHILD_ACTION.set(previous);
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = on_child_signal
+                as extern "C" fn(c_int, *mut libc::siginfo_t, *mut libc::c_void)
+                as libc::sighandler_t;
+            // SA_NOCLDSTOP even if the previous handler wanted stops, which it then misses.
+            action.sa_flags = libc::SA_RESTART | libc::SA_NOCLDSTOP | libc::SA_SIGINFO;
+            libc::sigemptyset(&mut action.sa_mask);
+            convert_to_result(libc::sigaction(libc::SIGCHLD, &action, ptr::null_mut()))?;
+        }
+        *installed = true;
+    }
+    Ok(())
+}
+
+macro_rules! impl_file_traits {
+    ($($t:ident)*) => ($(
+        impl AsRawFd for $t {
+            fn as_raw_fd(&self) -> RawFd {
+                self.file.as_raw_fd()
+            }
+        }
+
+        impl Write for $t {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.file.write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.file.flush()
+            }
+        }
+    )*)
+}
+
+impl_file_traits! { PtyMaster PtySlave }
+
+/// `O_NONBLOCK` set on a master, until dropped, which puts the previous flags back.
+#[derive(Debug)]
+pub(crate) struct NonBlocking {
+    fd: RawFd,
+    flags: c_int,
+}
+
+impl PtyMaster {
+    /// Makes reads and writes on the master return `WouldBlock` instead of waiting, for as
+    /// long as the returned value lives.
+    pub(crate) fn nonblocking(&self) -> io::Result<NonBlocking> {
+        let fd = self.file.as_raw_fd();
+        let flags = convert_to_result(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+        convert_to_result(unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) })?;
+        Ok(NonBlocking { fd, flags })
+    }
+}
+
+impl Drop for NonBlocking {
+    fn drop(&mut self) {
+        unsafe { libc::fcntl(self.fd, libc::F_SETFL, self.flags) };
+    }
+}
+
+impl Read for PtyMaster {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.unmatched.is_empty() {
+            return self.file.read(buf);
+        }
+        let n = buf.len().min(self.unmatched.len());
+        buf[..n].copy_from_slice(&self.unmatched[..n]);
+        self.unmatched.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Read for PtySlave {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_open() -> io::Result<()> {
+        let (mut master, mut slave) = open()?;
+        assert!(master.slave_name().starts_with("/dev"));
+        #[cfg(target_os = "linux")]
+        assert_eq!(master.slave_open(), SlaveOpen::Peer);
+        assert_eq!(master.grant(), Grant::Granted);
+
+        let size = WindowSize {
+            rows: 24,
+            cols: 80,
+            ..WindowSize::default()
+        };
+        master.set_window_size(size)?;
+        assert_eq!(get_window_size(slave.as_raw_fd())?, size);
+
+        master.write_all(b"hi\n")?;
+        let mut buf = [0; 3];
+        slave.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"hi\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_slave_raw_and_echo() -> io::Result<()> {
+        let (mut master, mut slave) = open()?;
+        master.set_slave_echo(false)?;
+        let ios = get_terminal_attr(slave.as_raw_fd())?;
+        assert_eq!(ios.c_lflag & libc::ECHO, 0);
+        assert_ne!(ios.c_lflag & libc::ICANON, 0);
+
+        master.set_slave_raw()?;
+        assert_eq!(
+            get_terminal_attr(slave.as_raw_fd())?.c_lflag & libc::ICANON,
+            0
+        );
+        // No line buffering: the byte arrives without a newline.
+        master.write_all(b"x")?;
+        let mut buf = [0];
+        slave.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"x");
+        Ok(())
+    }
+
+    #[test]
+    fn test_packet_mode() -> io::Result<()> {
+        let (mut master, mut slave) = open()?;
+        master.set_packet_mode(true)?;
+        let mut buf = [0; 16];
+
+        slave.write_all(b"hi")?;
+        assert_eq!(master.read_packet(&mut buf)?, Packet::Data(2));
+        assert_eq!(&buf[..2], b"hi");
+
+        convert_to_result(unsafe { libc::tcflow(slave.as_raw_fd(), libc::TCOOFF) })?;
+        assert_eq!(
+            master.read_packet(&mut buf)?,
+            Packet::Control(vec![PacketEvent::Stop])
+        );
+        convert_to_result(unsafe { libc::tcflow(slave.as_raw_fd(), libc::TCOON) })?;
+        assert_eq!(
+            master.read_packet(&mut buf)?,
+            Packet::Control(vec![PacketEvent::Start])
+        );
+        Ok(())
+    }
+
+    /// Whether a child started now would have `fd` open.
+    fn inherited(fd: RawFd) -> io::Result<bool> {
+        let path = format!("/dev/fd/{}", fd);
+        Ok(Command::new("sh")
+            .args(["-c", "test -e \"$0\"", &path])
+            .status()?
+            .success())
+    }
+
+    #[test]
+    fn test_close_on_exec() -> io::Result<()> {
+        let (master, slave) = open()?;
+        assert!(!inherited(master.as_raw_fd())?);
+        assert!(!inherited(slave.as_raw_fd())?);
+
+        slave.set_inheritable(true)?;
+        assert!(inherited(slave.as_raw_fd())?);
+        slave.set_inheritable(false)?;
+        assert!(!inherited(slave.as_raw_fd())?);
+
+        let (_master, child) = spawn(&mut Command::new("true"))?;
+        for &fd in child.exited.pipe.iter() {
+            assert!(!inherited(fd)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_spawn_and_wait() -> io::Result<()> {
+        let (mut master, mut child) = spawn(Command::new("sh").args(["-c", "tty; exit 3"]))?;
+        let status = child.wait_timeout(Duration::from_secs(5))?;
+        assert_eq!(status.and_then(|status| status.code()), Some(3));
+
+        let mut output = Vec::new();
+        let _ = master.read_to_end(&mut output);
+        let name = master.slave_name().as_os_str().as_bytes();
+        assert!(output.starts_with(name));
+
+        let (_master, mut child) = spawn(Command::new("sleep").arg("5"))?;
+        assert_eq!(child.wait_timeout(Duration::from_millis(50))?, None);
+        child.kill()?;
+        assert!(child.wait_timeout(Duration::from_secs(5))?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_spawn_keeps_no_slave() -> io::Result<()> {
+        let mut command = Command::new("true");
+        let (master, mut child) = spawn(&mut command)?;
+        assert!(child.wait_timeout(Duration::from_secs(5))?.is_some());
+        // With `command` still alive, the slave is closed nonetheless.
+        assert!(wait_readable(
+            master.as_raw_fd(),
+            Some(Duration::from_secs(5))
+        )?);
+        drop(command);
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+//! Driving an interactive program by waiting for its output, in the manner of `expect(1)`.
+//!
+//! ```no_run
+//! use raw_tty::pty;
+//! use std::io;
+//! use std::process::Command;
+//! use std::time::Duration;
+//!
+//! fn main() -> io::Result<()> {
+//!     let (mut master, mut child) = pty::spawn(&mut Command::new("passwd"))?;
+//!     let timeout = Duration::from_secs(5);
+//!     master.expect("Current password: ", timeout)?;
+//!     master.send("hunter2\n")?;
+//!     let reply = master.expect("\n", timeout)?;
+//!     println!("passwd said {:?}", String::from_utf8_lossy(&reply.before));
+//!     child.kill()
+//! }
+//! ```
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use super::PtyMaster;
+use crate::util::poll;
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+/// What [`PtyMaster::expect`] found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// The output between the previous match and this one.
+    pub before: Vec<u8>,
+    /// The output which matched.
+    pub matched: Vec<u8>,
+}
+
+impl PtyMaster {
+    /// Writes `bytes` to the program, as if typed, and flushes.
+    pub fn send(&mut self, bytes: impl AsRef<[u8]>) -> io::Result<()> {
+        self.file.write_all(bytes.as_ref())?;
+        self.file.flush()
+    }
+
+    /// Reads the output of the program until it contains `pattern`, giving up after
+    /// `timeout`.
+    ///
+    /// Output after the match stays buffered, for the next `expect` or `read`. Fails with
+    /// `TimedOut` if the pattern doesn't show up in time and with `UnexpectedEof` if the
+    /// program closes the pty first; the output so far stays buffered then too, see
+    /// [`unmatched`](PtyMaster::unmatched). Both errors include it, for test failures.
+    ///
+    /// Remember that the pty translates `\n` in the output to `\r\n`, and echoes what is
+    /// sent unless the program switched echo off.
+    pub fn expect(&mut self, pattern: impl AsRef<[u8]>, timeout: Duration) -> io::Result<Match> {
+        let pattern = pattern.as_ref();
+        self.expect_with(timeout, &Escaped(pattern), |output| {
+            if pattern.is_empty() {
+                return Some((0, 0));
+            }
+            let start = output.windows(pattern.len()).position(|w| w == pattern)?;
+            Some((start, start + pattern.len()))
+        })
+    }
+
+    /// [`expect`](PtyMaster::expect) with a regular expression, with the `expect-regex`
+    /// feature. The first match in the output so far counts, so a pattern like `\d+`
+    /// may match before all digits arrived.
+    #[cfg(feature = "expect-regex")]
+    pub fn expect_regex(
+        &mut self,
+        regex: &regex::bytes::Regex,
+        timeout: Duration,
+    ) -> io::Result<Match> {
+        self.expect_with(timeout, regex, |output| {
+            regex.find(output).map(|found| (found.start(), found.end()))
+        })
+    }
+
+    /// The output which was read by an `expect`, but isn't part of a match yet.
+    pub fn unmatched(&self) -> &[u8] {
+        &self.unmatched
+    }
+
+    fn expect_with(
+        &mut self,
+        timeout: Duration,
+        what: &dyn fmt::Display,
+        mut find: impl FnMut(&[u8]) -> Option<(usize, usize)>,
+    ) -> io::Result<Match> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0; 4096];
+        loop {
+            if let Some((start, end)) = find(&self.unmatched) {
+                let matched = self.unmatched[start..end].to_vec();
+                let before = self.unmatched.drain(..end).take(start).collect();
+                return Ok(Match { before, matched });
+            }
+            let mut pollfd = libc::pollfd {
+                fd: self.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if poll(std::slice::from_mut(&mut pollfd), Some(deadline))? == 0 {
+                return Err(self.expect_error(io::ErrorKind::TimedOut, what));
+            }
+            match self.file.read(&mut buf) {
+                // Linux reports a closed slave as EIO.
+                Ok(0) => return Err(self.expect_error(io::ErrorKind::UnexpectedEof, what)),
+                Err(ref e) if e.raw_os_error() == Some(libc::EIO) => {
+                    return Err(self.expect_error(io::ErrorKind::UnexpectedEof, what))
+                }
+                Ok(n) => self.unmatched.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn expect_error(&self, kind: io::ErrorKind, what: &dyn fmt::Display) -> io::Error {
+        let reason = match kind {
+            io::ErrorKind::TimedOut => "timed out",
+            _ => "the pty closed",
+        };
+        io::Error::new(
+            kind,
+            format!(
+                "{} waiting for {}, the output was \"{}\"",
+                reason,
+                what,
+                Escaped(&self.unmatched)
+            ),
+        )
+    }
+}
+
+/// Shows bytes the way `b"..."` literals escape them.
+struct Escaped<'a>(&'a [u8]);
+
+impl fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &byte in self.0 {
+            write!(f, "{}", std::ascii::escape_default(byte))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pty::spawn;
+    use std::process::Command;
+
+    #[test]
+    fn test_expect() -> io::Result<()> {
+        let (mut master, mut child) = spawn(&mut Command::new("cat"))?;
+        let timeout = Duration::from_secs(5);
+        master.send("hello\n")?;
+        // The echo of the pty, then the output of cat.
+        let echo = master.expect("hello\r\n", timeout)?;
+        assert_eq!(
+            (echo.before.as_slice(), echo.matched.as_slice()),
+            (&b""[..], &b"hello\r\n"[..])
+        );
+        master.expect("hello", timeout)?;
+        let err = master.expect("bye", Duration::from_millis(50)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(err.to_string().contains("output was \"\\r\\n\""));
+        assert_eq!(master.unmatched(), b"\r\n");
+
+        let mut buf = [0; 2];
+        master.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"\r\n");
+        assert!(master.unmatched().is_empty());
+
+        #[cfg(feature = "expect-regex")]
+        {
+            master.send("id 42\n")?;
+            let regex = regex::bytes::Regex::new(r"id (\d+)\r\n").unwrap();
+            master.expect_regex(&regex, timeout)?;
+            let found = master.expect_regex(&regex, timeout)?;
+            assert_eq!(found.matched, b"id 42\r\n");
+        }
+        child.kill()
+    }
+}
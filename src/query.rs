@@ -0,0 +1,577 @@
+//! Asking the terminal what it supports.
+//!
+//! Terminals answer queries by writing replies into the input stream, so this only works
+//! with the input side in raw mode. [`TerminalCapabilities::probe`] takes care of that,
+//! sends all of its queries at once and follows them with a primary device attributes
+//! request (DA1). Since every terminal answers DA1, its reply marks the end of the answers
+//! and the probe doesn't have to wait out the timeout on terminals which silently ignore
+//! some of the queries. [`read_clipboard`] uses the same trick to read the clipboard
+//! through OSC 52, and [`write_clipboard`] copies to it.
+//!
+//! ```no_run
+//! use raw_tty::query::TerminalCapabilities;
+//! use raw_tty::session::{Mode, Session};
+//! use std::io;
+//! use std::fs;
+//!
+//! fn main() -> io::Result<()> {
+//!     let tty = fs::OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+//!     let mut session = Session::new(tty)?;
+//!     let capabilities = TerminalCapabilities::probe(&mut session)?;
+//!     if capabilities.supports(Mode::BracketedPaste) {
+//!         session.enable(Mode::BracketedPaste)?;
+//!     }
+//!     Ok(())
+//! }
+//! ```
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::attr::raw_terminal_attr;
+use crate::session::Mode;
+use crate::util::wait_readable;
+use crate::TtyWithGuard;
+
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+const ESC: u8 = 0x1b;
+
+/// How long [`TerminalCapabilities::probe`] waits for the terminal to answer.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(200);
+
+const QUERIES: &[u8] = concat!(
+    // DECRQM for bracketed paste, SGR mouse, focus reporting and synchronized output.
+    "\x1b[?2004$p",
+    "\x1b[?1006$p",
+    "\x1b[?1004$p",
+    "\x1b[?2026$p",
+    // The kitty keyboard protocol flags.
+    "\x1b[?u",
+    // XTGETTCAP for "RGB" and "Tc".
+    "\x1bP+q524742\x1b\\",
+    "\x1bP+q5463\x1b\\",
+    // DA1, which everything answers.
+    "\x1b[c",
+)
+.as_bytes();
+
+/// The optional features a terminal answered to support.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TerminalCapabilities {
+    /// Whether the terminal answered at all. If not, nothing else can be relied on.
+    pub responded: bool,
+    pub bracketed_paste: bool,
+    pub mouse_sgr: bool,
+    pub focus_events: bool,
+    pub kitty_keyboard: bool,
+    /// Synchronized updates, see [`Session::synchronized`](crate::session::Session::synchronized).
+    pub synchronized_output: bool,
+    /// 24-bit color, as reported through XTGETTCAP.
+    pub truecolor: bool,
+    /// The parameters of the primary device attributes reply.
+    pub device_attributes: Vec<u32>,
+}
+
+impl TerminalCapabilities {
+    /// Probes the terminal, waiting at most [`DEFAULT_TIMEOUT`] for answers.
+    pub fn probe<T: Read + Write + AsRawFd>(
+        tty: &mut TtyWithGuard<T>,
+    ) -> io::Result<TerminalCapabilities> {
+        TerminalCapabilities::probe_with_timeout(tty, DEFAULT_TIMEOUT)
+    }
+
+    /// Probes the terminal, waiting at most `timeout` for answers.
+    ///
+    /// The tty is put into raw mode for the duration of the probe and its previous mode
+    /// is restored afterwards. Input which arrives while probing is discarded.
+    pub fn probe_with_timeout<T: Read + Write + AsRawFd>(
+        tty: &mut TtyWithGuard<T>,
+        timeout: Duration,
+    ) -> io::Result<TerminalCapabilities> {
+        let (replies, _) = exchange(tty, QUERIES, timeout, usize::MAX)?;
+        Ok(TerminalCapabilities::parse(&replies).0)
+    }
+
+    /// Whether the terminal is known to support `mode`.
+    ///
+    /// Modes which can't be queried are assumed to be supported by any terminal which
+    /// answered.
+    pub fn supports(&self, mode: Mode) -> bool {
+        match mode {
+            Mode::BracketedPaste => self.bracketed_paste,
+            Mode::Mouse => self.mouse_sgr,
+            Mode::FocusEvents => self.focus_events,
+            Mode::KittyKeyboard(_) => self.kitty_keyboard,
+            Mode::SynchronizedOutput => self.synchronized_output,
+            _ => self.responded,
+        }
+    }
+
+    /// Interprets the replies in `buf`, also returning whether the DA1 reply was among them.
+    fn parse(buf: &[u8]) -> (TerminalCapabilities, bool) {
+        let mut capabilities = TerminalCapabilities::default();
+        for reply in replies(buf) {
+            match reply {
+                Reply::Csi {
+                    params,
+                    final_byte: b'y',
+                } => {
+                    let params = params.strip_prefix(b"?").unwrap_or(params);
+                    let params = params.strip_suffix(b"$").unwrap_or(params);
+                    let mut values = numbers(params);
+                    let (mode, value) = (values.next(), values.next());
+                    // 0 means the mode isn't recognized.
+                    let supported = value.is_some_and(|v| (1..=4).contains(&v));
+                    match mode {
+                        Some(2004) => capabilities.bracketed_paste = supported,
+                        Some(1006) => capabilities.mouse_sgr = supported,
+                        Some(1004) => capabilities.focus_events = supported,
+                        Some(2026) => capabilities.synchronized_output = supported,
+                        _ => {}
+                    }
+                }
+                Reply::Csi {
+                    params,
+                    final_byte: b'u',
+                } if params.starts_with(b"?") => capabilities.kitty_keyboard = true,
+                Reply::Csi {
+                    params,
+                    final_byte: b'c',
+                } if params.starts_with(b"?") => {
+                    capabilities.responded = true;
+                    capabilities.device_attributes = numbers(&params[1..]).collect();
+                }
+                Reply::Dcs(data) => {
+                    if data.starts_with(b"1+r524742") || data.starts_with(b"1+r5463") {
+                        capabilities.truecolor = true;
+                    }
+                }
+                Reply::Csi { .. } | Reply::Osc(_) => {}
+            }
+        }
+        let complete = capabilities.responded;
+        (capabilities, complete)
+    }
+}
+
+/// A selection which can be read with [`read_clipboard`] and written with
+/// [`write_clipboard`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    /// The clipboard, as filled by copy commands.
+    Clipboard,
+    /// The primary selection of X11 and Wayland, i.e. the selected text.
+    Primary,
+}
+
+/// The most bytes of decoded contents [`read_clipboard`] accepts.
+pub const DEFAULT_CLIPBOARD_LIMIT: usize = 1 << 20;
+
+/// Reads a selection through OSC 52, waiting at most [`DEFAULT_TIMEOUT`] and accepting at
+/// most [`DEFAULT_CLIPBOARD_LIMIT`] bytes.
+///
+/// ```no_run
+/// use raw_tty::query::{self, Selection};
+/// use raw_tty::GuardMode;
+/// use std::io;
+/// use std::fs;
+///
+/// fn main() -> io::Result<()> {
+///     let tty = fs::OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+///     let mut tty = tty.guard_mode()?;
+///     match query::read_clipboard(&mut tty, Selection::Clipboard)? {
+///         Some(contents) => println!("{}", String::from_utf8_lossy(&contents)),
+///         None => println!("the terminal doesn't allow reading the clipboard"),
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn read_clipboard<T: Read + Write + AsRawFd>(
+    tty: &mut TtyWithGuard<T>,
+    selection: Selection,
+) -> io::Result<Option<Vec<u8>>> {
+    read_clipboard_with_limits(tty, selection, DEFAULT_TIMEOUT, DEFAULT_CLIPBOARD_LIMIT)
+}
+
+/// Reads a selection through OSC 52, waiting at most `timeout` and accepting at most
+/// `limit` bytes of decoded contents.
+///
+/// Returns `None` if the terminal didn't answer, which is what most terminals do unless
+/// reading the clipboard was allowed in their settings. Some ask the user first, so a
+/// longer timeout than the default may be needed. Fails with `InvalidData` if the contents
+/// exceed `limit` or aren't valid base64.
+///
+/// Like [`TerminalCapabilities::probe`], the tty is in raw mode while waiting for the
+/// answer and input which arrives meanwhile is discarded.
+pub fn read_clipboard_with_limits<T: Read + Write + AsRawFd>(
+    tty: &mut TtyWithGuard<T>,
+    selection: Selection,
+    timeout: Duration,
+    limit: usize,
+) -> io::Result<Option<Vec<u8>>> {
+    let name = match selection {
+        Selection::Clipboard => 'c',
+        Selection::Primary => 'p',
+    };
+    let query = format!("\x1b]52;{};?\x1b\\\x1b[c", name);
+    // Base64 takes 4 bytes for every 3, plus the framing of the reply.
+    let encoded_limit = limit.saturating_add(2) / 3 * 4 + 16;
+    let (replies, overflowed) = exchange(tty, query.as_bytes(), timeout, encoded_limit)?;
+    let too_large = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the clipboard contents exceed the limit of {} bytes", limit),
+        )
+    };
+    if overflowed {
+        return Err(too_large());
+    }
+    for reply in self::replies(&replies) {
+        if let Reply::Osc(data) = reply {
+            if let Some(rest) = data.strip_prefix(b"52;") {
+                // The selection names come before the data.
+                let data = match rest.iter().position(|&b| b == b';') {
+                    Some(i) => &rest[i + 1..],
+                    None => continue,
+                };
+                let contents = decode_base64(data).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid base64 in OSC 52 reply")
+                })?;
+                if contents.len() > limit {
+                    return Err(too_large());
+                }
+                return Ok(Some(contents));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Copies `contents` to a selection through OSC 52, e.g. for a yank command.
+///
+/// The contents are base64 encoded in chunks, so that even large copies are written
+/// without building the whole sequence first, and the sequence is terminated with ST. Empty
+/// contents clear the selection. Terminals don't confirm the copy and many cap its size,
+/// some at 100 KB; what they ignore is simply not copied. Unlike reading, writing needs no
+/// change of mode.
+///
+/// ```no_run
+/// use raw_tty::query::{self, Selection};
+/// use raw_tty::GuardMode;
+/// use std::io;
+/// use std::fs;
+///
+/// fn main() -> io::Result<()> {
+///     let tty = fs::OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+///     let mut tty = tty.guard_mode()?;
+///     query::write_clipboard(&mut tty, Selection::Clipboard, b"yanked line")
+/// }
+/// ```
+pub fn write_clipboard<T: Write + AsRawFd>(
+    tty: &mut TtyWithGuard<T>,
+    selection: Selection,
+    contents: &[u8],
+) -> io::Result<()> {
+    /// A multiple of 3, so that only the last chunk can need padding.
+    const CHUNK: usize = 3 * 1024;
+    let name = match selection {
+        Selection::Clipboard => b'c',
+        Selection::Primary => b'p',
+    };
+    let tty = &mut **tty;
+    tty.write_all(&[ESC, b']', b'5', b'2', b';', name, b';'])?;
+    let mut encoded = Vec::with_capacity(CHUNK / 3 * 4);
+    for chunk in contents.chunks(CHUNK) {
+        encoded.clear();
+        encode_base64(chunk, &mut encoded);
+        tty.write_all(&encoded)?;
+    }
+    tty.write_all(&[ESC, b'\\'])?;
+    tty.flush()
+}
+
+/// Appends `data` as standard base64, with padding.
+fn encode_base64(data: &[u8], out: &mut Vec<u8>) {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    for group in data.chunks(3) {
+        let bits = group
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= group.len() {
+                out.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize]);
+            } else {
+                out.push(b'=');
+            }
+        }
+    }
+}
+
+/// Decodes standard base64, with or without padding.
+fn decode_base64(data: &[u8]) -> Option<Vec<u8>> {
+    let data = data
+        .strip_suffix(b"==")
+        .or_else(|| data.strip_suffix(b"="))
+        .unwrap_or(data);
+    let mut decoded = Vec::with_capacity(data.len() / 4 * 3 + 2);
+    let (mut bits, mut count) = (0u32, 0);
+    for &b in data {
+        let value = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = bits << 6 | u32::from(value);
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// Switches the tty into raw mode, sends `queries` and collects the replies, until the
+/// DA1 reply or the timeout. Then puts back the previous mode.
+///
+/// Once more than `limit` bytes arrived, only the end is kept, to be able to find the DA1
+/// reply, and the returned flag is set.
+fn exchange<T: Read + Write + AsRawFd>(
+    tty: &mut TtyWithGuard<T>,
+    queries: &[u8],
+    timeout: Duration,
+    limit: usize,
+) -> io::Result<(Vec<u8>, bool)> {
+    let previous = tty.guard.get_attr()?;
+    let mut ios = previous;
+    raw_terminal_attr(&mut ios);
+    tty.guard.set_attr("query", &ios)?;
+
+    let result = collect_replies(&mut **tty, queries, timeout, limit);
+
+    tty.guard.set_attr("query", &previous)?;
+    result
+}
+
+fn collect_replies<T: Read + Write + AsRawFd>(
+    tty: &mut T,
+    queries: &[u8],
+    timeout: Duration,
+    limit: usize,
+) -> io::Result<(Vec<u8>, bool)> {
+    tty.write_all(queries)?;
+    tty.flush()?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = Vec::new();
+    let mut overflowed = false;
+    let mut chunk = [0; 256];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if !wait_readable(tty.as_raw_fd(), Some(left))? {
+            return Ok((buf, overflowed));
+        }
+        match tty.read(&mut chunk)? {
+            0 => return Ok((buf, overflowed)),
+            n => buf.extend_from_slice(&chunk[..n]),
+        }
+        if buf.len() > limit {
+            overflowed = true;
+            buf.drain(..buf.len() - 64.min(buf.len()));
+        }
+        if TerminalCapabilities::parse(&buf).1 {
+            return Ok((buf, overflowed));
+        }
+    }
+}
+
+fn numbers(params: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    params
+        .split(|&b| b == b';')
+        .filter_map(|n| std::str::from_utf8(n).ok()?.parse().ok())
+}
+
+/// A control sequence sent by the terminal.
+enum Reply<'a> {
+    /// `ESC [ params final_byte`, where `params` includes any intermediate bytes.
+    Csi { params: &'a [u8], final_byte: u8 },
+    /// `ESC P data ESC \`
+    Dcs(&'a [u8]),
+    /// `ESC ] data` terminated by `BEL` or `ESC \`
+    Osc(&'a [u8]),
+}
+
+/// Extracts all complete replies from `buf`, skipping anything else.
+fn replies(mut buf: &[u8]) -> Vec<Reply<'_>> {
+    let mut replies = Vec::new();
+    while let Some(start) = buf.iter().position(|&b| b == ESC) {
+        buf = &buf[start..];
+        match buf.get(1) {
+            Some(b'[') => match buf[2..].iter().position(|b| (0x40..=0x7e).contains(b)) {
+                Some(end) => {
+                    replies.push(Reply::Csi {
+                        params: &buf[2..end + 2],
+                        final_byte: buf[end + 2],
+                    });
+                    buf = &buf[end + 3..];
+                }
+                None => break,
+            },
+            Some(b'P') => match buf[2..].windows(2).position(|w| w == b"\x1b\\") {
+                Some(end) => {
+                    replies.push(Reply::Dcs(&buf[2..end + 2]));
+                    buf = &buf[end + 4..];
+                }
+                None => break,
+            },
+            Some(b']') => match buf[2..].iter().position(|&b| b == 0x07 || b == ESC) {
+                Some(end) if buf[end + 2] == 0x07 => {
+                    replies.push(Reply::Osc(&buf[2..end + 2]));
+                    buf = &buf[end + 3..];
+                }
+                Some(end) if buf.get(end + 3) == Some(&b'\\') => {
+                    replies.push(Reply::Osc(&buf[2..end + 2]));
+                    buf = &buf[end + 4..];
+                }
+                Some(end) if buf.len() > end + 3 => buf = &buf[end + 2..],
+                _ => break,
+            },
+            _ => buf = &buf[1..],
+        }
+    }
+    replies
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::attr::get_terminal_attr;
+    use crate::util::openpty;
+    use crate::GuardMode;
+    use std::fs::File;
+    use std::thread;
+
+    #[test]
+    fn test_parse_replies() {
+        let (capabilities, complete) = TerminalCapabilities::parse(
+            b"\x1b[?2004;2$y\x1b[?1006;0$y\x1b[?2026;2$y\x1b[?0u\x1bP1+r524742=382f382f38\x1b\\\x1b[?62;4;22c",
+        );
+        assert!(complete);
+        assert_eq!(
+            capabilities,
+            TerminalCapabilities {
+                responded: true,
+                bracketed_paste: true,
+                mouse_sgr: false,
+                focus_events: false,
+                kitty_keyboard: true,
+                synchronized_output: true,
+                truecolor: true,
+                device_attributes: vec![62, 4, 22],
+            }
+        );
+        assert!(!TerminalCapabilities::parse(b"\x1b[?2004;2$y\x1b[?62").1);
+    }
+
+    /// Answers the clipboard query on `master` with `reply` once it arrives. The master is
+    /// handed back, since closing it would hang up the slave.
+    fn answer_clipboard_query(mut master: File, reply: &'static [u8]) -> thread::JoinHandle<File> {
+        thread::spawn(move || {
+            let mut queries = Vec::new();
+            let mut buf = [0; 64];
+            while !queries.ends_with(b"\x1b[c") {
+                match master.read(&mut buf) {
+                    Ok(n) if n > 0 => queries.extend_from_slice(&buf[..n]),
+                    _ => break,
+                }
+            }
+            assert!(queries.starts_with(b"\x1b]52;c;?\x1b\\"));
+            master.write_all(reply).unwrap();
+            master
+        })
+    }
+
+    #[test]
+    fn test_read_clipboard() -> io::Result<()> {
+        let (master, slave) = openpty()?;
+        let mut tty = slave.guard_mode()?;
+        let answer = answer_clipboard_query(master, b"\x1b]52;c;aMOpbGxv\x07\x1b[?62c");
+        let timeout = Duration::from_secs(5);
+        let contents = read_clipboard_with_limits(&mut tty, Selection::Clipboard, timeout, 16)?;
+        assert_eq!(contents.as_deref(), Some("h\u{e9}llo".as_bytes()));
+        answer.join().unwrap();
+
+        let (master, slave) = openpty()?;
+        let mut tty = slave.guard_mode()?;
+        let answer = answer_clipboard_query(master, b"\x1b]52;c;aMOpbGxv\x1b\\\x1b[?62c");
+        let error =
+            read_clipboard_with_limits(&mut tty, Selection::Clipboard, timeout, 3).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        answer.join().unwrap();
+
+        // A terminal which doesn't allow reading the clipboard only answers DA1.
+        let (master, slave) = openpty()?;
+        let mut tty = slave.guard_mode()?;
+        let answer = answer_clipboard_query(master, b"\x1b[?62c");
+        assert_eq!(read_clipboard(&mut tty, Selection::Clipboard)?, None);
+        answer.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_base64() {
+        assert_eq!(decode_base64(b"aGk=").as_deref(), Some(&b"hi"[..]));
+        assert_eq!(decode_base64(b"aGk").as_deref(), Some(&b"hi"[..]));
+        assert_eq!(decode_base64(b"").as_deref(), Some(&b""[..]));
+        assert_eq!(decode_base64(b"a?"), None);
+
+        for data in [&b""[..], b"h", b"hi", b"hi!", "h\u{e9}llo".as_bytes()] {
+            let mut encoded = Vec::new();
+            encode_base64(data, &mut encoded);
+            assert_eq!(encoded.len() % 4, 0);
+            assert_eq!(decode_base64(&encoded).as_deref(), Some(data));
+        }
+    }
+
+    #[test]
+    fn test_write_clipboard() -> io::Result<()> {
+        let (mut master, slave) = openpty()?;
+        let mut tty = slave.guard_mode()?;
+        write_clipboard(&mut tty, Selection::Primary, "h\u{e9}llo".as_bytes())?;
+        let mut buf = [0; 17];
+        master.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"\x1b]52;p;aMOpbGxv\x1b\\");
+
+        // Chunks are encoded without padding in between.
+        let contents = vec![b'x'; 3 * 1024 + 1];
+        let reader = thread::spawn(move || {
+            let mut buf = vec![0; 7 + (3 * 1024 + 3) / 3 * 4 + 2];
+            master.read_exact(&mut buf).map(|()| buf)
+        });
+        write_clipboard(&mut tty, Selection::Clipboard, &contents)?;
+        let written = reader.join().unwrap()?;
+        let data = &written[7..written.len() - 2];
+        assert_eq!(data.iter().filter(|&&b| b == b'=').count(), 2);
+        assert_eq!(decode_base64(data), Some(contents));
+        Ok(())
+    }
+
+    #[test]
+    fn test_probe_times_out() -> io::Result<()> {
+        let (_master, slave) = openpty()?;
+        let mut tty = slave.guard_mode()?;
+        let ios = get_terminal_attr(tty.as_raw_fd())?;
+
+        let capabilities =
+            TerminalCapabilities::probe_with_timeout(&mut tty, Duration::from_millis(10))?;
+        assert_eq!(capabilities, TerminalCapabilities::default());
+        assert_eq!(get_terminal_attr(tty.as_raw_fd())?.c_lflag, ios.c_lflag);
+        Ok(())
+    }
+}
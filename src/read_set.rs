@@ -0,0 +1,154 @@
+//! Waiting for any of several fds to become readable, like `select(2)`.
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::util::poll;
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+/// The sources a synchronous tool reads from, e.g. a guarded tty and a pipe from a worker
+/// thread, to be waited on together.
+///
+/// Anything with an fd can be added: ttys, [`TtyWithGuard`](crate::TtyWithGuard)s, pty
+/// masters, pipes and sockets. A source which hung up or failed counts as readable, so
+/// that the following read reports it. Data already buffered in user space, like the
+/// output `PtyMaster::expect` read past its match, isn't seen by `poll`.
+///
+/// ```no_run
+/// use raw_tty::{IntoRawMode, ReadSet};
+/// use std::io::{self, stdin, Read};
+/// use std::os::unix::net::UnixStream;
+/// use std::time::Duration;
+///
+/// fn main() -> io::Result<()> {
+///     let mut tty = stdin().into_raw_mode()?;
+///     let (mut worker, _other_end) = UnixStream::pair()?;
+///     let mut set = ReadSet::new().add(&tty).add(&worker);
+///     let readable = set.wait(Some(Duration::from_secs(1)))?;
+///     let mut buf = [0; 64];
+///     if readable.contains(&tty) {
+///         tty.read(&mut buf)?;
+///     }
+///     if readable.contains(&worker) {
+///         worker.read(&mut buf)?;
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ReadSet {
+    fds: Vec<libc::pollfd>,
+}
+
+impl ReadSet {
+    pub fn new() -> ReadSet {
+        ReadSet::default()
+    }
+
+    /// Adds `source`, which has the next index.
+    // Chained like a builder, unrelated to `ops::Add`.
+    #[allow(clippy::should_implement_trait)]
+    pub fn add<T: AsRawFd>(mut self, source: &T) -> ReadSet {
+        self.insert(source);
+        self
+    }
+
+    /// Adds `source` to an existing set, returning its index.
+    pub fn insert<T: AsRawFd>(&mut self, source: &T) -> usize {
+        self.fds.push(libc::pollfd {
+            fd: source.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        });
+        self.fds.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.fds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+
+    /// Waits until at least one source is readable or `timeout` passes, forever if it is
+    /// `None`. Which sources are readable is empty after a timeout.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> io::Result<Readable> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        poll(&mut self.fds, deadline)?;
+        let ready = libc::POLLIN | libc::POLLHUP | libc::POLLERR | libc::POLLNVAL;
+        Ok(Readable {
+            fds: self
+                .fds
+                .iter()
+                .enumerate()
+                .filter(|(_, pollfd)| pollfd.revents & ready != 0)
+                .map(|(index, pollfd)| (index, pollfd.fd))
+                .collect(),
+        })
+    }
+}
+
+/// The sources found readable by [`ReadSet::wait`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Readable {
+    /// The indices and fds, in the order the sources were added.
+    fds: Vec<(usize, RawFd)>,
+}
+
+impl Readable {
+    /// Whether `source` is readable.
+    pub fn contains<T: AsRawFd>(&self, source: &T) -> bool {
+        let fd = source.as_raw_fd();
+        self.fds.iter().any(|&(_, readable)| readable == fd)
+    }
+
+    /// The indices of the readable sources, in the order they were added.
+    pub fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.fds.iter().map(|&(index, _)| index)
+    }
+
+    /// Whether nothing is readable, i.e. the wait timed out.
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::openpty;
+    use crate::IntoRawMode;
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_read_set() -> io::Result<()> {
+        let (mut master, slave) = openpty()?;
+        let tty = slave.into_raw_mode()?;
+        let (mut writer, pipe) = UnixStream::pair()?;
+        let mut set = ReadSet::new().add(&tty).add(&pipe);
+        assert_eq!(set.len(), 2);
+
+        let readable = set.wait(Some(Duration::from_millis(10)))?;
+        assert!(readable.is_empty());
+
+        writer.write_all(b"x")?;
+        let readable = set.wait(None)?;
+        assert!(readable.contains(&pipe));
+        assert!(!readable.contains(&tty));
+        assert_eq!(readable.indices().collect::<Vec<_>>(), [1]);
+
+        master.write_all(b"k")?;
+        assert!(!ReadSet::new()
+            .add(&tty)
+            .wait(Some(Duration::from_secs(5)))?
+            .is_empty());
+        drop(writer);
+        let readable = set.wait(Some(Duration::from_secs(5)))?;
+        assert_eq!(readable.indices().collect::<Vec<_>>(), [0, 1]);
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+//! Recording terminal sessions in the asciicast v2 format.
+//!
+//! The files can be played back with `asciinema play`. Together with
+//! [`Proxy::record`](crate::proxy::Proxy::record), any tool built on the proxy loop can offer
+//! `--record session.cast`:
+//!
+//! ```no_run
+//! use raw_tty::events::Events;
+//! use raw_tty::proxy::Proxy;
+//! use raw_tty::record::Recorder;
+//! use raw_tty::{pty, GuardMode};
+//! use std::io;
+//! use std::fs::{self, File};
+//!
+//! fn main() -> io::Result<()> {
+//!     let (mut master, _slave) = pty::open()?;
+//!     let tty = fs::OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+//!     let mut tty = tty.guard_mode()?;
+//!     tty.set_raw_mode()?;
+//!
+//!     let mut recorder = Recorder::new(File::create("session.cast")?, tty.window_size()?)?;
+//!     let mut events = Events::new(&*tty)?;
+//!     Proxy::new()
+//!         .record(&mut recorder)
+//!         .run(&mut master, &mut tty, &mut events)?;
+//!     recorder.finish()?;
+//!     Ok(())
+//! }
+//! ```
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::WindowSize;
+
+use std::env;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::str;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Writes an asciicast v2 recording to `W`.
+pub struct Recorder<W: Write> {
+    writer: W,
+    start: Instant,
+    /// The start of a UTF-8 sequence which was split between two writes, per stream.
+    partial: [Vec<u8>; 2],
+    error: Option<io::Error>,
+}
+
+/// The kinds of events in a recording.
+#[derive(Clone, Copy)]
+enum Stream {
+    Output = 0,
+    Input = 1,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Starts a recording of a terminal with the given size, writing the header.
+    pub fn new(mut writer: W, size: WindowSize) -> io::Result<Recorder<W>> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_secs());
+        let mut header = format!(
+            "{{\"version\": 2, \"width\": {}, \"height\": {}, \"timestamp\": {}",
+            size.cols, size.rows, timestamp
+        );
+        if let Ok(term) = env::var("TERM") {
+            header.push_str(", \"env\": {\"TERM\": ");
+            push_json_string(&mut header, &term);
+            header.push('}');
+        }
+        header.push_str("}\n");
+        writer.write_all(header.as_bytes())?;
+
+        Ok(Recorder {
+            writer,
+            start: Instant::now(),
+            partial: [Vec::new(), Vec::new()],
+            error: None,
+        })
+    }
+
+    /// Records output printed to the terminal.
+    pub fn output(&mut self, data: &[u8]) -> io::Result<()> {
+        self.event(Stream::Output, data)
+    }
+
+    /// Records input typed at the terminal.
+    pub fn input(&mut self, data: &[u8]) -> io::Result<()> {
+        self.event(Stream::Input, data)
+    }
+
+    /// Records a change of the window size.
+    pub fn resize(&mut self, size: WindowSize) -> io::Result<()> {
+        let line = format!(
+            "[{:.6}, \"r\", \"{}x{}\"]\n",
+            self.start.elapsed().as_secs_f64(),
+            size.cols,
+            size.rows
+        );
+        self.writer.write_all(line.as_bytes())
+    }
+
+    /// Records `data` and keeps the first error, for use from hooks which can't fail.
+    pub(crate) fn output_or_keep_error(&mut self, data: &[u8]) {
+        if self.error.is_none() {
+            self.error = self.output(data).err();
+        }
+    }
+
+    /// Flushes the recording and returns the writer, or the first error of recording from
+    /// a [`Proxy`](crate::proxy::Proxy).
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn event(&mut self, stream: Stream, data: &[u8]) -> io::Result<()> {
+        let partial = &mut self.partial[stream as usize];
+        partial.extend_from_slice(data);
+        let text = take_utf8(partial);
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let code = match stream {
+            Stream::Output => "o",
+            Stream::Input => "i",
+        };
+        let mut line = format!("[{:.6}, \"{}\", ", self.start.elapsed().as_secs_f64(), code);
+        push_json_string(&mut line, &text);
+        line.push_str("]\n");
+        self.writer.write_all(line.as_bytes())
+    }
+}
+
+/// Takes the decodable part out of `buf`, leaving an incomplete trailing sequence behind.
+/// Invalid bytes are replaced with U+FFFD.
+fn take_utf8(buf: &mut Vec<u8>) -> String {
+    let mut text = String::new();
+    let mut rest = &buf[..];
+    loop {
+        match str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                rest = &[];
+                break;
+            }
+            Err(error) => {
+                let (valid, after) = rest.split_at(error.valid_up_to());
+                // Can't fail, everything up to `valid_up_to` is valid.
+                text.push_str(str::from_utf8(valid).unwrap_or_default());
+                match error.error_len() {
+                    Some(len) => {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                        rest = &after[len..];
+                    }
+                    None => {
+                        rest = after;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    let consumed = buf.len() - rest.len();
+    buf.drain(..consumed);
+    text
+}
+
+fn push_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_recording() -> io::Result<()> {
+        let size = WindowSize {
+            rows: 24,
+            cols: 80,
+            ..WindowSize::default()
+        };
+        let mut recorder = Recorder::new(Vec::new(), size)?;
+        recorder.output(b"\x1b[1m\"hi\"\r\n\xc3")?;
+        recorder.output(b"\xa9")?;
+        recorder.input(b"\xff")?;
+        let recording = String::from_utf8(recorder.finish()?).unwrap();
+
+        let lines: Vec<_> = recording.lines().collect();
+        assert!(lines[0].starts_with("{\"version\": 2, \"width\": 80, \"height\": 24, "));
+        let events: Vec<_> = lines[1..]
+            .iter()
+            .map(|line| &line[line.find(',').unwrap()..])
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                ", \"o\", \"\\u001b[1m\\\"hi\\\"\\r\\n\"]",
+                ", \"o\", \"\u{e9}\"]",
+                ", \"i\", \"\u{fffd}\"]",
+            ]
+        );
+        Ok(())
+    }
+}
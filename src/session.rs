@@ -0,0 +1,595 @@
+//! Combining the termios guard with escape sequence controlled terminal modes.
+//!
+//! Full screen programs usually do more than switching to raw mode: they enter the
+//! alternate screen, enable bracketed paste or mouse reporting, and so on. All of these
+//! have to be undone on exit, and the order in which that happens matters. For example,
+//! if cooked mode is restored while the terminal is still on the alternate screen, anything
+//! printed in between (a shell prompt, a panic message) ends up on the alternate screen and
+//! vanishes as soon as it is left.
+//!
+//! A [`Session`] keeps track of the enabled [`Mode`]s and tears everything down according
+//! to its [`RestoreOrder`] when dropped.
+//!
+//! ```no_run
+//! use raw_tty::session::{Mode, Session};
+//! use std::io::{self, Write};
+//! use std::fs;
+//!
+//! fn main() -> io::Result<()> {
+//!     let tty = fs::OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+//!     let mut session = Session::new(tty)?;
+//!     session.set_raw_mode()?;
+//!     session.enable(Mode::AlternateScreen)?;
+//!
+//!     write!(session, "Hey there.")
+//! }
+//! ```
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::{TtyLock, TtyWithGuard};
+
+use std::borrow::Cow;
+use std::env;
+use std::io::{self, Read, Write};
+use std::ops;
+use std::os::unix::io::AsRawFd;
+
+/// A terminal mode which is switched on and off with escape sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    /// The alternate screen buffer (`?1049`).
+    AlternateScreen,
+    /// Hides the cursor (`?25`).
+    HideCursor,
+    /// Bracketed paste (`?2004`).
+    BracketedPaste,
+    /// Mouse button reporting with SGR encoded coordinates (`?1000` and `?1006`).
+    Mouse,
+    /// Application cursor keys (DECCKM, `?1`) and application keypad (DECKPAM).
+    ///
+    /// While enabled, arrow keys are sent as `ESC O A` instead of `ESC [ A` and the keypad
+    /// sends its own sequences instead of digits. [`crate::input::Decoder`] decodes those
+    /// as the keys the keypad has in numeric mode.
+    ApplicationKeypad,
+    /// Focus reporting (`?1004`), decoded as
+    /// [`InputEvent::FocusGained`](crate::input::InputEvent::FocusGained) and
+    /// [`InputEvent::FocusLost`](crate::input::InputEvent::FocusLost).
+    FocusEvents,
+    /// The kitty progressive keyboard enhancement (`CSI > flags u`).
+    ///
+    /// Enabling pushes `flags` onto the terminal's keyboard mode stack and disabling pops
+    /// them again. [`crate::input::Decoder`] understands the resulting key encodings.
+    KittyKeyboard(KittyFlags),
+    /// Synchronized output (`?2026`): the terminal holds back drawing until the mode is
+    /// disabled again, so that a frame shows up all at once. See
+    /// [`Session::synchronized`].
+    SynchronizedOutput,
+}
+
+/// The enhancements requested from the kitty keyboard protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KittyFlags(pub u8);
+
+impl KittyFlags {
+    /// Report ambiguous keys (like Escape or Ctrl-i) with `CSI u` encodings.
+    pub const DISAMBIGUATE: KittyFlags = KittyFlags(1);
+    /// Report key repeat and release events.
+    pub const REPORT_EVENT_TYPES: KittyFlags = KittyFlags(2);
+    /// Report shifted and base layout keys.
+    pub const REPORT_ALTERNATE_KEYS: KittyFlags = KittyFlags(4);
+    /// Report every key, including plain text, as an escape sequence.
+    pub const REPORT_ALL_KEYS: KittyFlags = KittyFlags(8);
+    /// Report the text a key would produce along with it.
+    pub const REPORT_TEXT: KittyFlags = KittyFlags(16);
+
+    /// Whether all flags in `other` are set.
+    pub fn contains(self, other: KittyFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl ops::BitOr for KittyFlags {
+    type Output = KittyFlags;
+
+    fn bitor(self, other: KittyFlags) -> KittyFlags {
+        KittyFlags(self.0 | other.0)
+    }
+}
+
+impl Mode {
+    /// The sequence which switches this mode on.
+    pub fn enable_sequence(self) -> Cow<'static, [u8]> {
+        match self {
+            Mode::AlternateScreen => b"\x1b[?1049h"[..].into(),
+            Mode::HideCursor => b"\x1b[?25l"[..].into(),
+            Mode::BracketedPaste => b"\x1b[?2004h"[..].into(),
+            Mode::Mouse => b"\x1b[?1000h\x1b[?1006h"[..].into(),
+            Mode::ApplicationKeypad => b"\x1b[?1h\x1b="[..].into(),
+            Mode::FocusEvents => b"\x1b[?1004h"[..].into(),
+            Mode::KittyKeyboard(flags) => format!("\x1b[>{}u", flags.0).into_bytes().into(),
+            Mode::SynchronizedOutput => b"\x1b[?2026h"[..].into(),
+        }
+    }
+
+    /// The sequence which switches this mode back off.
+    pub fn disable_sequence(self) -> Cow<'static, [u8]> {
+        match self {
+            Mode::AlternateScreen => b"\x1b[?1049l"[..].into(),
+            Mode::HideCursor => b"\x1b[?25h"[..].into(),
+            Mode::BracketedPaste => b"\x1b[?2004l"[..].into(),
+            Mode::Mouse => b"\x1b[?1006l\x1b[?1000l"[..].into(),
+            Mode::ApplicationKeypad => b"\x1b[?1l\x1b>"[..].into(),
+            Mode::FocusEvents => b"\x1b[?1004l"[..].into(),
+            Mode::KittyKeyboard(_) => b"\x1b[<u"[..].into(),
+            Mode::SynchronizedOutput => b"\x1b[?2026l"[..].into(),
+        }
+    }
+}
+
+/// The order in which a [`Session`] undoes its changes when dropped.
+///
+/// Modes are always disabled in the reverse order of being enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RestoreOrder {
+    /// Disable the escape sequence modes first, then restore the termios. Output produced
+    /// after the session is gone lands on the normal screen. This is the default.
+    #[default]
+    ModesFirst,
+    /// Restore the termios first, then disable the escape sequence modes.
+    TermiosFirst,
+}
+
+/// One step of tearing down a [`Session`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Teardown {
+    /// Disable a mode by writing its disable sequence.
+    Disable(Mode),
+    /// Restore the saved termios.
+    RestoreTermios,
+}
+
+/// What the environment says about the terminal's support for escape sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalClass {
+    /// `TERM` is `dumb`, empty or unset: the terminal (or an editor's shell buffer, or a
+    /// CI log) can't be expected to interpret escape sequences.
+    Dumb,
+    /// Anything else, assumed to be xterm compatible.
+    Standard,
+}
+
+impl TerminalClass {
+    /// Classifies the terminal named by `$TERM`.
+    pub fn detect() -> TerminalClass {
+        TerminalClass::from_term(env::var("TERM").ok().as_deref())
+    }
+
+    /// Classifies the terminal named `term`.
+    pub fn from_term(term: Option<&str>) -> TerminalClass {
+        match term {
+            None | Some("") | Some("dumb") => TerminalClass::Dumb,
+            Some(_) => TerminalClass::Standard,
+        }
+    }
+
+    pub fn is_dumb(self) -> bool {
+        self == TerminalClass::Dumb
+    }
+}
+
+/// A tty whose termios and escape sequence modes are restored together on drop.
+pub struct Session<T: AsRawFd + Write> {
+    tty: TtyWithGuard<T>,
+    modes: Vec<Mode>,
+    order: RestoreOrder,
+    class: TerminalClass,
+    /// Released last, after the termios was restored.
+    _lock: Option<TtyLock>,
+}
+
+impl<T: AsRawFd + Write> ops::Deref for Session<T> {
+    type Target = TtyWithGuard<T>;
+
+    #[inline]
+    fn deref(&self) -> &TtyWithGuard<T> {
+        &self.tty
+    }
+}
+
+impl<T: AsRawFd + Write> ops::DerefMut for Session<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut TtyWithGuard<T> {
+        &mut self.tty
+    }
+}
+
+impl<T: AsRawFd + Write> Session<T> {
+    /// Saves the termios of `tty`. No modes are enabled yet.
+    ///
+    /// The [`TerminalClass`] is detected from the environment.
+    pub fn new(tty: T) -> io::Result<Session<T>> {
+        Session::with_class(tty, TerminalClass::detect())
+    }
+
+    /// Like [`new`](Session::new), for a terminal of the given class.
+    ///
+    /// On a [`TerminalClass::Dumb`] terminal, enabling modes does nothing, since their
+    /// escape sequences would only show up as garbage. The termios can be changed as usual.
+    pub fn with_class(tty: T, class: TerminalClass) -> io::Result<Session<T>> {
+        Session::builder(tty).class(class).build()
+    }
+
+    /// Starts building a session, for the options which [`new`](Session::new) doesn't
+    /// take.
+    pub fn builder(tty: T) -> SessionBuilder<T> {
+        SessionBuilder {
+            tty,
+            class: None,
+            order: RestoreOrder::default(),
+            lock: false,
+        }
+    }
+
+    /// The class of the terminal, which decides whether modes can be enabled.
+    pub fn terminal_class(&self) -> TerminalClass {
+        self.class
+    }
+
+    /// Sets the order in which the session is torn down.
+    pub fn set_restore_order(&mut self, order: RestoreOrder) {
+        self.order = order;
+    }
+
+    /// The order in which the session is torn down.
+    pub fn restore_order(&self) -> RestoreOrder {
+        self.order
+    }
+
+    /// The currently enabled modes, in the order they were enabled.
+    pub fn modes(&self) -> &[Mode] {
+        &self.modes
+    }
+
+    /// Whether `mode` is currently enabled.
+    pub fn is_enabled(&self, mode: Mode) -> bool {
+        self.modes.contains(&mode)
+    }
+
+    /// Enables `mode`. Does nothing if it is already enabled or the terminal is dumb.
+    pub fn enable(&mut self, mode: Mode) -> io::Result<()> {
+        if self.class.is_dumb() || self.modes.contains(&mode) {
+            return Ok(());
+        }
+        self.tty.write_all(&mode.enable_sequence())?;
+        self.tty.flush()?;
+        self.modes.push(mode);
+        Ok(())
+    }
+
+    /// Disables `mode`. Does nothing if it isn't enabled.
+    pub fn disable(&mut self, mode: Mode) -> io::Result<()> {
+        if let Some(index) = self.modes.iter().position(|&m| m == mode) {
+            self.tty.write_all(&mode.disable_sequence())?;
+            self.tty.flush()?;
+            self.modes.remove(index);
+        }
+        Ok(())
+    }
+
+    /// Runs `f`, which draws a frame, inside a synchronized update, so that the terminal
+    /// doesn't show it half drawn. The update ends and the output is flushed even if `f`
+    /// fails.
+    ///
+    /// Terminals without synchronized output ignore the sequences, and nested calls only
+    /// end the update when the outermost returns. Whether the terminal supports it can be
+    /// found out with [`TerminalCapabilities`](crate::query::TerminalCapabilities), e.g.
+    /// to only skip redundant redraws where it doesn't.
+    ///
+    /// ```no_run
+    /// use raw_tty::session::Session;
+    /// use std::io::{self, stdout, Write};
+    ///
+    /// fn main() -> io::Result<()> {
+    ///     let mut session = Session::new(stdout())?;
+    ///     session.synchronized(|out| write!(out, "\x1b[H\x1b[2Jframe"))
+    /// }
+    /// ```
+    pub fn synchronized<R>(
+        &mut self,
+        f: impl FnOnce(&mut Session<T>) -> io::Result<R>,
+    ) -> io::Result<R> {
+        let outermost = !self.is_enabled(Mode::SynchronizedOutput);
+        self.enable(Mode::SynchronizedOutput)?;
+        let result = f(self);
+        if outermost {
+            self.disable(Mode::SynchronizedOutput)?;
+        }
+        result
+    }
+
+    /// The steps which dropping the session would perform, in order.
+    pub fn teardown_plan(&self) -> Vec<Teardown> {
+        let modes = self.modes.iter().rev().map(|&mode| Teardown::Disable(mode));
+        match self.order {
+            RestoreOrder::ModesFirst => modes.chain(Some(Teardown::RestoreTermios)).collect(),
+            RestoreOrder::TermiosFirst => Some(Teardown::RestoreTermios)
+                .into_iter()
+                .chain(modes)
+                .collect(),
+        }
+    }
+
+    /// Disables all modes and restores the termios, according to the restore order.
+    ///
+    /// Every step is performed even if an earlier one failed, e.g. the termios is restored
+    /// although writing the disable sequences failed; the first error is returned. The
+    /// session stays usable afterwards, as if it had just been created.
+    pub fn restore(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        for step in self.teardown_plan() {
+            let step = match step {
+                Teardown::Disable(mode) => self.tty.write_all(&mode.disable_sequence()),
+                Teardown::RestoreTermios => {
+                    let flushed = self.tty.flush();
+                    self.tty.restore().and(flushed)
+                }
+            };
+            result = result.and(step);
+        }
+        result = result.and(self.tty.flush());
+        self.modes.clear();
+        result
+    }
+}
+
+/// Builds a [`Session`], see [`Session::builder`].
+pub struct SessionBuilder<T: AsRawFd + Write> {
+    tty: T,
+    class: Option<TerminalClass>,
+    order: RestoreOrder,
+    lock: bool,
+}
+
+impl<T: AsRawFd + Write> SessionBuilder<T> {
+    /// The class of the terminal, instead of detecting it from the environment.
+    pub fn class(mut self, class: TerminalClass) -> SessionBuilder<T> {
+        self.class = Some(class);
+        self
+    }
+
+    /// The order in which the session is torn down.
+    pub fn restore_order(mut self, order: RestoreOrder) -> SessionBuilder<T> {
+        self.order = order;
+        self
+    }
+
+    /// Holds a [`TtyLock`] for the lifetime of the session. `build` waits for the lock
+    /// before saving the termios, and it is released after the termios was restored, so
+    /// that cooperating processes never see each other's half restored terminal.
+    pub fn lock(mut self) -> SessionBuilder<T> {
+        self.lock = true;
+        self
+    }
+
+    pub fn build(self) -> io::Result<Session<T>> {
+        let lock = match self.lock {
+            true => Some(TtyLock::acquire(&self.tty)?),
+            false => None,
+        };
+        Ok(Session {
+            tty: TtyWithGuard::new(self.tty)?,
+            modes: Vec::new(),
+            order: self.order,
+            class: self.class.unwrap_or_else(TerminalClass::detect),
+            _lock: lock,
+        })
+    }
+}
+
+impl<T: AsRawFd + Write> Drop for Session<T> {
+    fn drop(&mut self) {
+        let _ = self.restore();
+    }
+}
+
+impl<T: AsRawFd + Write> Write for Session<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tty.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.tty.flush()
+    }
+}
+
+impl<T: AsRawFd + Write + Read> Read for Session<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.tty.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::attr::get_terminal_attr;
+    use crate::util::openpty;
+    use std::fs::File;
+    use std::os::unix::io::RawFd;
+
+    /// Records whether the tty was still in raw mode for every write.
+    struct Recorder {
+        file: File,
+        writes: Vec<(Vec<u8>, bool)>,
+        /// Makes every write fail, e.g. like a closed pipe.
+        failing: bool,
+    }
+
+    impl AsRawFd for Recorder {
+        fn as_raw_fd(&self) -> RawFd {
+            self.file.as_raw_fd()
+        }
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.failing {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            let ios = get_terminal_attr(self.as_raw_fd())?;
+            let raw = ios.c_lflag & libc::ICANON == 0;
+            self.writes.push((buf.to_vec(), raw));
+            self.file.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.file.flush()
+        }
+    }
+
+    fn alternate_screen_left_in_raw_mode(order: RestoreOrder) -> io::Result<bool> {
+        let (_master, slave) = openpty()?;
+        let mut session = Session::with_class(
+            Recorder {
+                file: slave,
+                writes: Vec::new(),
+                failing: false,
+            },
+            TerminalClass::Standard,
+        )?;
+        session.set_restore_order(order);
+        session.set_raw_mode()?;
+        session.enable(Mode::AlternateScreen)?;
+        session.restore()?;
+
+        let leave = Mode::AlternateScreen.disable_sequence();
+        let &(_, raw) = session
+            .writes
+            .iter()
+            .find(|(bytes, _)| bytes.as_slice() == &*leave)
+            .expect("alternate screen wasn't left");
+        Ok(raw)
+    }
+
+    #[test]
+    fn test_builder_lock() -> io::Result<()> {
+        let (_master, slave) = crate::util::openpty()?;
+        let other = slave.try_clone()?;
+        let session = Session::builder(slave)
+            .class(TerminalClass::Dumb)
+            .lock()
+            .build()?;
+        assert_eq!(session.terminal_class(), TerminalClass::Dumb);
+        assert!(TtyLock::try_acquire(&other)?.is_none());
+        drop(session);
+        assert!(TtyLock::try_acquire(&other)?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_alternate_screen_left_before_cooked_mode() -> io::Result<()> {
+        // Leaving the alternate screen only after restoring cooked mode is what makes a
+        // prompt printed in between disappear.
+        assert!(alternate_screen_left_in_raw_mode(RestoreOrder::ModesFirst)?);
+        assert!(!alternate_screen_left_in_raw_mode(
+            RestoreOrder::TermiosFirst
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_after_failed_write() -> io::Result<()> {
+        let (_master, slave) = openpty()?;
+        let fd = slave.as_raw_fd();
+        let mut session = Session::with_class(
+            Recorder {
+                file: slave,
+                writes: Vec::new(),
+                failing: false,
+            },
+            TerminalClass::Standard,
+        )?;
+        session.set_raw_mode()?;
+        session.enable(Mode::AlternateScreen)?;
+        session.failing = true;
+        let error = session.restore().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+        // The termios was restored all the same.
+        assert_ne!(get_terminal_attr(fd)?.c_lflag & libc::ICANON, 0);
+        assert!(!session.is_enabled(Mode::AlternateScreen));
+        Ok(())
+    }
+
+    #[test]
+    fn test_teardown_plan() -> io::Result<()> {
+        let (_master, slave) = openpty()?;
+        let mut session = Session::with_class(slave, TerminalClass::Standard)?;
+        session.enable(Mode::AlternateScreen)?;
+        session.enable(Mode::Mouse)?;
+        assert_eq!(
+            session.teardown_plan(),
+            vec![
+                Teardown::Disable(Mode::Mouse),
+                Teardown::Disable(Mode::AlternateScreen),
+                Teardown::RestoreTermios
+            ]
+        );
+        session.set_restore_order(RestoreOrder::TermiosFirst);
+        assert_eq!(session.teardown_plan()[0], Teardown::RestoreTermios);
+        Ok(())
+    }
+
+    #[test]
+    fn test_application_keypad_restored() -> io::Result<()> {
+        let (mut master, slave) = openpty()?;
+        let mut session = Session::with_class(slave, TerminalClass::Standard)?;
+        session.enable(Mode::ApplicationKeypad)?;
+        assert!(session.is_enabled(Mode::ApplicationKeypad));
+        drop(session);
+
+        let mut buf = [0; 14];
+        master.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"\x1b[?1h\x1b=\x1b[?1l\x1b>");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dumb_terminal() -> io::Result<()> {
+        assert!(TerminalClass::from_term(None).is_dumb());
+        assert!(TerminalClass::from_term(Some("dumb")).is_dumb());
+        assert!(!TerminalClass::from_term(Some("xterm-256color")).is_dumb());
+
+        let (mut master, slave) = openpty()?;
+        let mut session = Session::with_class(slave, TerminalClass::Dumb)?;
+        session.set_raw_mode()?;
+        session.enable(Mode::AlternateScreen)?;
+        assert!(!session.is_enabled(Mode::AlternateScreen));
+        session.write_all(b"x")?;
+        drop(session);
+
+        let mut buf = [0; 1];
+        master.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"x");
+        Ok(())
+    }
+
+    #[test]
+    fn test_synchronized() -> io::Result<()> {
+        let (mut master, slave) = openpty()?;
+        let mut session = Session::with_class(slave, TerminalClass::Standard)?;
+        session.set_raw_mode()?;
+        let result = session.synchronized(|out| {
+            out.write_all(b"a")?;
+            out.synchronized(|out| out.write_all(b"b"))?;
+            assert!(out.is_enabled(Mode::SynchronizedOutput));
+            Err::<(), _>(io::Error::other("drawing failed"))
+        });
+        assert!(result.is_err());
+        assert!(!session.is_enabled(Mode::SynchronizedOutput));
+
+        let mut buf = [0; 18];
+        master.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"\x1b[?2026hab\x1b[?2026l");
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+//! Line speeds, for serial ports.
+//!
+//! A termios stores the speed as one of the `B*` constants rather than as a number, and
+//! rates without a constant need the `termios2` ioctls on Linux, see
+//! [`bare::termios2`](crate::bare::termios2). [`Speed`] covers both:
+//!
+//! ```no_run
+//! use raw_tty::{GuardMode, Speed};
+//! use std::fs::OpenOptions;
+//! use std::io;
+//!
+//! fn main() -> io::Result<()> {
+//!     let port = OpenOptions::new().read(true).write(true).open("/dev/ttyUSB0")?;
+//!     let mut port = port.guard_mode()?;
+//!     port.set_raw_mode()?;
+//!     port.set_speed(Speed::B115200)?;
+//!     // DMX512, which has no constant.
+//!     # #[cfg(target_os = "linux")]
+//!     port.set_speed(Speed::Custom(250_000))?;
+//!     assert_eq!(port.speed()?.baud(), 250_000);
+//!     Ok(())
+//! }
+//! ```
+
+use crate::{Termios, TtyModeGuard};
+
+use libc::speed_t;
+use std::io;
+
+macro_rules! speeds {
+    ($($(#[$cfg:meta])* $name:ident = $baud:literal,)*) => {
+        /// A line speed, in baud.
+        ///
+        /// The variants are the rates with a `B*` constant on the platform. Other rates are
+        /// [`Custom`](Speed::Custom), on Linux only; [`Speed::from_baud`] picks the constant
+        /// where there is one. `B0` hangs up the line.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Speed {
+            $($(#[$cfg])* $name,)*
+            /// A rate without a constant, set with `BOTHER` and the `termios2` ioctls.
+            #[cfg(target_os = "linux")]
+            Custom(u32),
+        }
+
+        impl Speed {
+            /// The rate in baud.
+            pub fn baud(self) -> u32 {
+                match self {
+                    $($(#[$cfg])* Speed::$name => $baud,)*
+                    #[cfg(target_os = "linux")]
+                    Speed::Custom(baud) => baud,
+                }
+            }
+
+            /// The speed for `baud`, or `None` if it has no constant on a platform without
+            /// custom rates.
+            pub fn from_baud(baud: u32) -> Option<Speed> {
+                match baud {
+                    $($(#[$cfg])* $baud => Some(Speed::$name),)*
+                    #[cfg(target_os = "linux")]
+                    _ => Some(Speed::Custom(baud)),
+                    #[cfg(not(target_os = "linux"))]
+                    _ => None,
+                }
+            }
+
+            /// The `B*` constant, unless it is a custom rate.
+            pub fn to_speed_t(self) -> Option<speed_t> {
+                match self {
+                    $($(#[$cfg])* Speed::$name => Some(libc::$name),)*
+                    #[cfg(target_os = "linux")]
+                    Speed::Custom(_) => None,
+                }
+            }
+
+            /// The speed for a `B*` constant.
+            pub fn from_speed_t(speed: speed_t) -> Option<Speed> {
+                $($(#[$cfg])* {
+                    if speed == libc::$name {
+                        return Some(Speed::$name);
+                    }
+                })*
+                None
+            }
+        }
+    };
+}
+
+speeds! {
+    B0 = 0,
+    B50 = 50,
+    B75 = 75,
+    B110 = 110,
+    B134 = 134,
+    B150 = 150,
+    B200 = 200,
+    B300 = 300,
+    B600 = 600,
+    B1200 = 1200,
+    B1800 = 1800,
+    B2400 = 2400,
+    B4800 = 4800,
+    B9600 = 9600,
+    B19200 = 19200,
+    B38400 = 38400,
+    B57600 = 57600,
+    B115200 = 115200,
+    B230400 = 230400,
+    #[cfg(target_os = "linux")]
+    B460800 = 460800,
+    #[cfg(target_os = "linux")]
+    B500000 = 500000,
+    #[cfg(target_os = "linux")]
+    B576000 = 576000,
+    #[cfg(target_os = "linux")]
+    B921600 = 921600,
+    #[cfg(target_os = "linux")]
+    B1000000 = 1000000,
+    #[cfg(target_os = "linux")]
+    B1152000 = 1152000,
+    #[cfg(target_os = "linux")]
+    B1500000 = 1500000,
+    #[cfg(target_os = "linux")]
+    B2000000 = 2000000,
+    #[cfg(target_os = "linux")]
+    B2500000 = 2500000,
+    #[cfg(target_os = "linux")]
+    B3000000 = 3000000,
+    #[cfg(target_os = "linux")]
+    B3500000 = 3500000,
+    #[cfg(target_os = "linux")]
+    B4000000 = 4000000,
+}
+
+use crate::bare::termios2;
+
+fn from_errno(e: crate::bare::Errno) -> io::Error {
+    io::Error::from_raw_os_error(e.0)
+}
+
+impl Termios {
+    /// The input speed, custom rates included.
+    pub fn input_speed(&self) -> Option<Speed> {
+        match termios2::custom_speeds(self.as_bare()) {
+            Some((input, _)) => Speed::from_baud(input),
+            None => Speed::from_speed_t(unsafe { libc::cfgetispeed(self.as_libc()) }),
+        }
+    }
+
+    /// The output speed, custom rates included.
+    pub fn output_speed(&self) -> Option<Speed> {
+        match termios2::custom_speeds(self.as_bare()) {
+            Some((_, output)) => Speed::from_baud(output),
+            None => Speed::from_speed_t(unsafe { libc::cfgetospeed(self.as_libc()) }),
+        }
+    }
+
+    /// Sets the input and output speed.
+    ///
+    /// A custom rate is stored with `BOTHER`, and set with `TCSETS2` instead of `tcsetattr`
+    /// when this termios is applied. Fails with `EINVAL` where Linux has no `termios2`.
+    pub fn set_speed(&mut self, speed: Speed) -> io::Result<()> {
+        let speed = match speed.to_speed_t() {
+            Some(speed) => speed,
+            None => {
+                return termios2::set_custom_speed(self.as_bare_mut(), speed.baud())
+                    .map_err(from_errno)
+            }
+        };
+        termios2::clear_input_speed(self.as_bare_mut());
+        let ios = self.as_libc_mut();
+        crate::util::convert_to_result(unsafe { libc::cfsetispeed(ios, speed) })?;
+        crate::util::convert_to_result(unsafe { libc::cfsetospeed(ios, speed) })?;
+        Ok(())
+    }
+}
+
+impl TtyModeGuard {
+    /// Sets the input and output speed of the line, custom rates included.
+    ///
+    /// Restoring puts the saved speed back too, also when it was a custom one.
+    pub fn set_speed(&mut self, speed: Speed) -> io::Result<()> {
+        let mut ios = self.get_attr()?;
+        ios.set_speed(speed)?;
+        self.set_attr("set_speed", &ios)
+    }
+
+    /// The output speed of the line.
+    pub fn speed(&self) -> io::Result<Speed> {
+        self.get_attr()?.output_speed().ok_or_else(unknown_speed)
+    }
+
+    /// The input speed of the line.
+    pub fn input_speed(&self) -> io::Result<Speed> {
+        self.get_attr()?.input_speed().ok_or_else(unknown_speed)
+    }
+}
+
+fn unknown_speed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "the tty has a speed without a constant",
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::attr::get_terminal_attr;
+    use crate::util;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn test_speed() -> io::Result<()> {
+        assert_eq!(Speed::from_baud(115200), Some(Speed::B115200));
+        assert_eq!(Speed::B9600.to_speed_t(), Some(libc::B9600));
+        assert_eq!(Speed::from_speed_t(libc::B38400), Some(Speed::B38400));
+
+        let (_master, slave) = util::openpty()?;
+        let fd = slave.as_raw_fd();
+        let saved = get_terminal_attr(fd)?.output_speed();
+        let mut guard = TtyModeGuard::new(fd)?;
+        guard.set_speed(Speed::B9600)?;
+        assert_eq!(guard.speed()?, Speed::B9600);
+        assert_eq!(guard.input_speed()?, Speed::B9600);
+        assert_eq!(get_terminal_attr(fd)?.output_speed(), Some(Speed::B9600));
+
+        #[cfg(target_os = "linux")]
+        {
+            guard.set_speed(Speed::Custom(250_000))?;
+            assert_eq!(guard.speed()?, Speed::Custom(250_000));
+            assert_eq!(guard.input_speed()?, Speed::Custom(250_000));
+            // A custom speed is saved and restored like any other.
+            let mut nested = TtyModeGuard::new(fd)?;
+            nested.set_speed(Speed::B4800)?;
+            assert_eq!(nested.input_speed()?, Speed::B4800);
+            drop(nested);
+            assert_eq!(
+                get_terminal_attr(fd)?.output_speed(),
+                Some(Speed::Custom(250_000))
+            );
+        }
+
+        drop(guard);
+        assert_eq!(get_terminal_attr(fd)?.output_speed(), saved);
+        Ok(())
+    }
+}
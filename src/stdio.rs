@@ -0,0 +1,176 @@
+//! Finding the terminal among the standard streams, for programs which are piped but still
+//! interactive.
+//!
+//! `git log | less` or `fzf < list` have their data on a pipe, while the user is still
+//! reachable through one of the other streams. Like the `isatty` cascades of pagers,
+//! [`guard_first_tty`] tries stdout, stderr and stdin in that order and guards the first
+//! which is a terminal, telling which one it picked.
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::TtyWithGuard;
+
+use std::fs::File;
+use std::io::{self, IsTerminal, Read, Write};
+use std::os::unix::io::{AsFd, AsRawFd, RawFd};
+
+/// One of the standard streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StdStream {
+    Stdout,
+    Stderr,
+    Stdin,
+}
+
+impl StdStream {
+    /// The order in which [`first_tty`] tries the streams.
+    pub const ORDER: [StdStream; 3] = [StdStream::Stdout, StdStream::Stderr, StdStream::Stdin];
+
+    pub fn fd(self) -> RawFd {
+        match self {
+            StdStream::Stdout => libc::STDOUT_FILENO,
+            StdStream::Stderr => libc::STDERR_FILENO,
+            StdStream::Stdin => libc::STDIN_FILENO,
+        }
+    }
+
+    pub fn is_tty(self) -> bool {
+        match self {
+            StdStream::Stdout => io::stdout().is_terminal(),
+            StdStream::Stderr => io::stderr().is_terminal(),
+            StdStream::Stdin => io::stdin().is_terminal(),
+        }
+    }
+
+    fn try_clone(self) -> io::Result<File> {
+        let fd = match self {
+            StdStream::Stdout => io::stdout().as_fd().try_clone_to_owned(),
+            StdStream::Stderr => io::stderr().as_fd().try_clone_to_owned(),
+            StdStream::Stdin => io::stdin().as_fd().try_clone_to_owned(),
+        };
+        fd.map(File::from)
+    }
+}
+
+/// The first of stdout, stderr and stdin which is a terminal.
+pub fn first_tty() -> Option<StdStream> {
+    first_matching(StdStream::is_tty)
+}
+
+fn first_matching(is_tty: impl Fn(StdStream) -> bool) -> Option<StdStream> {
+    StdStream::ORDER
+        .iter()
+        .copied()
+        .find(|&stream| is_tty(stream))
+}
+
+/// Guards the [first standard stream which is a terminal](first_tty).
+///
+/// Fails with a [`NotATty`](crate::NotATty) error for stdin if none is, where
+/// [`OrControllingTty`](crate::OrControllingTty) can fall back to `/dev/tty`.
+///
+/// ```no_run
+/// use raw_tty::{guard_first_tty, StdStream};
+/// use std::io::{self, Read, Write};
+///
+/// fn main() -> io::Result<()> {
+///     // E.g. `producer | this-program > out.txt`: the keys come from stderr's terminal.
+///     let mut tty = guard_first_tty()?;
+///     tty.set_raw_mode()?;
+///     if tty.stream() == StdStream::Stderr {
+///         eprintln!("using stderr for the prompt\r");
+///     }
+///     let mut key = [0];
+///     tty.read_exact(&mut key)?;
+///     tty.write_all(b"thanks\r\n")
+/// }
+/// ```
+pub fn guard_first_tty() -> io::Result<TtyWithGuard<StdTty>> {
+    let stream = first_tty().unwrap_or(StdStream::Stdin);
+    TtyWithGuard::new(StdTty::new(stream)?)
+}
+
+/// A standard stream used as the terminal, see [`guard_first_tty`].
+///
+/// It reads and writes from the terminal whichever stream it is: terminals are opened for
+/// both, so keys can be read from stderr too. Writes to stdout and stderr go through the
+/// standard library's handles, to keep their order with `print!` and `eprint!`, and reads
+/// from stdin through its buffer.
+#[derive(Debug)]
+pub struct StdTty {
+    stream: StdStream,
+    /// A duplicate of the stream's fd, for reading stdout and stderr and writing stdin.
+    file: File,
+}
+
+impl StdTty {
+    pub fn new(stream: StdStream) -> io::Result<StdTty> {
+        Ok(StdTty {
+            stream,
+            file: stream.try_clone()?,
+        })
+    }
+
+    /// Which stream this is.
+    pub fn stream(&self) -> StdStream {
+        self.stream
+    }
+}
+
+impl AsRawFd for StdTty {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Read for StdTty {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.stream {
+            StdStream::Stdin => io::stdin().read(buf),
+            _ => self.file.read(buf),
+        }
+    }
+}
+
+impl Write for StdTty {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.stream {
+            StdStream::Stdout => io::stdout().write(buf),
+            StdStream::Stderr => io::stderr().write(buf),
+            StdStream::Stdin => self.file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stream {
+            StdStream::Stdout => io::stdout().flush(),
+            StdStream::Stderr => io::stderr().flush(),
+            StdStream::Stdin => self.file.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_first_tty() -> io::Result<()> {
+        // `producer | program > out.txt`
+        let only_stderr = |stream| stream == StdStream::Stderr;
+        assert_eq!(first_matching(only_stderr), Some(StdStream::Stderr));
+        assert_eq!(first_matching(|_| true), Some(StdStream::Stdout));
+        assert_eq!(first_matching(|_| false), None);
+
+        // Whatever the test runner connected, the choice agrees with isatty.
+        let clones = StdStream::ORDER.map(|stream| stream.try_clone().map(|f| f.is_terminal()));
+        assert_eq!(
+            first_tty(),
+            first_matching(|stream| *clones[stream as usize].as_ref().unwrap_or(&false))
+        );
+        let tty = StdTty::new(StdStream::Stdin)?;
+        assert_eq!(tty.stream(), StdStream::Stdin);
+        assert_ne!(tty.as_raw_fd(), libc::STDIN_FILENO);
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+//! The terminal syscalls the guards make, behind a trait object.
+//!
+//! Normally every call goes straight to [`bare`](crate::bare). In tests, and with the
+//! `fake-syscalls` feature, an fd can be claimed by a fake instead, see
+//! [`testing::FakeTty`](crate::testing::FakeTty). That way the guard registry, [`exit`] and
+//! the restore paths can run under miri, or with many threads at once, without a kernel
+//! terminal behind them. Fds which weren't claimed keep using the real syscalls, so fakes
+//! don't get in the way of other tests running in parallel.
+//!
+//! [`exit`]: crate::exit
+
+use crate::attr::Termios;
+use crate::bare;
+use crate::JobControl;
+
+use std::io;
+use std::os::unix::io::RawFd;
+#[cfg(any(test, feature = "fake-syscalls"))]
+use std::sync::{Arc, Mutex};
+
+pub(crate) trait Syscalls: Send + Sync {
+    /// `tcgetattr`.
+    fn get_attr(&self, fd: RawFd) -> io::Result<Termios>;
+    /// `tcsetattr` with `TCSANOW`.
+    fn set_attr(&self, fd: RawFd, termios: &Termios) -> io::Result<()>;
+    /// `tcgetpgrp`, compared with the process group of the process.
+    fn job_control(&self, fd: RawFd) -> io::Result<JobControl>;
+}
+
+struct Real;
+
+fn from_errno(e: bare::Errno) -> io::Error {
+    io::Error::from_raw_os_error(e.0)
+}
+
+impl Syscalls for Real {
+    fn get_attr(&self, fd: RawFd) -> io::Result<Termios> {
+        bare::get_attr(fd).map(Termios::from).map_err(from_errno)
+    }
+
+    fn set_attr(&self, fd: RawFd, termios: &Termios) -> io::Result<()> {
+        bare::set_attr(fd, termios.as_bare()).map_err(from_errno)
+    }
+
+    fn job_control(&self, fd: RawFd) -> io::Result<JobControl> {
+        match unsafe { libc::tcgetpgrp(fd) } {
+            -1 => match io::Error::last_os_error() {
+                ref e if e.raw_os_error() == Some(libc::ENOTTY) => Ok(JobControl::Unavailable),
+                e => Err(e),
+            },
+            pgrp if pgrp == unsafe { libc::getpgrp() } => Ok(JobControl::Foreground),
+            _ => Ok(JobControl::Background),
+        }
+    }
+}
+
+/// The fakes which claimed an fd.
+#[cfg(any(test, feature = "fake-syscalls"))]
+static FAKES: Mutex<Vec<(RawFd, Arc<dyn Syscalls>)>> = Mutex::new(Vec::new());
+
+/// Makes the calls for `fd` go to `fake` until [`release`] is called.
+#[cfg(any(test, feature = "fake-syscalls"))]
+pub(crate) fn claim(fd: RawFd, fake: Arc<dyn Syscalls>) {
+    let mut fakes = FAKES.lock().unwrap_or_else(|e| e.into_inner());
+    fakes.retain(|(claimed, _)| *claimed != fd);
+    fakes.push((fd, fake));
+}
+
+#[cfg(any(test, feature = "fake-syscalls"))]
+pub(crate) fn release(fd: RawFd) {
+    FAKES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|(claimed, _)| *claimed != fd);
+}
+
+/// Runs `f` with the syscalls which are responsible for `fd`.
+#[cfg_attr(not(any(test, feature = "fake-syscalls")), allow(unused_variables))]
+pub(crate) fn with<T>(fd: RawFd, f: impl FnOnce(&dyn Syscalls) -> T) -> T {
+    #[cfg(any(test, feature = "fake-syscalls"))]
+    {
+        let fake = FAKES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|(claimed, _)| *claimed == fd)
+            .map(|(_, fake)| fake.clone());
+        // Called without holding the lock, a fake may block to force an interleaving.
+        if let Some(fake) = fake {
+            return f(&*fake);
+        }
+    }
+    f(&Real)
+}
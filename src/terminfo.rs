@@ -0,0 +1,349 @@
+//! Looking up key and reset sequences in the terminfo database.
+//!
+//! The built-in tables of [`Decoder`] cover xterm compatible terminals, which is nearly
+//! all of them. For the remaining exotic ones, this module reads the compiled terminfo
+//! entry of the terminal and binds its key sequences on a decoder, with the built-in
+//! decoding staying in place as the fallback. It only understands the capabilities this
+//! crate has a use for and doesn't evaluate parameterized strings.
+//!
+//! ```no_run
+//! use raw_tty::input::Decoder;
+//! use raw_tty::terminfo::Terminfo;
+//! use raw_tty::GuardMode;
+//! use std::io::{self, stdin};
+//!
+//! fn main() -> io::Result<()> {
+//!     let terminfo = Terminfo::from_env()?;
+//!     let mut decoder = Decoder::new();
+//!     terminfo.bind_keys(&mut decoder);
+//!
+//!     let mut stdin = stdin().guard_mode()?;
+//!     stdin.restore_with_reset_sequence(&terminfo.reset_sequence());
+//!     stdin.set_raw_mode()?;
+//!     Ok(())
+//! }
+//! ```
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::input::{Decoder, Key, KeyEvent, Modifiers};
+use crate::BellKind;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// The magic number of the legacy format, with 16-bit numbers.
+const MAGIC_LEGACY: u16 = 0o432;
+/// The magic number of the extended number format, with 32-bit numbers.
+const MAGIC_32BIT: u16 = 0o1036;
+
+// Indices into the string capabilities, as defined by term.h.
+const BELL: usize = 1;
+const CURSOR_NORMAL: usize = 16;
+const EXIT_ATTRIBUTE_MODE: usize = 39;
+const FLASH_SCREEN: usize = 45;
+const KEYPAD_LOCAL: usize = 88;
+
+const KEYS: &[(usize, Key)] = &[
+    (55, Key::Backspace),
+    (59, Key::Delete),
+    (61, Key::Down),
+    (66, Key::F(1)),
+    (67, Key::F(10)),
+    (68, Key::F(2)),
+    (69, Key::F(3)),
+    (70, Key::F(4)),
+    (71, Key::F(5)),
+    (72, Key::F(6)),
+    (73, Key::F(7)),
+    (74, Key::F(8)),
+    (75, Key::F(9)),
+    (76, Key::Home),
+    (77, Key::Insert),
+    (79, Key::Left),
+    (81, Key::PageDown),
+    (82, Key::PageUp),
+    (83, Key::Right),
+    (87, Key::Up),
+    (164, Key::End),
+    (216, Key::F(11)),
+    (217, Key::F(12)),
+];
+
+/// The key back tab, which is reported as shift + tab.
+const KEY_BTAB: usize = 148;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A compiled terminfo entry.
+#[derive(Debug, Clone)]
+pub struct Terminfo {
+    names: Vec<String>,
+    strings: Vec<Option<Vec<u8>>>,
+}
+
+impl Terminfo {
+    /// Loads the entry for the terminal named by `$TERM`.
+    pub fn from_env() -> io::Result<Terminfo> {
+        let term = env::var("TERM")
+            .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "TERM is not set"))?;
+        Terminfo::load(&term)
+    }
+
+    /// Loads the entry for `term`, searching the usual terminfo directories.
+    pub fn load(term: &str) -> io::Result<Terminfo> {
+        let first = match term.chars().next() {
+            Some(c) if !term.contains('/') => c,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid TERM")),
+        };
+        for dir in search_dirs() {
+            // Linux uses the first letter as the directory, macOS its hex code.
+            for sub in &[first.to_string(), format!("{:x}", first as u32)] {
+                if let Ok(data) = fs::read(dir.join(sub).join(term)) {
+                    return Terminfo::parse(&data);
+                }
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no terminfo entry found",
+        ))
+    }
+
+    /// Parses a compiled terminfo entry.
+    pub fn parse(data: &[u8]) -> io::Result<Terminfo> {
+        let short = |i: usize| -> io::Result<i16> {
+            data.get(i..i + 2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                .ok_or_else(|| invalid("truncated terminfo entry"))
+        };
+        let count = |i: usize| -> io::Result<usize> {
+            let n = short(i)?;
+            if n < 0 {
+                return Err(invalid("negative terminfo section size"));
+            }
+            Ok(n as usize)
+        };
+
+        let number_size = match short(0)? as u16 {
+            MAGIC_LEGACY => 2,
+            MAGIC_32BIT => 4,
+            _ => return Err(invalid("not a terminfo entry")),
+        };
+        let names_size = count(2)?;
+        let bools = count(4)?;
+        let numbers = count(6)?;
+        let strings = count(8)?;
+        let table_size = count(10)?;
+
+        let names_start = 12;
+        let mut offsets_start = names_start + names_size + bools;
+        if offsets_start % 2 == 1 {
+            offsets_start += 1;
+        }
+        offsets_start += numbers * number_size;
+        let table_start = offsets_start + strings * 2;
+        let table = data
+            .get(table_start..table_start + table_size)
+            .ok_or_else(|| invalid("truncated terminfo entry"))?;
+
+        let names = data
+            .get(names_start..names_start + names_size)
+            .ok_or_else(|| invalid("truncated terminfo entry"))?;
+        let names = String::from_utf8_lossy(names.split(|&b| b == 0).next().unwrap_or(names))
+            .split('|')
+            .map(String::from)
+            .collect();
+
+        let strings = (0..strings)
+            .map(|i| {
+                let offset = short(offsets_start + i * 2)?;
+                if offset < 0 {
+                    return Ok(None);
+                }
+                let string = table.get(offset as usize..).unwrap_or(&[]);
+                let end = string.iter().position(|&b| b == 0).unwrap_or(string.len());
+                Ok(Some(string[..end].to_vec()))
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Terminfo { names, strings })
+    }
+
+    /// The names of the terminal, the first being the one it was looked up with.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The string capability at `index`, in the order defined by term.h.
+    pub fn string(&self, index: usize) -> Option<&[u8]> {
+        self.strings.get(index)?.as_deref()
+    }
+
+    /// The key sequences defined by the entry.
+    pub fn keys(&self) -> Vec<(&[u8], KeyEvent)> {
+        let mut keys: Vec<_> = KEYS
+            .iter()
+            .filter_map(|&(index, key)| Some((self.string(index)?, KeyEvent::from(key))))
+            .collect();
+        if let Some(sequence) = self.string(KEY_BTAB) {
+            keys.push((sequence, KeyEvent::new(Key::Tab, Modifiers::SHIFT)));
+        }
+        keys.retain(|(sequence, _)| !sequence.is_empty());
+        keys
+    }
+
+    /// Binds the key sequences of the entry on `decoder`.
+    pub fn bind_keys(&self, decoder: &mut Decoder) {
+        for (sequence, key) in self.keys() {
+            decoder.bind(sequence, key);
+        }
+    }
+
+    /// The `bel` or `flash` capability, padding included, see
+    /// [`TtyWithGuard::bell`](crate::TtyWithGuard::bell).
+    pub fn bell(&self, kind: BellKind) -> Option<&[u8]> {
+        let index = match kind {
+            BellKind::Audible => BELL,
+            BellKind::Visual => FLASH_SCREEN,
+        };
+        self.string(index).filter(|sequence| !sequence.is_empty())
+    }
+
+    /// The sequence which resets text attributes, leaves keypad transmit mode and shows the
+    /// cursor, with any padding removed.
+    pub fn reset_sequence(&self) -> Vec<u8> {
+        [EXIT_ATTRIBUTE_MODE, KEYPAD_LOCAL, CURSOR_NORMAL]
+            .iter()
+            .filter_map(|&index| self.string(index))
+            .flat_map(strip_padding)
+            .collect()
+    }
+}
+
+/// Removes `$<..>` padding specifications.
+fn strip_padding(mut string: &[u8]) -> Vec<u8> {
+    let mut stripped = Vec::with_capacity(string.len());
+    while let Some(start) = string.windows(2).position(|w| w == b"$<") {
+        match string[start..].iter().position(|&b| b == b'>') {
+            Some(end) => {
+                stripped.extend_from_slice(&string[..start]);
+                string = &string[start + end + 1..];
+            }
+            None => break,
+        }
+    }
+    stripped.extend_from_slice(string);
+    stripped
+}
+
+fn search_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(dir) = env::var_os("TERMINFO") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Some(home) = env::var_os("HOME") {
+        dirs.push(PathBuf::from(home).join(".terminfo"));
+    }
+    let defaults = [
+        "/etc/terminfo",
+        "/lib/terminfo",
+        "/usr/share/terminfo",
+        "/usr/lib/terminfo",
+    ];
+    match env::var("TERMINFO_DIRS") {
+        Ok(list) => {
+            for dir in list.split(':') {
+                // An empty entry stands for the default locations.
+                if dir.is_empty() {
+                    dirs.extend(defaults.iter().map(PathBuf::from));
+                } else {
+                    dirs.push(PathBuf::from(dir));
+                }
+            }
+        }
+        Err(_) => dirs.extend(defaults.iter().map(PathBuf::from)),
+    }
+    dirs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::InputEvent;
+
+    /// Compiles an entry with the given string capabilities.
+    fn compile(names: &str, strings: &[(usize, &[u8])]) -> Vec<u8> {
+        let count = strings.iter().map(|&(i, _)| i + 1).max().unwrap_or(0);
+        let mut offsets = vec![-1i16; count];
+        let mut table = Vec::new();
+        for &(index, string) in strings {
+            offsets[index] = table.len() as i16;
+            table.extend_from_slice(string);
+            table.push(0);
+        }
+
+        let mut data = Vec::new();
+        let names_size = names.len() + 1;
+        for &n in &[
+            MAGIC_LEGACY as i16,
+            names_size as i16,
+            1,
+            0,
+            count as i16,
+            table.len() as i16,
+        ] {
+            data.extend_from_slice(&n.to_le_bytes());
+        }
+        data.extend_from_slice(names.as_bytes());
+        data.push(0);
+        // One boolean, then padding to an even offset.
+        data.push(1);
+        if data.len() % 2 == 1 {
+            data.push(0);
+        }
+        for offset in offsets {
+            data.extend_from_slice(&offset.to_le_bytes());
+        }
+        data.extend_from_slice(&table);
+        data
+    }
+
+    #[test]
+    fn test_parse_and_bind() -> io::Result<()> {
+        let data = compile(
+            "exotic|An exotic terminal",
+            &[
+                (87, b"\x1b[[up"),
+                (EXIT_ATTRIBUTE_MODE, b"\x1b[m$<2>"),
+                (CURSOR_NORMAL, b"\x1b[?25h"),
+            ],
+        );
+        let terminfo = Terminfo::parse(&data)?;
+        assert_eq!(terminfo.names(), &["exotic", "An exotic terminal"]);
+        assert_eq!(terminfo.reset_sequence(), b"\x1b[m\x1b[?25h");
+        assert_eq!(terminfo.bell(BellKind::Visual), None);
+
+        let mut decoder = Decoder::new();
+        terminfo.bind_keys(&mut decoder);
+        decoder.feed(b"\x1b[[up\x1b[B");
+        assert_eq!(
+            decoder.next_event(),
+            Some(InputEvent::Key(KeyEvent::from(Key::Up)))
+        );
+        assert_eq!(
+            decoder.next_event(),
+            Some(InputEvent::Key(KeyEvent::from(Key::Down)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reject_garbage() {
+        assert!(Terminfo::parse(b"not a terminfo entry").is_err());
+    }
+}
@@ -0,0 +1,557 @@
+//! Helpers for testing raw mode code, also in containers and CI pipelines which don't
+//! provide a controlling terminal.
+//!
+//! Most tests don't need the real terminal at all: a `FakeTerminal`, with the `pty`
+//! feature, is the master side of a pty, and the slave it comes with is a terminal device
+//! like any other, which can be put into raw mode, resized and typed into. Tests which really need the controlling terminal
+//! can skip themselves with [`require_tty!`](crate::require_tty) where there is none.
+//!
+//! ```
+//! # #[cfg(feature = "pty")]
+//! # fn main() -> std::io::Result<()> {
+//! use raw_tty::testing::FakeTerminal;
+//! use raw_tty::{IntoRawMode, WindowSize};
+//! use std::io::Read;
+//!
+//! let (mut terminal, slave) = FakeTerminal::new(WindowSize::default())?;
+//! let mut tty = slave.into_raw_mode()?;
+//! terminal.type_input(b"q")?;
+//!
+//! let mut key = [0];
+//! tty.read_exact(&mut key)?;
+//! assert_eq!(&key, b"q");
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "pty"))]
+//! # fn main() {}
+//! ```
+
+#[cfg(feature = "proptest-support")]
+pub mod strategies;
+
+#[cfg(feature = "pty")]
+use crate::pty::{self, PtyMaster, PtySlave};
+#[cfg(feature = "pty")]
+use crate::util::wait_readable;
+#[cfg(feature = "pty")]
+use crate::WindowSize;
+#[cfg(any(test, feature = "fake-syscalls"))]
+use crate::{syscall, JobControl, Termios};
+
+use std::env;
+use std::fs;
+#[cfg(any(test, feature = "fake-syscalls", feature = "pty"))]
+use std::io;
+#[cfg(feature = "pty")]
+use std::io::{Read, Write};
+#[cfg(any(test, feature = "fake-syscalls", feature = "pty"))]
+use std::os::unix::io::AsRawFd;
+#[cfg(any(test, feature = "fake-syscalls"))]
+use std::os::unix::io::RawFd;
+#[cfg(any(test, feature = "fake-syscalls"))]
+use std::sync::atomic::{AtomicI32, Ordering};
+#[cfg(any(test, feature = "fake-syscalls"))]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "pty")]
+use std::time::Duration;
+
+/// Setting this environment variable to anything makes [`tty_available`] return `false`,
+/// to skip tests needing a terminal even where there is one.
+pub const SKIP_TTY_TESTS_VAR: &str = "RAW_TTY_SKIP_TTY_TESTS";
+
+/// Whether stdin is a terminal and the process has a controlling terminal.
+pub fn tty_available() -> bool {
+    if env::var_os(SKIP_TTY_TESTS_VAR).is_some() {
+        return false;
+    }
+    let stdin_is_tty = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+    stdin_is_tty
+        && fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .is_ok()
+}
+
+/// Returns from the current function unless [`tty_available`], printing why.
+///
+/// Without an argument the function has to return `()`, otherwise the argument is the
+/// value to return.
+///
+/// ```
+/// use raw_tty::{require_tty, IntoRawMode};
+/// use std::io::{self, stdin};
+///
+/// fn test_stdin_raw_mode() -> io::Result<()> {
+///     require_tty!(Ok(()));
+///     let _stdin = stdin().into_raw_mode()?;
+///     Ok(())
+/// }
+/// # test_stdin_raw_mode().unwrap();
+/// ```
+#[macro_export]
+macro_rules! require_tty {
+    () => {
+        $crate::require_tty!(())
+    };
+    ($skipped:expr) => {
+        if !$crate::testing::tty_available() {
+            eprintln!("skipping {}: no terminal available", module_path!());
+            return $skipped;
+        }
+    };
+}
+
+/// The terminal side of a pty, standing in for a terminal emulator in tests.
+#[cfg(feature = "pty")]
+#[derive(Debug)]
+pub struct FakeTerminal {
+    master: PtyMaster,
+}
+
+#[cfg(feature = "pty")]
+impl FakeTerminal {
+    /// Opens a pty of the given size, returning the fake terminal and the slave device that
+    /// the code under test should use as its tty.
+    pub fn new(size: WindowSize) -> io::Result<(FakeTerminal, PtySlave)> {
+        let (master, slave) = pty::open()?;
+        master.set_window_size(size)?;
+        Ok((FakeTerminal { master }, slave))
+    }
+
+    /// Sends `bytes` as if they were typed.
+    pub fn type_input(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.master.write_all(bytes)?;
+        self.master.flush()
+    }
+
+    /// Collects the output of the code under test until none arrived for `quiet`.
+    pub fn read_output(&mut self, quiet: Duration) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        let mut buf = [0; 1024];
+        while wait_readable(self.master.as_raw_fd(), Some(quiet))? {
+            match self.master.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => output.extend_from_slice(&buf[..n]),
+                // Every slave was closed.
+                Err(ref e) if e.raw_os_error() == Some(libc::EIO) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(output)
+    }
+
+    /// Resizes the terminal, which sends `SIGWINCH` to the foreground process group of the
+    /// slave.
+    pub fn resize(&mut self, size: WindowSize) -> io::Result<()> {
+        self.master.set_window_size(size)
+    }
+
+    /// The underlying pty master.
+    pub fn master(&mut self) -> &mut PtyMaster {
+        &mut self.master
+    }
+}
+
+/// A [`FakeTerminal`] behind a slow link, like ssh over a bad connection: typed input
+/// arrives in small chunks, with a delay before each one and escape sequences cut in two.
+///
+/// Decoders and Escape key timeouts which work at the speed of a local pty can break
+/// there, e.g. by taking the `ESC` of an arrow key for the Escape key. The jitter is
+/// pseudo-random from a fixed seed, so that a failing test fails the same way again.
+///
+/// Sending blocks until the last chunk is written, so the code under test usually runs
+/// on another thread:
+///
+/// ```
+/// # #[cfg(feature = "pty")]
+/// # fn main() -> std::io::Result<()> {
+/// use raw_tty::testing::ThrottledPty;
+/// use raw_tty::{IntoRawMode, WindowSize};
+/// use std::io::Read;
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// let (mut pty, slave) = ThrottledPty::new(WindowSize::default())?;
+/// pty.set_delay(Duration::from_millis(5), Duration::from_millis(5));
+/// let mut tty = slave.into_raw_mode()?;
+/// let reader = thread::spawn(move || {
+///     let mut keys = [0; 7];
+///     tty.read_exact(&mut keys).map(|()| keys)
+/// });
+/// pty.type_input(b"\x1b[A\x1b[Bq")?;
+/// assert_eq!(&reader.join().unwrap()?, b"\x1b[A\x1b[Bq");
+/// # Ok(())
+/// # }
+/// # #[cfg(not(feature = "pty"))]
+/// # fn main() {}
+/// ```
+#[cfg(feature = "pty")]
+#[derive(Debug)]
+pub struct ThrottledPty {
+    terminal: FakeTerminal,
+    delay: Duration,
+    jitter: Duration,
+    chunk_size: usize,
+    split_escapes: bool,
+    /// The state of the xorshift generator for the jitter, never 0.
+    random: u64,
+}
+
+#[cfg(feature = "pty")]
+impl ThrottledPty {
+    /// The seed of the jitter unless [`set_seed`](ThrottledPty::set_seed) changes it.
+    pub const DEFAULT_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+    /// Opens a pty like [`FakeTerminal::new`], with 20 ms of delay, up to 10 ms of jitter,
+    /// chunks of at most 3 bytes and escape sequences split.
+    pub fn new(size: WindowSize) -> io::Result<(ThrottledPty, PtySlave)> {
+        let (terminal, slave) = FakeTerminal::new(size)?;
+        let pty = ThrottledPty {
+            terminal,
+            delay: Duration::from_millis(20),
+            jitter: Duration::from_millis(10),
+            chunk_size: 3,
+            split_escapes: true,
+            random: ThrottledPty::DEFAULT_SEED,
+        };
+        Ok((pty, slave))
+    }
+
+    /// Waits `delay` plus up to `jitter` before each chunk.
+    pub fn set_delay(&mut self, delay: Duration, jitter: Duration) {
+        self.delay = delay;
+        self.jitter = jitter;
+    }
+
+    /// Sends at most `size` bytes at once, at least 1.
+    pub fn set_chunk_size(&mut self, size: usize) {
+        self.chunk_size = size.max(1);
+    }
+
+    /// Whether each `ESC` ends a chunk, so that it arrives before the rest of its
+    /// sequence.
+    pub fn set_split_escapes(&mut self, split: bool) {
+        self.split_escapes = split;
+    }
+
+    /// Restarts the jitter from `seed`.
+    pub fn set_seed(&mut self, seed: u64) {
+        // Xorshift stays at 0 forever.
+        self.random = seed.max(1);
+    }
+
+    /// Sends `bytes` as if they were typed on the other end of the link, chunk by chunk.
+    pub fn type_input(&mut self, bytes: &[u8]) -> io::Result<()> {
+        for chunk in chunks(bytes, self.chunk_size, self.split_escapes) {
+            let delay = self.delay + self.next_jitter();
+            if !delay.is_zero() {
+                std::thread::sleep(delay);
+            }
+            self.terminal.type_input(chunk)?;
+        }
+        Ok(())
+    }
+
+    /// The terminal, for reading the output and resizing. Its `type_input` isn't throttled.
+    pub fn terminal(&mut self) -> &mut FakeTerminal {
+        &mut self.terminal
+    }
+
+    fn next_jitter(&mut self) -> Duration {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        let nanos = self.jitter.as_nanos() as u64;
+        Duration::from_nanos(self.random.checked_rem(nanos + 1).unwrap_or(0))
+    }
+}
+
+/// `bytes` cut into chunks of at most `size`, and after each `ESC` if `split_escapes`.
+#[cfg(feature = "pty")]
+fn chunks(bytes: &[u8], size: usize, split_escapes: bool) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let mut len = rest.len().min(size);
+        if split_escapes {
+            if let Some(escape) = rest[..len].iter().position(|&b| b == 0x1b) {
+                len = escape + 1;
+            }
+        }
+        let (chunk, tail) = rest.split_at(len);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+/// A terminal which only exists in the termios syscalls made by guards, with the
+/// `fake-syscalls` feature.
+///
+/// `FakeTerminal` needs a kernel pty, which miri can't run and which makes the timing of
+/// concurrent tests depend on the kernel. A `FakeTty` is an fd number which the guards,
+/// [`exit`](crate::exit) and the restore paths treat like a tty, with its mode kept in
+/// memory. Other calls on the fd, like reading or `set_nonblocking`, fail with `EBADF`.
+///
+/// ```
+/// # #[cfg(feature = "fake-syscalls")]
+/// # fn main() -> std::io::Result<()> {
+/// use raw_tty::testing::FakeTty;
+/// use raw_tty::TtyModeGuard;
+///
+/// let tty = FakeTty::new();
+/// let mut guard = TtyModeGuard::new(tty.fd())?;
+/// guard.set_raw_mode()?;
+/// assert_eq!(tty.termios().c_lflag & libc::ICANON, 0);
+/// drop(guard);
+/// assert_ne!(tty.termios().c_lflag & libc::ICANON, 0);
+/// # Ok(())
+/// # }
+/// # #[cfg(not(feature = "fake-syscalls"))]
+/// # fn main() {}
+/// ```
+#[cfg(any(test, feature = "fake-syscalls"))]
+#[derive(Debug)]
+pub struct FakeTty {
+    fd: RawFd,
+    state: Arc<Mutex<FakeState>>,
+}
+
+#[cfg(any(test, feature = "fake-syscalls"))]
+#[derive(Debug)]
+struct FakeState {
+    termios: Termios,
+    job_control: JobControl,
+    set_attr_calls: u64,
+    /// How many more mode changes are accepted without being applied.
+    ignored_changes: u32,
+    hung_up: bool,
+}
+
+/// Far above any fd the kernel hands out.
+#[cfg(any(test, feature = "fake-syscalls"))]
+static NEXT_FAKE_FD: AtomicI32 = AtomicI32::new(0x4000_0000);
+
+#[cfg(any(test, feature = "fake-syscalls"))]
+impl FakeTty {
+    /// A tty in the foreground, in the mode a shell leaves behind.
+    pub fn new() -> FakeTty {
+        let mut termios = Termios::default();
+        termios.c_iflag = libc::ICRNL | libc::IXON;
+        termios.c_oflag = libc::OPOST | libc::ONLCR;
+        termios.c_cflag = libc::CS8 | libc::CREAD;
+        termios.c_lflag = libc::ISIG | libc::ICANON | libc::ECHO | libc::IEXTEN;
+        termios.c_cc[libc::VMIN] = 1;
+        let state = Arc::new(Mutex::new(FakeState {
+            termios,
+            job_control: JobControl::Foreground,
+            set_attr_calls: 0,
+            ignored_changes: 0,
+            hung_up: false,
+        }));
+        let fd = NEXT_FAKE_FD.fetch_add(1, Ordering::Relaxed);
+        syscall::claim(fd, Arc::new(FakeSyscalls(state.clone())));
+        FakeTty { fd, state }
+    }
+
+    /// The fd to hand to the code under test.
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// The mode currently applied.
+    pub fn termios(&self) -> Termios {
+        self.state().termios
+    }
+
+    /// Changes the mode behind the back of the code under test, like `stty` would.
+    pub fn set_termios(&self, termios: &Termios) {
+        self.state().termios = *termios;
+    }
+
+    /// Moves the process to the foreground or background, or makes the tty not the
+    /// controlling terminal.
+    pub fn set_job_control(&self, job_control: JobControl) {
+        self.state().job_control = job_control;
+    }
+
+    /// Makes the next `count` mode changes succeed without being applied, like a terminal
+    /// which reconnects at the same time.
+    pub fn ignore_mode_changes(&self, count: u32) {
+        self.state().ignored_changes = count;
+    }
+
+    /// Makes every later call fail with `EIO`, like after the terminal closed.
+    pub fn hang_up(&self) {
+        self.state().hung_up = true;
+    }
+
+    /// How often the mode was set.
+    pub fn set_attr_calls(&self) -> u64 {
+        self.state().set_attr_calls
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(any(test, feature = "fake-syscalls"))]
+impl Default for FakeTty {
+    fn default() -> FakeTty {
+        FakeTty::new()
+    }
+}
+
+#[cfg(any(test, feature = "fake-syscalls"))]
+impl AsRawFd for FakeTty {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+#[cfg(any(test, feature = "fake-syscalls"))]
+impl Drop for FakeTty {
+    fn drop(&mut self) {
+        syscall::release(self.fd);
+    }
+}
+
+#[cfg(any(test, feature = "fake-syscalls"))]
+struct FakeSyscalls(Arc<Mutex<FakeState>>);
+
+#[cfg(any(test, feature = "fake-syscalls"))]
+impl FakeSyscalls {
+    fn state(&self) -> io::Result<std::sync::MutexGuard<'_, FakeState>> {
+        let state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if state.hung_up {
+            return Err(io::Error::from_raw_os_error(libc::EIO));
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(any(test, feature = "fake-syscalls"))]
+impl syscall::Syscalls for FakeSyscalls {
+    fn get_attr(&self, _: RawFd) -> io::Result<Termios> {
+        Ok(self.state()?.termios)
+    }
+
+    fn set_attr(&self, _: RawFd, termios: &Termios) -> io::Result<()> {
+        let mut state = self.state()?;
+        state.set_attr_calls += 1;
+        match state.ignored_changes {
+            0 => state.termios = *termios,
+            _ => state.ignored_changes -= 1,
+        }
+        Ok(())
+    }
+
+    fn job_control(&self, _: RawFd) -> io::Result<JobControl> {
+        Ok(self.state()?.job_control)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TtyModeGuard;
+
+    #[cfg(feature = "pty")]
+    #[test]
+    fn test_fake_terminal() -> io::Result<()> {
+        use crate::GuardMode;
+
+        let size = WindowSize {
+            rows: 10,
+            cols: 40,
+            ..WindowSize::default()
+        };
+        let (mut terminal, slave) = FakeTerminal::new(size)?;
+        let mut tty = slave.guard_mode()?;
+        assert_eq!(tty.window_size()?, size);
+
+        tty.write_all(b"out\n")?;
+        assert_eq!(terminal.read_output(Duration::from_millis(20))?, b"out\r\n");
+        drop(tty);
+        assert_eq!(terminal.read_output(Duration::from_millis(20))?, b"");
+        Ok(())
+    }
+
+    #[cfg(feature = "pty")]
+    #[test]
+    fn test_throttled_pty() -> io::Result<()> {
+        use crate::IntoRawMode;
+        use std::time::Instant;
+
+        assert_eq!(
+            chunks(b"ab\x1b[Acd", 3, true),
+            [&b"ab\x1b"[..], b"[Ac", b"d"]
+        );
+        assert_eq!(chunks(b"ab\x1b[Acd", 4, false), [&b"ab\x1b["[..], b"Acd"]);
+        assert!(chunks(b"", 3, true).is_empty());
+
+        let (mut pty, slave) = ThrottledPty::new(WindowSize::default())?;
+        pty.set_delay(Duration::from_millis(10), Duration::from_millis(5));
+        let mut tty = slave.into_raw_mode()?;
+        let start = Instant::now();
+        // Two chunks: the ESC, then the rest of the sequence.
+        pty.type_input(b"\x1b[A")?;
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let mut buf = [0; 3];
+        tty.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"\x1b[A");
+
+        // The same seed gives the same jitter.
+        pty.set_seed(7);
+        let first: Vec<_> = (0..4).map(|_| pty.next_jitter()).collect();
+        pty.set_seed(7);
+        let again: Vec<_> = (0..4).map(|_| pty.next_jitter()).collect();
+        assert_eq!(first, again);
+        assert!(first
+            .iter()
+            .all(|&jitter| jitter <= Duration::from_millis(5)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fake_tty() -> io::Result<()> {
+        let tty = FakeTty::new();
+        let cooked = tty.termios();
+        // Guards on one tty, created and dropped by many threads at once.
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let fd = tty.fd();
+                std::thread::spawn(move || -> io::Result<()> {
+                    for _ in 0..100 {
+                        let mut guard = TtyModeGuard::new(fd)?;
+                        guard.set_raw_mode()?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap()?;
+        }
+        // One `set_raw_mode` each, and up to three attempts for a restore which looks like
+        // it didn't take, as another guard set the same raw mode right after it.
+        let calls = tty.set_attr_calls();
+        assert!((8 * 100 * 2..=8 * 100 * 4).contains(&calls), "{}", calls);
+
+        let mut guard = TtyModeGuard::new(tty.fd())?;
+        guard.set_raw_mode()?;
+        tty.set_job_control(JobControl::Unavailable);
+        assert!(guard.suspend_process().is_err());
+        tty.hang_up();
+        // The hangup is ignored when dropping.
+        drop(guard);
+        assert!(TtyModeGuard::new(tty.fd()).is_err());
+
+        let other = FakeTty::new();
+        drop(TtyModeGuard::new(other.fd())?);
+        assert_eq!(other.termios().c_lflag, cooked.c_lflag);
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+//! Proptest strategies for termios configurations, with the `proptest-support` feature.
+//!
+//! [`termios_like`] varies the input, output and local flags this crate has constants for
+//! and the usual control characters, and [`roundtrip`] checks that a tty keeps a configuration exactly. Together
+//! they verify that a backend - a real tty, a [`FakeTty`](super::FakeTty) or another
+//! platform - preserves the mode, and they can feed random modes to a `modify_mode` closure:
+//!
+//! ```no_run
+//! use proptest::prelude::*;
+//! use raw_tty::testing::strategies::{roundtrip, termios_like};
+//! use raw_tty::Termios;
+//! use std::fs::File;
+//! use std::os::unix::io::AsRawFd;
+//!
+//! fn my_mode(ios: &mut Termios) {
+//!     ios.c_lflag &= !libc::ECHO;
+//! }
+//!
+//! let tty = File::options().read(true).write(true).open("/dev/tty").unwrap();
+//! let base = raw_tty::TtyModeGuard::new(tty.as_raw_fd()).unwrap().saved_termios();
+//! proptest!(|(mut ios in termios_like(base))| {
+//!     my_mode(&mut ios);
+//!     prop_assert_eq!(ios.c_lflag & libc::ECHO, 0);
+//!     roundtrip(tty.as_raw_fd(), &ios).unwrap();
+//! });
+//! ```
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use crate::attr::{get_terminal_attr, set_terminal_attr};
+use crate::{InputFlags, LocalFlags, OutputFlags, Termios};
+
+use libc::{cc_t, tcflag_t};
+use proptest::prelude::*;
+use std::io;
+use std::os::unix::io::RawFd;
+
+/// The control characters [`termios_like`] varies.
+pub const CONTROL_CHARS: [usize; 10] = [
+    libc::VINTR,
+    libc::VQUIT,
+    libc::VERASE,
+    libc::VKILL,
+    libc::VEOF,
+    libc::VSTART,
+    libc::VSTOP,
+    libc::VSUSP,
+    libc::VMIN,
+    libc::VTIME,
+];
+
+/// Configurations which differ from `base` in the flags with constants in
+/// [`InputFlags`], [`OutputFlags`] and [`LocalFlags`], and in the [`CONTROL_CHARS`].
+///
+/// Everything else stays as in `base`, which should come from the tty under test. That
+/// includes the control flags and the speed: they configure the hardware, which a pty
+/// doesn't have, so Linux forces `CS8` and no parity on ptys, and a speed of 0 hangs up
+/// the line.
+pub fn termios_like(base: Termios) -> impl Strategy<Value = Termios> {
+    (any::<[tcflag_t; 3]>(), any::<[cc_t; CONTROL_CHARS.len()]>()).prop_map(
+        move |(flags, chars)| {
+            let mut ios = base;
+            let vary = |field: &mut tcflag_t, all: tcflag_t, bits: tcflag_t| {
+                *field = (*field & !all) | (bits & all);
+            };
+            vary(&mut ios.c_iflag, InputFlags::ALL.0, flags[0]);
+            vary(&mut ios.c_oflag, OutputFlags::ALL.0, flags[1]);
+            vary(&mut ios.c_lflag, LocalFlags::ALL.0, flags[2]);
+            for (&index, &c) in CONTROL_CHARS.iter().zip(&chars) {
+                ios.c_cc[index] = c;
+            }
+            ios
+        },
+    )
+}
+
+/// Applies `ios` to `fd`, reads it back and restores the previous mode. Fails with
+/// `InvalidData` naming the first field that didn't come back as it was set.
+pub fn roundtrip(fd: RawFd, ios: &Termios) -> io::Result<()> {
+    let saved = get_terminal_attr(fd)?;
+    let result = set_terminal_attr(fd, ios)
+        .and_then(|()| get_terminal_attr(fd))
+        .and_then(|read| compare(ios, &read));
+    set_terminal_attr(fd, &saved)?;
+    result
+}
+
+fn compare(set: &Termios, read: &Termios) -> io::Result<()> {
+    let fields = [
+        ("c_iflag", set.c_iflag, read.c_iflag),
+        ("c_oflag", set.c_oflag, read.c_oflag),
+        ("c_cflag", set.c_cflag, read.c_cflag),
+        ("c_lflag", set.c_lflag, read.c_lflag),
+    ];
+    let changed = fields.iter().find(|&&(_, set, read)| set != read);
+    let message = match changed {
+        Some(&(name, set, read)) => format!("{} was set to {:#x}, read {:#x}", name, set, read),
+        None => match (0..set.c_cc.len()).find(|&i| set.c_cc[i] != read.c_cc[i]) {
+            Some(i) => format!(
+                "c_cc[{}] was set to {}, read {}",
+                i, set.c_cc[i], read.c_cc[i]
+            ),
+            None => return Ok(()),
+        },
+    };
+    Err(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::FakeTty;
+    use crate::util;
+    use std::os::unix::io::AsRawFd;
+
+    fn pty_mode() -> Termios {
+        let (_master, slave) = util::openpty().unwrap();
+        get_terminal_attr(slave.as_raw_fd()).unwrap()
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_roundtrip_pty(ios in termios_like(pty_mode())) {
+            let (_master, slave) = util::openpty().unwrap();
+            roundtrip(slave.as_raw_fd(), &ios).unwrap();
+        }
+
+        #[test]
+        fn test_roundtrip_fake(ios in termios_like(FakeTty::new().termios())) {
+            let tty = FakeTty::new();
+            let saved = tty.termios();
+            roundtrip(tty.fd(), &ios).unwrap();
+            prop_assert_eq!(tty.termios().c_lflag, saved.c_lflag);
+        }
+    }
+}
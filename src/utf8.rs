@@ -0,0 +1,343 @@
+//! Reading characters instead of bytes.
+//!
+//! Raw mode delivers whatever the terminal sends, one `read` at a time, and a multibyte
+//! character can be split between two reads. A [`Utf8Reader`] buffers the incomplete
+//! sequences and yields whole `char`s, handling invalid bytes according to an
+//! [`InvalidPolicy`].
+//!
+//! With the `unicode` feature, a [`GraphemeReader`] goes one step further and groups the
+//! characters into grapheme clusters, so that an emoji with a skin tone or a letter with
+//! combining accents arrives as one unit.
+//!
+//! ```no_run
+//! use raw_tty::utf8::Utf8Reader;
+//! use raw_tty::IntoRawMode;
+//! use std::io::{self, stdin};
+//!
+//! fn main() -> io::Result<()> {
+//!     let mut chars = Utf8Reader::new(stdin().into_raw_mode()?);
+//!     while let Some(c) = chars.read_char()? {
+//!         if c == 'q' {
+//!             break;
+//!         }
+//!         print!("{:?}\r\n", c);
+//!     }
+//!     Ok(())
+//! }
+//! ```
+
+#![cfg_attr(feature = "sys-unsafe-audited", forbid(unsafe_code))]
+
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::str;
+#[cfg(feature = "unicode")]
+use std::time::Duration;
+
+#[cfg(feature = "unicode")]
+use crate::util::wait_readable;
+#[cfg(feature = "unicode")]
+use unicode_segmentation::UnicodeSegmentation;
+
+/// What a [`Utf8Reader`] does with bytes which aren't valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InvalidPolicy {
+    /// Yield U+FFFD REPLACEMENT CHARACTER for every invalid sequence. This is the default.
+    #[default]
+    Replace,
+    /// Drop invalid sequences silently.
+    Skip,
+    /// Fail with `InvalidData`. Reading can continue after the error, with the bytes
+    /// following the invalid sequence.
+    Error,
+}
+
+/// Decodes the bytes read from `R` into `char`s, see the [module docs](self).
+///
+/// Reads are buffered, so bytes following a character may already have been read from `R`.
+#[derive(Debug)]
+pub struct Utf8Reader<R: Read> {
+    inner: R,
+    buf: Vec<u8>,
+    /// The start of the bytes in `buf` which weren't decoded yet.
+    pos: usize,
+    policy: InvalidPolicy,
+}
+
+impl<R: Read> Utf8Reader<R> {
+    pub fn new(inner: R) -> Utf8Reader<R> {
+        Utf8Reader {
+            inner,
+            buf: Vec::new(),
+            pos: 0,
+            policy: InvalidPolicy::default(),
+        }
+    }
+
+    /// Sets how invalid bytes are handled.
+    pub fn set_invalid_policy(&mut self, policy: InvalidPolicy) {
+        self.policy = policy;
+    }
+
+    /// Whether bytes were read but not decoded yet, e.g. the start of a multibyte
+    /// character.
+    pub fn has_pending(&self) -> bool {
+        self.pos < self.buf.len()
+    }
+
+    /// Reads the next character, or returns `None` at end of file.
+    ///
+    /// An incomplete sequence at end of file is invalid.
+    pub fn read_char(&mut self) -> io::Result<Option<char>> {
+        loop {
+            let pending = &self.buf[self.pos..];
+            let available = pending.len();
+            let chunk = &pending[..available.min(4)];
+            let (valid, error) = match str::from_utf8(chunk) {
+                Ok(valid) => (valid, None),
+                Err(e) => (
+                    str::from_utf8(&chunk[..e.valid_up_to()]).unwrap_or_default(),
+                    Some(e),
+                ),
+            };
+            if let Some(c) = valid.chars().next() {
+                return Ok(Some(self.take(c)));
+            }
+            let invalid = match error.map(|e| e.error_len()) {
+                None if self.fill()? => continue,
+                None => return Ok(None),
+                Some(Some(len)) => len,
+                // The sequence is incomplete so far.
+                Some(None) if self.fill()? => continue,
+                Some(None) => available,
+            };
+            let bytes = self.buf[self.pos..self.pos + invalid].to_vec();
+            self.pos += invalid;
+            match self.policy {
+                InvalidPolicy::Replace => return Ok(Some(char::REPLACEMENT_CHARACTER)),
+                InvalidPolicy::Skip => {}
+                InvalidPolicy::Error => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid UTF-8 sequence {:02x?}", bytes),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// The underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// The underlying reader. Reading from it directly skips the buffered bytes.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwraps the underlying reader, dropping the buffered bytes.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn take(&mut self, c: char) -> char {
+        self.pos += c.len_utf8();
+        c
+    }
+
+    /// Reads more bytes after the pending ones, returning `false` at end of file.
+    fn fill(&mut self) -> io::Result<bool> {
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        let start = self.buf.len();
+        self.buf.resize(start + 64, 0);
+        let result = self.inner.read(&mut self.buf[start..]);
+        self.buf.truncate(start + *result.as_ref().unwrap_or(&0));
+        Ok(result? > 0)
+    }
+}
+
+impl<R: Read> Iterator for Utf8Reader<R> {
+    type Item = io::Result<char>;
+
+    fn next(&mut self) -> Option<io::Result<char>> {
+        self.read_char().transpose()
+    }
+}
+
+impl<R: Read + AsRawFd> AsRawFd for Utf8Reader<R> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+/// Groups the characters of a [`Utf8Reader`] into extended grapheme clusters.
+///
+/// Whether a cluster is complete is only known once the next character arrives, so a
+/// cluster is also considered complete when nothing more arrives within a short
+/// [timeout](GraphemeReader::set_timeout). Terminals and input methods send a cluster in
+/// one go, so this only splits a cluster which is really typed key by key.
+#[cfg(feature = "unicode")]
+#[derive(Debug)]
+pub struct GraphemeReader<R: Read + AsRawFd> {
+    chars: Utf8Reader<R>,
+    /// The first character of the next cluster, read while looking for the end of the last.
+    next: Option<char>,
+    timeout: Duration,
+}
+
+#[cfg(feature = "unicode")]
+impl<R: Read + AsRawFd> GraphemeReader<R> {
+    pub fn new(chars: Utf8Reader<R>) -> GraphemeReader<R> {
+        GraphemeReader {
+            chars,
+            next: None,
+            timeout: crate::prompt::ESCAPE_TIMEOUT,
+        }
+    }
+
+    /// Sets how long to wait for more of a cluster, 25ms by default.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Reads the next grapheme cluster, or returns `None` at end of file.
+    pub fn read_grapheme(&mut self) -> io::Result<Option<String>> {
+        let mut cluster = String::new();
+        match self
+            .next
+            .take()
+            .map_or_else(|| self.chars.read_char(), |c| Ok(Some(c)))?
+        {
+            Some(c) => cluster.push(c),
+            None => return Ok(None),
+        }
+        loop {
+            if !self.chars.has_pending()
+                && !wait_readable(self.chars.as_raw_fd(), Some(self.timeout))?
+            {
+                return Ok(Some(cluster));
+            }
+            let c = match self.chars.read_char()? {
+                Some(c) => c,
+                None => return Ok(Some(cluster)),
+            };
+            let len = cluster.len();
+            cluster.push(c);
+            if cluster.graphemes(true).nth(1).is_some() {
+                cluster.truncate(len);
+                self.next = Some(c);
+                return Ok(Some(cluster));
+            }
+        }
+    }
+
+    /// The underlying character reader.
+    pub fn get_mut(&mut self) -> &mut Utf8Reader<R> {
+        &mut self.chars
+    }
+
+    /// Unwraps the underlying character reader, dropping a character which was read ahead.
+    pub fn into_inner(self) -> Utf8Reader<R> {
+        self.chars
+    }
+}
+
+#[cfg(feature = "unicode")]
+impl<R: Read + AsRawFd> Iterator for GraphemeReader<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        self.read_grapheme().transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{util, IntoRawMode};
+    use std::io::Write;
+
+    /// Reads one byte at a time, splitting every multibyte character.
+    struct OneByte<'a>(&'a [u8]);
+
+    impl Read for OneByte<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((&byte, rest)) if !buf.is_empty() => {
+                    buf[0] = byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    fn decode(bytes: &[u8], policy: InvalidPolicy) -> Vec<io::Result<char>> {
+        let mut reader = Utf8Reader::new(OneByte(bytes));
+        reader.set_invalid_policy(policy);
+        reader.collect()
+    }
+
+    #[test]
+    fn test_split_and_invalid() {
+        let bytes = "aé€😀".as_bytes();
+        let chars: Vec<_> = decode(bytes, InvalidPolicy::Error)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(chars, ['a', 'é', '€', '😀']);
+
+        let invalid = b"a\xffb\xe2\x82";
+        let replaced: Vec<_> = decode(invalid, InvalidPolicy::Replace)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(replaced, ['a', '\u{fffd}', 'b', '\u{fffd}']);
+        let skipped: Vec<_> = decode(invalid, InvalidPolicy::Skip)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(skipped, ['a', 'b']);
+
+        let errors = decode(invalid, InvalidPolicy::Error);
+        assert_eq!(errors.len(), 4);
+        assert_eq!(
+            errors[1].as_ref().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(*errors[2].as_ref().unwrap(), 'b');
+    }
+
+    #[test]
+    fn test_raw_reader() -> io::Result<()> {
+        let (mut master, slave) = util::openpty()?;
+        let mut reader = Utf8Reader::new(slave.into_raw_mode()?);
+        master.write_all(&"ü".as_bytes()[..1])?;
+        master.write_all(&"ü!".as_bytes()[1..])?;
+        assert_eq!(reader.read_char()?, Some('ü'));
+        assert!(reader.has_pending());
+        assert_eq!(reader.read_char()?, Some('!'));
+        Ok(())
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn test_graphemes() -> io::Result<()> {
+        let (mut master, slave) = util::openpty()?;
+        let mut graphemes = GraphemeReader::new(Utf8Reader::new(slave.into_raw_mode()?));
+        // A thumbs up with a skin tone, e with a combining acute accent, a flag.
+        master.write_all("👍🏽e\u{301}x🇩🇪".as_bytes())?;
+        assert_eq!(graphemes.read_grapheme()?.as_deref(), Some("👍🏽"));
+        assert_eq!(graphemes.read_grapheme()?.as_deref(), Some("e\u{301}"));
+        assert_eq!(graphemes.read_grapheme()?.as_deref(), Some("x"));
+        assert_eq!(graphemes.read_grapheme()?.as_deref(), Some("🇩🇪"));
+
+        // Nothing follows, so the timeout ends the cluster.
+        master.write_all(b"a")?;
+        assert_eq!(graphemes.read_grapheme()?.as_deref(), Some("a"));
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+//! Registering pty sessions in utmp and wtmp.
+//!
+//! Login-like tools and multiplexers which run shells on a pty are expected to record the
+//! session in utmp (what `who` and `w` show) and wtmp (what `last` shows). Without an
+//! entry, some programs fail to find out who is logged in on their terminal. Writing these
+//! files usually requires privileges, e.g. membership in the `utmp` group.
+//!
+//! ```no_run
+//! use raw_tty::{pty, utmp};
+//! use std::io;
+//!
+//! fn main() -> io::Result<()> {
+//!     let (master, _slave) = pty::open()?;
+//!     let child_pid = 1234;
+//!     let registration = utmp::register(&master, "alice", "example.org", child_pid)?;
+//!     // ... run the session ...
+//!     registration.unregister()
+//! }
+//! ```
+
+use crate::pty::PtyMaster;
+
+use libc::{c_char, c_short};
+use std::os::unix::ffi::OsStrExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{io, mem};
+
+const WTMP_FILE: &[u8] = b"/var/log/wtmp\0";
+
+extern "C" {
+    fn updwtmpx(file: *const c_char, entry: *const libc::utmpx);
+}
+
+/// A session registered with [`register`]. It is unregistered when dropped.
+#[derive(Debug)]
+pub struct Registration {
+    line: Vec<u8>,
+    pid: libc::pid_t,
+    registered: bool,
+}
+
+/// Records a `USER_PROCESS` entry for the slave of `master` in utmp and wtmp.
+///
+/// `pid` is the process running the session, usually the child spawned on the slave.
+pub fn register(
+    master: &PtyMaster,
+    user: &str,
+    host: &str,
+    pid: libc::pid_t,
+) -> io::Result<Registration> {
+    let name = master.slave_name().as_os_str().as_bytes();
+    let line = name.strip_prefix(b"/dev/").unwrap_or(name).to_vec();
+    let mut registration = Registration {
+        line,
+        pid,
+        registered: false,
+    };
+    registration.write(libc::USER_PROCESS, user, host)?;
+    registration.registered = true;
+    Ok(registration)
+}
+
+/// The `ut_id` of a line: the terminal number for ptys, else the last four bytes.
+fn line_id(line: &[u8]) -> &[u8] {
+    let id = line.strip_prefix(b"pts/").unwrap_or(line);
+    &id[id.len().saturating_sub(4)..]
+}
+
+fn copy_into(field: &mut [c_char], value: &[u8]) {
+    for (dst, &src) in field.iter_mut().zip(value) {
+        *dst = src as c_char;
+    }
+}
+
+impl Registration {
+    /// Marks the session as ended (`DEAD_PROCESS`) in utmp and wtmp.
+    pub fn unregister(mut self) -> io::Result<()> {
+        self.registered = false;
+        self.write(libc::DEAD_PROCESS, "", "")
+    }
+
+    fn write(&self, kind: c_short, user: &str, host: &str) -> io::Result<()> {
+        let mut entry: libc::utmpx = unsafe { mem::zeroed() };
+        entry.ut_type = kind;
+        entry.ut_pid = self.pid;
+        copy_into(&mut entry.ut_line, &self.line);
+        copy_into(&mut entry.ut_id, line_id(&self.line));
+        copy_into(&mut entry.ut_user, user.as_bytes());
+        copy_into(&mut entry.ut_host, host.as_bytes());
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        entry.ut_tv.tv_sec = now.as_secs() as _;
+        entry.ut_tv.tv_usec = now.subsec_micros() as _;
+
+        unsafe {
+            libc::setutxent();
+            let written = libc::pututxline(&entry);
+            // pututxline may leave errno at ENOENT even on success, so read it right away.
+            let error = io::Error::last_os_error();
+            libc::endutxent();
+            if written.is_null() {
+                return Err(error);
+            }
+            updwtmpx(WTMP_FILE.as_ptr() as *const c_char, &entry);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if self.registered {
+            let _ = self.write(libc::DEAD_PROCESS, "", "");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line_id() {
+        assert_eq!(line_id(b"pts/12"), b"12");
+        assert_eq!(line_id(b"ttyS0"), b"tyS0");
+    }
+}
@@ -0,0 +1,119 @@
+//! The examples, run on a pty the way a user runs them in a terminal.
+//!
+//! Each one goes through a public subsystem end to end, so these are acceptance tests for
+//! the API: a change which breaks a real usage pattern breaks an example. Cargo builds the
+//! examples before running this, with `cargo test --features full`.
+
+use raw_tty::pty::{self, PtyChild, PtyMaster};
+use raw_tty::WindowSize;
+use std::env;
+use std::fs;
+use std::io;
+use std::process::Command;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Starts the example `name` on a new pty.
+fn spawn(name: &str, args: &[&str]) -> io::Result<(PtyMaster, PtyChild)> {
+    // The examples are next to the deps directory with this test.
+    let mut path = env::current_exe()?;
+    path.pop();
+    if path.ends_with("deps") {
+        path.pop();
+    }
+    path.push("examples");
+    path.push(name);
+    pty::spawn(Command::new(path).args(args))
+}
+
+/// Waits for the example to exit successfully.
+fn finish(mut child: PtyChild) -> io::Result<()> {
+    match child.wait_timeout(TIMEOUT)? {
+        Some(status) => assert!(status.success(), "{}", status),
+        None => {
+            child.kill()?;
+            panic!("the example didn't exit");
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn key_echo() -> io::Result<()> {
+    let (mut master, child) = spawn("key_echo", &[])?;
+    master.expect("q quits.\r\n", TIMEOUT)?;
+    master.send("a\x1b[A\x1b[1;5B")?;
+    master.expect("Char('a')\r\n", TIMEOUT)?;
+    master.expect("Up\r\n", TIMEOUT)?;
+    master.expect("Down Modifiers(4)\r\n", TIMEOUT)?;
+    master.send("q")?;
+    master.expect("Char('q')\r\n", TIMEOUT)?;
+    finish(child)
+}
+
+#[test]
+fn pager() -> io::Result<()> {
+    let path = env::temp_dir().join(format!("raw_tty_pager_{}.txt", std::process::id()));
+    let text: String = (1..=40).map(|n| format!("line {}\n", n)).collect();
+    fs::write(&path, text)?;
+    let (mut master, child) = spawn("pager", &[path.to_str().unwrap()])?;
+
+    // An unsized pty gets 23 lines.
+    let screen = master.expect("--More--", TIMEOUT)?;
+    assert!(screen.before.ends_with(b"line 23\r\n"));
+    master.send("\r")?;
+    let screen = master.expect("--More--", TIMEOUT)?;
+    assert!(screen.before.ends_with(b"line 24\r\n"));
+
+    // The next screen fits the window as it is now.
+    master.set_window_size(WindowSize {
+        rows: 6,
+        cols: 40,
+        ..WindowSize::default()
+    })?;
+    master.send(" ")?;
+    let screen = master.expect("--More--", TIMEOUT)?;
+    assert!(screen.before.ends_with(b"line 29\r\n"));
+    assert!(!screen.before.windows(7).any(|w| w == b"line 30"));
+    master.send("q")?;
+    let result = finish(child);
+    fs::remove_file(&path)?;
+    result
+}
+
+#[test]
+fn password_prompt() -> io::Result<()> {
+    let (mut master, child) = spawn("password_prompt", &[])?;
+    master.expect("Password: ", TIMEOUT)?;
+    master.send("hunter2\n")?;
+    let prompt = master.expect("Retype password: ", TIMEOUT)?;
+    assert!(!prompt.before.windows(7).any(|w| w == b"hunter2"));
+    master.send("hunter2\n")?;
+    let reply = master.expect("Passwords match, 7 characters.\r\n", TIMEOUT)?;
+    assert!(!reply.before.windows(7).any(|w| w == b"hunter2"));
+    finish(child)
+}
+
+#[test]
+fn pty_spawn() -> io::Result<()> {
+    let (mut master, child) = spawn("pty_spawn", &[])?;
+    master.expect("cat replied\r\n", TIMEOUT)?;
+    master.expect("cat exited: exit status: 0\r\n", TIMEOUT)?;
+    finish(child)
+}
+
+#[test]
+fn proxy() -> io::Result<()> {
+    let script = "read line; echo \"got $line\"";
+    let (mut master, child) = spawn("proxy", &["sh", "-c", script])?;
+    master.send("hi\n")?;
+    master.expect("got hi\r\n", TIMEOUT)?;
+    // The child exiting or closing the pty, whichever the proxy notices first.
+    let summary = master.expect(" bytes out\r\n", TIMEOUT)?;
+    assert!(summary
+        .before
+        .windows(6)
+        .any(|w| w == b"Closed" || w == b"Exited"));
+    finish(child)
+}